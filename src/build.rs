@@ -7,55 +7,127 @@ use std::ffi::{OsStr, OsString};
 use std::collections::{HashMap, HashSet};
 use std::iter::once;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::future::Future;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use std::thread;
 
 use async_recursion::async_recursion;
-use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
 
 use indicatif::{ProgressBar, ProgressStyle, WeakProgressBar};
 use serde::{Serialize, Deserialize};
 use filetime::FileTime;
+use tokio::task;
 
-use crate::proj_config::{Platform, Os, ProjectConfig, OutputType};
+use crate::proj_config::{Platform, Os, ProjectConfig, OutputType, UacExecutionLevel, IncludePrecedence, Subsystem, DebugInfoFormat, CodeSigningOptions, CxxStandard};
 use crate::cmd_options::BuildOptions;
 use crate::canonicalize;
-use crate::toolchain_paths::ToolchainPaths;
+use crate::toolchain_paths::{ToolchainPaths, Vendor};
 use crate::println_above_progress_bar_if_visible;
-use crate::task::{CxxTask, Task, TaskExt};
+use crate::task::{CxxTask, AsmTask, Task, TaskExt};
+use crate::build_manager::diagnostic_code;
+use crate::ignore::IgnoreRules;
 
 // TODO: All fields of BuildEnvironment should be made private again after task.rs
 // stops depending on being able to access them.
 pub struct BuildEnvironment<'a> {
     pub config_path: PathBuf,
-    pub manifest_path: Option<PathBuf>,
+    /// windows_manifest.xml (if it exists), followed by the manifests listed in `config.manifests`
+    pub manifest_paths: Vec<PathBuf>,
 
+    /// `config.link_libraries`, followed by the libraries that `config.target_link_libraries` lists for `target`
+    pub link_libraries: Vec<PathBuf>,
     pub linker_lib_dependencies: Vec<PathBuf>,
+    /// Libraries built from dependency projects. Unlike `link_libraries`, which may refer to system libraries
+    /// by name, these are always concrete paths.
+    pub dependency_libraries: Vec<PathBuf>,
+    /// Directories from `config.library_paths`, resolved against the project root
+    pub library_paths: Vec<PathBuf>,
+    /// Flags from the `ABS_CXXFLAGS` environment variable, passed to the compiler after all others
+    pub extra_compile_flags: Vec<String>,
+    /// Flags from the `ABS_LDFLAGS` environment variable, passed to the linker after all others
+    pub extra_link_flags: Vec<String>,
+    /// A file containing `extra_compile_flags` and `extra_link_flags`, which is only rewritten when they change. Every
+    /// artifact depends on it.
+    pub env_flags_path: PathBuf,
+    /// A file containing `definitions`, which is only rewritten when they change. Every artifact depends on it, since
+    /// the definitions passed to a dependency by its dependents aren't recorded anywhere else.
+    pub definitions_path: PathBuf,
     
     pub toolchain_paths: &'a ToolchainPaths,
     pub config: &'a ProjectConfig,
     pub build_options: &'a BuildOptions,
+    pub target: Platform,
     pub definitions: &'a [(&'a str, &'a str)],
     pub project_path: PathBuf,
     pub artifact_path: PathBuf,
     pub src_dir_path: PathBuf,
     pub assets_dir_path: PathBuf,
+    /// The directory containing the directories below. The artifact directory, unless `--scratch-dir` was passed.
+    pub intermediates_path: PathBuf,
     pub objs_path: PathBuf,
     pub src_deps_path: PathBuf,
     pub dependency_headers_path: PathBuf,
     pub warning_cache_path: PathBuf,
+    /// The files to include in a package of the build product. Populated by `build()`.
+    pub package_file_paths: Vec<PathBuf>,
 
-    pub file_edit_times: Mutex<HashMap<PathBuf, FileTime>>,
+    pub file_edit_times: Mutex<HashMap<PathBuf, Option<FileTime>>>,
     pub unique_compiler_output: Arc<Mutex<HashSet<String>>>,
+    /// The number of distinct compiler errors reported so far, for `--max-errors`
+    pub num_errors: AtomicUsize,
+    /// With `--warning-summary`, the number of times each warning code occurred, and the sources it occurred in
+    pub warning_counts: Mutex<HashMap<String, (usize, HashSet<PathBuf>)>>,
     pub progress_bar: Mutex<WeakProgressBar>,
 }
 
+/// The directory, relative to each artifact directory, in which artifacts of extra sources are placed
+const EXTRA_SOURCES_NAMESPACE: &str = ".extra";
+
+/// The maximum length of a path on Windows, including the null terminator
+const MAX_PATH: usize = 260;
+
+/// The version of the layout and format of the caches in each artifact directory (e.g., src_deps and warning caches).
+/// Increment this whenever either changes.
+const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// Identifies the version of ABS that wrote an artifact directory's caches. Caches written by any other version are
+/// discarded, since they may be misinterpreted.
+fn cache_version() -> String {
+    format!("{}/{}", env!("CARGO_PKG_VERSION"), CACHE_FORMAT_VERSION)
+}
+
 #[derive(Debug)]
 pub enum BuildError {
     NoSrcDirectory,
     CantReadSrcDirectory,
     DiscoverSrcDepsError,
-    CompilerError,
-    LinkerError,
+    /// The exit code of the tool, if any
+    CompilerError(Option<i32>),
+    AssemblerError(Option<i32>),
+    LinkerError(Option<i32>),
+    PackageError,
+    PathTooLong(PathBuf),
+    MissingExtraSource(PathBuf),
+    OutputPermissionDenied(PathBuf),
+    LibraryNotFound(PathBuf),
+    MissingManifest(PathBuf),
+    DependencyModeMismatch(PathBuf, &'static str),
+    ConflictingCompileFlags(String),
+    UnreadableDefinitionsFile(PathBuf),
+    InvalidDefinition(PathBuf, usize),
+    ToolNotFound(&'static str, Vec<PathBuf>),
+    PdbLocked(PathBuf),
+    CompileTimedOut(PathBuf, u64),
+    ArtifactPathCollision(PathBuf, PathBuf, PathBuf),
+    SignToolNotFound,
+    SigningError(PathBuf),
+    MissingDependency(PathBuf),
+    IwyuNotFound(Option<PathBuf>),
+    /// The standard, the oldest MSVC version that supports it, and the MSVC version that was found
+    UnsupportedCxxStandard(CxxStandard, &'static str, [u64; 3]),
 
     IoError(io::Error),
 }
@@ -66,35 +138,293 @@ impl From<io::Error> for BuildError {
     }
 }
 
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::NoSrcDirectory => write!(f, "src directory does not exist"),
+            BuildError::CantReadSrcDirectory => write!(f, "unable to read src directory"),
+            BuildError::DiscoverSrcDepsError => write!(f, "unable to discover source dependencies"),
+            BuildError::CompilerError(code) => write!(f, "unable to compile{}", exit_code_suffix("compiler", *code)),
+            BuildError::AssemblerError(code) => write!(f, "unable to assemble{}", exit_code_suffix("assembler", *code)),
+            BuildError::LinkerError(code) => write!(f, "unable to link{}", exit_code_suffix("linker", *code)),
+            BuildError::PackageError => write!(f, "unable to package"),
+            BuildError::PathTooLong(path) => write!(
+                f,
+                "the artifact path \"{}\" exceeds the Windows MAX_PATH limit of {} characters. Consider adding `\"flatten_artifacts\": true` to abs.json, or moving the project to a shorter path",
+                path.as_os_str().to_string_lossy(),
+                MAX_PATH,
+            ),
+            BuildError::MissingExtraSource(path) => write!(f, "extra source \"{}\" does not exist", path.as_os_str().to_string_lossy()),
+            BuildError::OutputPermissionDenied(path) => write!(
+                f,
+                "unable to write to \"{}\", because it is read-only or locked by another process. Please make sure that it isn't read-only (some version control systems check files out that way), and that no other program (such as antivirus software) has it open",
+                path.as_os_str().to_string_lossy(),
+            ),
+            BuildError::LibraryNotFound(lib) => write!(f, "library \"{}\" in link_libraries was not found in any library path. Please check its spelling", lib.as_os_str().to_string_lossy()),
+            BuildError::MissingManifest(path) => write!(f, "manifest \"{}\" does not exist", path.as_os_str().to_string_lossy()),
+            BuildError::DependencyModeMismatch(path, mode) => write!(
+                f,
+                "dependency library \"{}\" was not built in {} mode. Linking libraries built in different modes mixes debug and release C runtimes",
+                path.as_os_str().to_string_lossy(),
+                mode,
+            ),
+            BuildError::ConflictingCompileFlags(conflict) => write!(f, "conflicting compiler flags {}", conflict),
+            BuildError::UnreadableDefinitionsFile(path) => write!(f, "unable to read definitions file \"{}\"", path.as_os_str().to_string_lossy()),
+            BuildError::InvalidDefinition(path, line) => write!(f, "line {} of \"{}\" is not a valid definition. Expected `NAME=VALUE` or `NAME`", line, path.as_os_str().to_string_lossy()),
+            BuildError::ToolNotFound(tool, bin_paths) => {
+                let bin_paths: Vec<_> = bin_paths.iter()
+                    .map(|path| format!("\"{}\"", path.as_os_str().to_string_lossy()))
+                    .collect();
+                write!(f, "{} was not found under {}; your MSVC install may be incomplete", tool, bin_paths.join(" or "))
+            },
+            BuildError::PdbLocked(path) => write!(
+                f,
+                "\"{}\" is locked by another process, probably a debugger that is still attached. Please detach or close it, then try again",
+                path.as_os_str().to_string_lossy(),
+            ),
+            BuildError::CompileTimedOut(path, seconds) => write!(
+                f,
+                "compiling \"{}\" took longer than the compile timeout of {} seconds, so the compiler was stopped",
+                path.as_os_str().to_string_lossy(),
+                seconds,
+            ),
+            BuildError::ArtifactPathCollision(first, second, artifact) => write!(
+                f,
+                "sources \"{}\" and \"{}\" would both be built to \"{}\". Please rename one of them",
+                first.as_os_str().to_string_lossy(),
+                second.as_os_str().to_string_lossy(),
+                artifact.as_os_str().to_string_lossy(),
+            ),
+            BuildError::SignToolNotFound => write!(f, "`code_signing` is enabled, but signtool.exe was not found in the Windows SDK. Please install the SDK's signing tools"),
+            BuildError::SigningError(path) => write!(f, "unable to sign \"{}\"", path.as_os_str().to_string_lossy()),
+            BuildError::MissingDependency(path) => write!(f, "\"{}\" is required to build, but does not exist", path.as_os_str().to_string_lossy()),
+            BuildError::IwyuNotFound(Some(path)) => write!(f, "include-what-you-use was not found at \"{}\", as specified by `iwyu_path`", path.as_os_str().to_string_lossy()),
+            BuildError::IwyuNotFound(None) => write!(f, "include-what-you-use was not found. Please add it to your PATH, or specify its location with `iwyu_path` in abs.json"),
+            BuildError::UnsupportedCxxStandard(standard, minimum_version, [major, minor, patch]) => write!(
+                f,
+                "`{}` requires {} or newer, but the installed MSVC version is {}.{}.{}. Please update Visual Studio, or use an older `cxx_options.standard`",
+                standard.name(),
+                minimum_version,
+                major,
+                minor,
+                patch,
+            ),
+
+            BuildError::IoError(io_error) => write!(f, "there was an io error: {:?}", io_error.kind()),
+        }
+    }
+}
+
+impl BuildError {
+    /// Converts an IO error that occurred while writing to `path` in the output directory, so that permission
+    /// problems are reported along with the path, rather than as a bare error kind.
+    pub fn from_output_io(path: impl AsRef<Path>, error: io::Error) -> Self {
+        if is_lock_error(&error) {
+            BuildError::OutputPermissionDenied(path.as_ref().to_owned())
+        } else {
+            error.into()
+        }
+    }
+}
+
+/// Windows error codes for a file that another process has open without sharing it, or has part of locked
+const ERROR_SHARING_VIOLATION: i32 = 32;
+const ERROR_LOCK_VIOLATION: i32 = 33;
+
+/// Whether `error` means that a file is read-only, or in use by another process (e.g., antivirus software scanning a
+/// freshly written file)
+fn is_lock_error(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::PermissionDenied ||
+        (cfg!(target_os = "windows") && matches!(error.raw_os_error(), Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)))
+}
+
+/// How long to wait before retrying an operation on an output file, which failed with `error` on attempt number
+/// `attempt`. `None` if it's not worth retrying.
+fn output_io_retry_delay(error: &io::Error, attempt: u32) -> Option<Duration> {
+    const ATTEMPTS: u32 = 5;
+    if is_lock_error(error) && attempt < ATTEMPTS {
+        Some(Duration::from_millis(100 * attempt as u64))
+    } else {
+        None
+    }
+}
+
+/// Runs `op`, which writes to `path` in the output directory. Locks held by other processes are usually brief, so if
+/// `path` is locked, `op` is retried a few times before giving up.
+pub fn retry_output_io<T>(path: impl AsRef<Path>, mut op: impl FnMut() -> io::Result<T>) -> Result<T, BuildError> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(error) => match output_io_retry_delay(&error, attempt) {
+                Some(delay) => thread::sleep(delay),
+                None => return Err(BuildError::from_output_io(path, error)),
+            },
+        }
+        attempt += 1;
+    }
+}
+
+/// Like `retry_output_io`, but waits between attempts without blocking the thread, for use in tasks run on the async
+/// runtime
+pub async fn retry_output_io_async<T>(path: impl AsRef<Path>, mut op: impl FnMut() -> io::Result<T>) -> Result<T, BuildError> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(error) => match output_io_retry_delay(&error, attempt) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(BuildError::from_output_io(path, error)),
+            },
+        }
+        attempt += 1;
+    }
+}
+
+/// Clears the read-only attribute of `path`, if it exists
+fn make_writable(path: &Path) -> io::Result<()> {
+    let mut permissions = match fs::metadata(path) {
+        Ok(metadata) => metadata.permissions(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    if permissions.readonly() {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(permissions.mode() | 0o200);
+        }
+        #[cfg(not(unix))]
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// The file in the artifact directory that records where intermediate files were last built to, when that was
+/// somewhere under `--scratch-dir`
+const SCRATCH_PATH_FILE: &str = "scratch_path";
+
+/// The file in each directory of intermediate files under `--scratch-dir` that records the (canonical) path of the
+/// artifact directory it belongs to. `abs clean` and `abs gc` delete the directory that SCRATCH_PATH_FILE names, so
+/// they check this first, in case that file is stale or has been edited to name some unrelated directory.
+const SCRATCH_MARKER_FILE: &str = "abs-artifact-path";
+
+/// The file in the artifact directory that records which mode the project was last built in, which is checked when the
+/// project's library is linked into another project
+const BUILD_MODE_FILE: &str = "build_mode";
+
+/// The directory under `--scratch-dir` that intermediate files were last built to for the artifact directory
+/// `artifact_path`, if any. Only a directory marked as belonging to `artifact_path` counts.
+fn scratch_path(artifact_path: &Path) -> Option<PathBuf> {
+    let scratch_path = PathBuf::from(fs::read_to_string(artifact_path.join(SCRATCH_PATH_FILE)).ok()?);
+    let marker = fs::read_to_string(scratch_path.join(SCRATCH_MARKER_FILE)).ok()?;
+    let artifact_path = canonicalize(artifact_path).ok()?;
+    (marker == artifact_path.to_string_lossy()).then_some(scratch_path)
+}
+
+/// The directory that intermediate files (objects, source dependencies, etc.) were last built to for the artifact
+/// directory `artifact_path`. This is the artifact directory itself, unless `--scratch-dir` was passed.
+pub fn intermediates_path(artifact_path: &Path) -> PathBuf {
+    scratch_path(artifact_path).unwrap_or_else(|| artifact_path.to_owned())
+}
+
+/// Describes the exit code of a failed tool, for errors. Windows reports crashes as NTSTATUS codes (e.g.,
+/// 0xC0000005 for an access violation), which are more recognizable in hexadecimal.
+pub fn exit_code_suffix(tool: &str, code: Option<i32>) -> String {
+    match code {
+        Some(code) if code < 0 => format!(" ({} exited with code {:#010X})", tool, code as u32),
+        Some(code) => format!(" ({} exited with code {})", tool, code),
+        None => String::new(),
+    }
+}
+
+/// Creates a directory in the output directory, if it doesn't exist already
+pub fn create_output_dir(path: impl AsRef<Path>) -> Result<(), BuildError> {
+    let path = path.as_ref();
+    fs::create_dir_all(path).map_err(|error| BuildError::from_output_io(path, error))
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::IoError(io_error) => Some(io_error),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if the header's first preprocessor directive is `#pragma once`, or an `#ifndef`
+/// immediately followed by a `#define` of the same macro.
+fn has_include_guard(contents: &str) -> bool {
+    let mut in_block_comment = false;
+    let mut directives = contents.lines()
+        .filter_map(|line| {
+            let mut line = line.trim();
+            if in_block_comment {
+                let end = line.find("*/")?;
+                in_block_comment = false;
+                line = line[(end + 2)..].trim();
+            }
+            if line.starts_with("/*") && !line.contains("*/") {
+                in_block_comment = true;
+                return None;
+            }
+            if line.is_empty() || line.starts_with("//") || (line.starts_with("/*") && line.ends_with("*/")) {
+                return None;
+            }
+            Some(line)
+        })
+        .map(|line| {
+            // Normalize directives like `#  pragma   once` to `#pragma once`
+            line.strip_prefix('#')
+                .map(|directive| directive.split_whitespace().collect::<Vec<_>>())
+                .unwrap_or_default()
+        });
+    match directives.next().as_deref() {
+        Some(["pragma", "once", ..]) => true,
+        Some(["ifndef", guard, ..]) => {
+            matches!(directives.next().as_deref(), Some(["define", defined, ..]) if defined == guard)
+        },
+        _ => false,
+    }
+}
+
 #[derive(Default)]
 pub struct SrcPaths {
     pub root: PathBuf,
     pub src_paths: Vec<PathBuf>,
+    pub asm_paths: Vec<PathBuf>,
     pub header_paths: Vec<PathBuf>,
     pub children: Vec<SrcPaths>,
 }
 
 impl SrcPaths {
+    /// Discovers the sources and headers in `root` (a project's src directory) and its subdirectories, skipping
+    /// anything matched by the project's .absignore file
     pub fn from_root(root: impl Into<PathBuf>) -> io::Result<SrcPaths> {
-        fn src_paths(root: PathBuf, entries: impl IntoIterator<Item=io::Result<fs::DirEntry>>) -> io::Result<SrcPaths> {
-            let mut paths = SrcPaths::default();
-            paths.root = root;
+        fn src_paths(root: PathBuf, entries: impl IntoIterator<Item=io::Result<fs::DirEntry>>, ignore_rules: &IgnoreRules) -> io::Result<SrcPaths> {
+            let mut paths = SrcPaths { root, ..Default::default() };
             for entry in entries {
                 let entry = entry?;
                 let file_type = entry.file_type()?;
+                let path = entry.path();
+                if ignore_rules.is_ignored(&path, file_type.is_dir()) {
+                    continue;
+                }
                 if file_type.is_file() {
-                    let path = entry.path();
                     if let Some(extension) = path.extension().and_then(OsStr::to_str) {
                         match extension {
                             "cpp" | "cxx" | "cc"   => paths.src_paths.push(path),
+                            "asm" => paths.asm_paths.push(path),
                             "h" | "hpp" => paths.header_paths.push(path),
                             _ => {},
                         }
                     }
                 } else if file_type.is_dir() {
-                    let path = entry.path();
                     let entries = fs::read_dir(&path)?;
-                    let child = src_paths(path, entries)?;
+                    let child = src_paths(path, entries, ignore_rules)?;
                     paths.children.push(child);
                 }
             }
@@ -103,9 +433,357 @@ impl SrcPaths {
         
         let root = root.into();
         let entries = fs::read_dir(&root)?;
-        let src_paths = src_paths(root, entries)?;
+        let ignore_rules = IgnoreRules::load(root.parent().unwrap_or_else(|| Path::new(".")))?;
+        let src_paths = src_paths(root, entries, &ignore_rules)?;
         Ok(src_paths)
     }
+
+    /// All source paths in this directory and its children
+    pub fn all_src_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = self.src_paths.iter().map(PathBuf::as_path).collect();
+        for child in &self.children {
+            paths.extend(child.all_src_paths());
+        }
+        paths
+    }
+
+    /// All header paths in this directory and its children
+    pub fn all_header_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = self.header_paths.iter().map(PathBuf::as_path).collect();
+        for child in &self.children {
+            paths.extend(child.all_header_paths());
+        }
+        paths
+    }
+
+    /// All assembly source paths in this directory and its children
+    pub fn all_asm_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = self.asm_paths.iter().map(PathBuf::as_path).collect();
+        for child in &self.children {
+            paths.extend(child.all_asm_paths());
+        }
+        paths
+    }
+}
+
+/// Goes from a src file path to an artifact path relative to output_dir_path
+/// (e.g., src/hello/world.cpp -> abs/debug/obj/hello/world.obj)
+///
+/// If the project flattens artifacts, the artifact is placed directly in output_dir_path, with a
+/// hash of the src file's relative path (minus extension) added to keep its name unique
+/// (e.g., src/hello/world.cpp -> abs/debug/obj/world-0123456789abcdef.obj)
+pub fn src_artifact_path(config: &ProjectConfig, project_path: &Path, src_path: &Path, output_dir_path: &Path, extension: impl AsRef<OsStr>) -> PathBuf {
+    let relative_path = src_relative_path(project_path, src_path);
+    if config.flatten_artifacts {
+        // Like the nested layout, ignore the src file's extension, so that e.g. pch.h and pch.cpp
+        // map to the same artifact.
+        let relative_path = relative_path.with_extension("");
+        // The extension is appended rather than set, because set_extension() would replace the hash
+        // of a dotted stem (e.g., x.test-0123456789abcdef.obj -> x.test.obj)
+        let mut file_name = relative_path.file_name().unwrap().to_owned();
+        file_name.push(format!("-{:016x}.", path_hash(&relative_path)));
+        file_name.push(extension);
+        output_dir_path.join(file_name)
+    } else {
+        let mut path = output_dir_path.join(relative_path);
+        let succ = path.set_extension(extension);
+        assert!(succ);
+        path
+    }
+}
+
+/// Goes from a src file path to a path relative to the src directory. Extra sources that live
+/// outside of the src directory are mapped into a dedicated namespace, so that their artifacts
+/// can't collide with those of regular sources
+/// (e.g., gen/hello.cpp -> .extra/gen/hello.cpp, C:/elsewhere/hello.cpp -> .extra/hello-0123456789abcdef.cpp)
+fn src_relative_path(project_path: &Path, src_path: &Path) -> PathBuf {
+    if let Ok(relative_path) = src_path.strip_prefix(project_path.join("src")) {
+        return relative_path.to_owned();
+    }
+    let mut path = PathBuf::from(EXTRA_SOURCES_NAMESPACE);
+    if let Ok(relative_path) = src_path.strip_prefix(project_path) {
+        path.push(relative_path);
+    } else {
+        let mut file_name = src_path.file_stem().unwrap().to_owned();
+        file_name.push(format!("-{:016x}", path_hash(src_path)));
+        if let Some(extension) = src_path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        path.push(file_name);
+    }
+    path
+}
+
+/// Reads the header dependencies of a src file from the JSON file generated by the compiler's
+/// /sourceDependencies flag
+pub fn read_src_deps(src_deps_json_path: impl AsRef<Path>) -> Result<Vec<PathBuf>, BuildError> {
+    // TODO: Support MSVC's versioning
+    #[derive(Deserialize)]
+    struct SrcDeps {
+        #[serde(rename = "Data")]
+        data: SrcDepsData,
+    }
+
+    #[derive(Deserialize)]
+    struct SrcDepsData {
+        #[serde(rename = "Includes")]
+        includes: Vec<PathBuf>,
+        #[serde(rename = "PCH")]
+        pch: Option<PathBuf>,
+    }
+
+    let src_deps_file = File::open(src_deps_json_path)?;
+    let src_deps_reader = BufReader::new(src_deps_file);
+    let src_deps: SrcDeps = serde_json::from_reader(src_deps_reader)
+        .or(Err(BuildError::DiscoverSrcDepsError))?;
+
+    let mut dependencies = DependencyBuilder::default()
+        .files(src_deps.data.includes);
+    if let Some(pch) = src_deps.data.pch {
+        dependencies = dependencies.file(pch);
+    }
+    Ok(dependencies.build())
+}
+
+/// A 64-bit FNV-1a hasher. Unlike `DefaultHasher`, its results are stable across runs and platforms.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: impl AsRef<[u8]>) {
+        for &byte in bytes.as_ref() {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// A hash of a relative path that is stable across runs and platforms
+fn path_hash(path: &Path) -> u64 {
+    let mut hash = Fnv1a::new();
+    for (i, component) in path.components().enumerate() {
+        if i > 0 {
+            hash.write([b'/']);
+        }
+        hash.write(component.as_os_str().to_string_lossy().as_bytes());
+    }
+    hash.0
+}
+
+/// A hash of a command line's arguments that is stable across runs and platforms
+pub fn args_hash(args: &[OsString]) -> u64 {
+    let mut hash = Fnv1a::new();
+    for arg in args {
+        hash.write(arg.to_string_lossy().as_bytes());
+        // Keeps e.g. ["/a", "b"] distinct from ["/ab"]
+        hash.write([0]);
+    }
+    hash.0
+}
+
+/// Hashes the path, size and modification time of `path`, and of every file under it if it's a directory
+fn hash_file_metadata(hash: &mut Fnv1a, path: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    hash.write(path.as_os_str().to_string_lossy().as_bytes());
+    if metadata.is_dir() {
+        let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(fs::DirEntry::file_name);
+        for entry in entries {
+            hash_file_metadata(hash, &entry.path())?;
+        }
+    } else {
+        let modified = FileTime::from_last_modification_time(&metadata);
+        hash.write(metadata.len().to_le_bytes());
+        hash.write(modified.unix_seconds().to_le_bytes());
+        hash.write(modified.nanoseconds().to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Parses the contents of a file passed to `--define-from-file` into (name, value) pairs. `path` is only used for
+/// error reporting.
+pub fn parse_definitions<'a>(path: &Path, contents: &'a str) -> Result<Vec<(&'a str, &'a str)>, BuildError> {
+    let mut definitions = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line.split_once('=').unwrap_or((line, "1"));
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(BuildError::InvalidDefinition(path.to_owned(), i + 1));
+        }
+        definitions.push((name, value.trim()));
+    }
+    Ok(definitions)
+}
+
+/// Splits the value of a flags environment variable (e.g., `ABS_CXXFLAGS`) into individual flags at whitespace,
+/// except within single or double quotes, which are removed, or where the whitespace is escaped with a backslash
+pub fn split_flags(value: &str) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut flag = String::new();
+    let mut in_flag = false;
+    let mut quote = None;
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => flag.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_flag = true;
+            },
+            // Backslashes are path separators on Windows, so they only escape whitespace and quotes
+            None if c == '\\' && matches!(chars.peek(), Some(&next) if next.is_whitespace() || next == '"' || next == '\'') => {
+                flag.push(chars.next().unwrap());
+                in_flag = true;
+            },
+            None if c.is_whitespace() => if in_flag {
+                flags.push(std::mem::take(&mut flag));
+                in_flag = false;
+            },
+            None => {
+                flag.push(c);
+                in_flag = true;
+            },
+        }
+    }
+    if in_flag {
+        flags.push(flag);
+    }
+    flags
+}
+
+/// Reads flags from the environment variable `name`, if it is set
+pub fn env_flags(name: &str) -> Vec<String> {
+    std::env::var(name).map(|value| split_flags(&value)).unwrap_or_default()
+}
+
+/// Stands for the project's src directory in `include_paths`
+const SRC_PLACEHOLDER: &str = "{src}";
+/// Stands for the directory that dependencies' headers are copied to in `include_paths`
+const DEPENDENCIES_PLACEHOLDER: &str = "{dependencies}";
+
+fn is_include_placeholder(path: &Path) -> bool {
+    path == Path::new(SRC_PLACEHOLDER) || path == Path::new(DEPENDENCIES_PLACEHOLDER)
+}
+
+/// A fingerprint of everything that goes into building a project: its configuration, its sources and include
+/// directories, the sources of the dependencies whose headers it copies, and the definitions and options it is built with. If the
+/// fingerprint hasn't changed since the project was last built, neither has its build product.
+///
+/// Only file metadata is read, which is much cheaper than setting up a build.
+pub fn inputs_fingerprint(config: &ProjectConfig, config_path: &Path, definitions: &[(&str, &str)], build_options: &BuildOptions) -> io::Result<u64> {
+    let mut hash = Fnv1a::new();
+    hash.write(cache_version());
+    // Hash the configuration as it will be used, which may differ from the project file (e.g., in a workspace)
+    hash.write(serde_json::to_string(config).unwrap());
+    for (name, value) in definitions {
+        hash.write(format!("{}={};", name, value));
+    }
+    hash.write([build_options.dep_files as u8, build_options.time_trace as u8]);
+    for name in ["ABS_CXXFLAGS", "ABS_LDFLAGS"] {
+        hash.write(std::env::var(name).unwrap_or_default());
+        hash.write([0]);
+    }
+
+    let project_path = config_path.parent().unwrap();
+    hash_file_metadata(&mut hash, config_path)?;
+    if let Some(definitions_path) = &build_options.define_from_file {
+        hash_file_metadata(&mut hash, definitions_path)?;
+    }
+    hash_file_metadata(&mut hash, &project_path.join("src"))?;
+    for extra_source in &config.extra_sources {
+        hash_file_metadata(&mut hash, &project_path.join(extra_source))?;
+    }
+    for include_path in config.include_paths.iter().filter(|path| !is_include_placeholder(path)) {
+        hash_file_metadata(&mut hash, &project_path.join(include_path))?;
+    }
+    for dependency in &config.dependencies {
+        let dependency_path = project_path.join(dependency);
+        hash_file_metadata(&mut hash, &dependency_path.join("abs.json"))?;
+        hash_file_metadata(&mut hash, &dependency_path.join("src"))?;
+    }
+    Ok(hash.0)
+}
+
+/// Recursively removes files in `dir` with the given extension that are not in `expected`. Returns the number of files
+/// removed and their total size in bytes.
+fn remove_unexpected_files(dir: &Path, extension: &str, expected: &HashSet<PathBuf>) -> io::Result<(u64, u64)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if matches!(err.kind(), io::ErrorKind::NotFound) => return Ok((0, 0)),
+        Err(err) => return Err(err),
+    };
+    let mut files = 0;
+    let mut bytes = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            let (child_files, child_bytes) = remove_unexpected_files(&path, extension, expected)?;
+            files += child_files;
+            bytes += child_bytes;
+        } else if file_type.is_file() && path.extension() == Some(OsStr::new(extension)) && !expected.contains(&path) {
+            bytes += entry.metadata()?.len();
+            files += 1;
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok((files, bytes))
+}
+
+/// Removes the files in a project's intermediates directory that were generated from sources other than `src_paths`
+/// and `asm_paths` (e.g., because they were deleted or renamed). Returns the number of files removed and their total
+/// size in bytes.
+pub fn remove_orphaned_source_artifacts(config: &ProjectConfig, project_path: &Path, intermediates_path: &Path, src_paths: &[&Path], asm_paths: &[&Path]) -> io::Result<(u64, u64)> {
+    let artifact_dirs = [
+        ("obj", "obj"),
+        ("obj", "o"),
+        // The precompiled header has the same artifact path as src/pch.cpp, so it's removed along with it
+        ("obj", "pch"),
+        ("obj", "flags"),
+        ("obj", "d"),
+        ("obj", "json"),
+        ("src_deps", "json"),
+        ("warning_cache", "warnings"),
+    ];
+    let mut files = 0;
+    let mut bytes = 0;
+    for (dir, extension) in artifact_dirs {
+        let dir = intermediates_path.join(dir);
+        let mut expected: HashSet<PathBuf> = src_paths.iter()
+            .map(|path| src_artifact_path(config, project_path, path, &dir, extension))
+            .collect();
+        if extension == "obj" {
+            expected.extend(asm_paths.iter().map(|path| src_artifact_path(config, project_path, path, &dir, "asm.obj")));
+        }
+        let (removed_files, removed_bytes) = remove_unexpected_files(&dir, extension, &expected)?;
+        files += removed_files;
+        bytes += removed_bytes;
+    }
+    Ok((files, bytes))
+}
+
+/// The linker flag that controls the UAC settings embedded in the manifest of the project's binary, if any.
+/// `has_manifests` is whether custom manifests are merged into it.
+fn manifest_uac_flag(config: &ProjectConfig, has_manifests: bool) -> Option<&'static str> {
+    let is_executable = matches!(config.output_type, OutputType::GuiApp | OutputType::ConsoleApp);
+    match config.uac_execution_level.filter(|_| is_executable) {
+        Some(UacExecutionLevel::AsInvoker) => Some("/manifestuac:level='asInvoker' uiAccess='false'"),
+        Some(UacExecutionLevel::HighestAvailable) => Some("/manifestuac:level='highestAvailable' uiAccess='false'"),
+        Some(UacExecutionLevel::RequireAdministrator) => Some("/manifestuac:level='requireAdministrator' uiAccess='false'"),
+        Some(UacExecutionLevel::Disabled) => Some("/manifestuac:no"),
+        // A custom manifest is expected to supply its own UAC settings, if any.
+        None if has_manifests => Some("/manifestuac:no"),
+        None => None,
+    }
 }
 
 fn cmd_flag(flag: impl AsRef<OsStr>, argument: impl AsRef<OsStr>) -> OsString {
@@ -114,6 +792,17 @@ fn cmd_flag(flag: impl AsRef<OsStr>, argument: impl AsRef<OsStr>) -> OsString {
     string
 }
 
+/// How `should_build_artifact` treats a dependency that doesn't exist
+#[derive(Clone, Copy)]
+pub enum MissingDependency {
+    /// The dependency is expected to exist (e.g., a source file, or a library to link), so its absence is an error
+    Fail,
+    /// The dependency was recorded by a previous build (e.g., a header the compiler reported), and has since been
+    /// removed, so the artifact is rebuilt. Rebuilding records the dependencies afresh, so a dependency that stays
+    /// missing doesn't cause rebuilds indefinitely.
+    Rebuild,
+}
+
 #[derive(Clone, Default)]
 pub struct DependencyBuilder {
     dependencies: Vec<PathBuf>,
@@ -133,11 +822,11 @@ impl DependencyBuilder {
     pub fn build(self) -> Vec<PathBuf> { self.dependencies }
 }
 
-fn run_cmd(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr>>, bin_paths: &[PathBuf], error: BuildError) -> Result<(), BuildError> {
+fn run_cmd(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr>>, bin_paths: &[PathBuf], error: impl FnOnce(Option<i32>) -> BuildError) -> Result<(), BuildError> {
     let mut path = OsString::from("%PATH%");
-    for i in 0..bin_paths.len() {
+    for bin_path in bin_paths {
         path.push(";");
-        path.push(bin_paths[i].as_os_str());
+        path.push(bin_path.as_os_str());
     }
     let code = Command::new(cmd)
         .args(args)
@@ -148,7 +837,7 @@ fn run_cmd(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr
     if code.success() {
         Ok(())
     } else {
-        Err(error)
+        Err(error(code.code()))
     }
 }
 
@@ -159,7 +848,7 @@ fn get_ps_args(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<O
         let arg: &OsStr = arg.as_ref();
         let lossy_str = arg.to_string_lossy();
         // The first condition here is a hack to support arrays
-        if lossy_str.chars().next() != Some('@') && lossy_str.contains(char::is_whitespace) {
+        if !lossy_str.starts_with('@') && lossy_str.contains(char::is_whitespace) {
             command.push("\"");
             command.push(arg);
             command.push("\"");
@@ -170,11 +859,11 @@ fn get_ps_args(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<O
     ["-command".into(), command]
 }
 
-#[allow(unused)]
-fn run_ps_cmd(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr>>, error: BuildError) -> Result<(), BuildError> {
+fn run_ps_cmd(cmd: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr>>, error: impl FnOnce(Option<i32>) -> BuildError) -> Result<(), BuildError> {
     run_cmd("powershell", get_ps_args(cmd, args), &[], error)
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Copy, Clone)]
 pub enum PchOption {
     GeneratePch,
@@ -182,97 +871,274 @@ pub enum PchOption {
     NoPch,
 }
 
+type CompileJob<'a> = Pin<Box<dyn Future<Output=Result<(), BuildError>> + Send + 'a>>;
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct WarningCache {
     pub warnings: Vec<String>,
 }
 
+/// The libraries in a set of library directories, keyed by lowercased file name. Persisted between builds, because
+/// scanning the toolchain's library directories is slow and they rarely change.
+#[derive(Default, Serialize, Deserialize)]
+struct LibraryIndex {
+    /// The indexed directories, in search order, along with their modification times when they were indexed. The
+    /// toolchain's directories include its version, so updating the toolchain invalidates the index too.
+    dirs: Vec<(PathBuf, Option<SystemTime>)>,
+    libraries: HashMap<String, PathBuf>,
+}
+
+impl LibraryIndex {
+    fn dir_times(dirs: &[&PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+        dirs.iter()
+            .map(|&dir| (dir.clone(), fs::metadata(dir).and_then(|metadata| metadata.modified()).ok()))
+            .collect()
+    }
+
+    fn build(dirs: &[&PathBuf]) -> io::Result<Self> {
+        let mut libraries = HashMap::new();
+        for &dir in dirs {
+            // A missing library directory is reported as a missing library, if one is needed
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    // Earlier directories take precedence, as they do for the linker
+                    libraries.entry(entry.file_name().to_string_lossy().to_ascii_lowercase())
+                        .or_insert_with(|| entry.path());
+                }
+            }
+        }
+        Ok(LibraryIndex { dirs: Self::dir_times(dirs), libraries })
+    }
+
+    /// Reads the index cached at `cache_path` if it is still valid for `dirs`. Otherwise, rebuilds it and updates the
+    /// cache.
+    fn load_or_build(cache_path: &Path, dirs: &[&PathBuf]) -> Result<Self, BuildError> {
+        let cached = fs::read_to_string(cache_path).ok()
+            .and_then(|cache| serde_json::from_str::<LibraryIndex>(&cache).ok())
+            .filter(|index| index.dirs == Self::dir_times(dirs));
+        if let Some(index) = cached {
+            return Ok(index);
+        }
+        let index = Self::build(dirs)?;
+        retry_output_io(cache_path, || fs::write(cache_path, serde_json::to_string(&index).unwrap()))?;
+        Ok(index)
+    }
+
+    fn find(&self, name: &Path) -> Option<&PathBuf> {
+        self.libraries.get(&name.as_os_str().to_string_lossy().to_ascii_lowercase())
+    }
+}
+
 impl<'a> BuildEnvironment<'a> {
     pub fn new(
         config: &'a ProjectConfig,
         config_path: impl Into<PathBuf>,
         build_options: &'a BuildOptions,
+        target: Platform,
         toolchain_paths: &'a ToolchainPaths,
         definitions: &'a [(&'a str, &'a str)],
         artifact_path: impl Into<PathBuf>,
     ) -> Result<Self, BuildError> {
         let host = Platform::host();
+        // Otherwise, a broken toolchain would only be reported as a generic failure to compile
+        if let Some(tool) = toolchain_paths.missing_tool() {
+            return Err(BuildError::ToolNotFound(tool, toolchain_paths.bin_paths.clone()));
+        }
+        let standard = config.cxx_options.standard_for(build_options.compile_mode);
+        if let Some(minimum_version) = toolchain_paths.unsupported_standard(standard) {
+            return Err(BuildError::UnsupportedCxxStandard(standard, minimum_version, toolchain_paths.msvc_version.unwrap()));
+        }
         let config_path = config_path.into();
         let mut project_path = config_path.clone();
         project_path.pop();
-        let manifest_path = project_path.join("windows_manifest.xml");
-        let has_manifest = manifest_path.exists();
+        let default_manifest_path = project_path.join("windows_manifest.xml");
+        let mut manifest_paths: Vec<PathBuf> = Some(default_manifest_path)
+            .filter(|path| path.exists())
+            .into_iter()
+            .collect();
+        for path in &config.manifests {
+            let path = project_path.join(path);
+            if !path.is_file() {
+                return Err(BuildError::MissingManifest(path));
+            }
+            manifest_paths.push(path);
+        }
+        let library_paths: Vec<PathBuf> = config.library_paths.iter()
+            .map(|path| project_path.join(path))
+            .collect();
+        let link_libraries: Vec<PathBuf> = config.link_libraries.iter()
+            .chain(config.target_link_libraries.get(&target).into_iter().flatten())
+            .cloned()
+            .collect();
+        let artifact_path = artifact_path.into();
+        let scratch_path_file = artifact_path.join(SCRATCH_PATH_FILE);
+        let intermediates_path = match &build_options.scratch_dir {
+            Some(scratch_dir) => {
+                create_output_dir(scratch_dir)?;
+                let scratch_dir = canonicalize(scratch_dir).map_err(|error| BuildError::from_output_io(scratch_dir, error))?;
+                // The scratch directory may be shared between unrelated projects, so give each root project its own
+                // subdirectory. The working directory is the root project's directory.
+                let root_path = canonicalize(".")?;
+                let root_name = root_path.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+                let intermediates_path = scratch_dir
+                    .join(format!("{}-{:016x}", root_name, path_hash(&root_path)))
+                    .join(&artifact_path);
+                let recorded_path = intermediates_path.to_string_lossy();
+                if fs::read_to_string(&scratch_path_file).ok().as_deref() != Some(&*recorded_path) {
+                    create_output_dir(&artifact_path)?;
+                    retry_output_io(&scratch_path_file, || fs::write(&scratch_path_file, &*recorded_path))?;
+                }
+                create_output_dir(&intermediates_path)?;
+                let marker_path = intermediates_path.join(SCRATCH_MARKER_FILE);
+                let canonical_artifact_path = canonicalize(&artifact_path).map_err(|error| BuildError::from_output_io(&artifact_path, error))?;
+                let canonical_artifact_path = canonical_artifact_path.to_string_lossy();
+                if fs::read_to_string(&marker_path).ok().as_deref() != Some(&*canonical_artifact_path) {
+                    retry_output_io(&marker_path, || fs::write(&marker_path, &*canonical_artifact_path))?;
+                }
+                intermediates_path
+            },
+            None => {
+                match fs::remove_file(&scratch_path_file) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(BuildError::from_output_io(&scratch_path_file, error)),
+                    _ => {},
+                }
+                artifact_path.clone()
+            },
+        };
+        let objs_path = intermediates_path.join("obj");
+        let src_deps_path = intermediates_path.join("src_deps");
+        let dependency_headers_path = intermediates_path.join("dependency_headers");
+        let warning_cache_path = intermediates_path.join("warning_cache");
+        let cache_version_path = artifact_path.join("abs-cache-version");
+        let cache_version = cache_version();
+        let cache_version_matches = fs::read_to_string(&cache_version_path).ok().as_deref() == Some(cache_version.as_str());
+        if !cache_version_matches {
+            // Start from scratch, which also forces everything to be rebuilt, since the objects are gone
+            for path in [&objs_path, &src_deps_path, &dependency_headers_path, &warning_cache_path] {
+                match fs::remove_dir_all(path) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(BuildError::from_output_io(path, error)),
+                    _ => {},
+                }
+            }
+            for name in ["library_index.json", "inputs_fingerprint", "env_flags", "definitions", "archive_inputs"] {
+                let path = artifact_path.join(name);
+                match fs::remove_file(&path) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(BuildError::from_output_io(&path, error)),
+                    _ => {},
+                }
+            }
+        }
+        create_output_dir(&objs_path)?;
+        create_output_dir(&src_deps_path)?;
+        create_output_dir(&dependency_headers_path)?;
+        create_output_dir(&warning_cache_path)?;
+        if !cache_version_matches {
+            retry_output_io(&cache_version_path, || fs::write(&cache_version_path, &cache_version))?;
+        }
+
+        let extra_compile_flags = env_flags("ABS_CXXFLAGS");
+        let extra_link_flags = env_flags("ABS_LDFLAGS");
+        let env_flags_path = artifact_path.join("env_flags");
+        let env_flags = serde_json::json!({ "ABS_CXXFLAGS": extra_compile_flags, "ABS_LDFLAGS": extra_link_flags }).to_string();
+        if fs::read_to_string(&env_flags_path).ok().as_deref() != Some(env_flags.as_str()) {
+            retry_output_io(&env_flags_path, || fs::write(&env_flags_path, &env_flags))?;
+        }
+        let build_mode_path = artifact_path.join(BUILD_MODE_FILE);
+        let build_mode = build_options.compile_mode.name();
+        if fs::read_to_string(&build_mode_path).ok().as_deref() != Some(build_mode) {
+            retry_output_io(&build_mode_path, || fs::write(&build_mode_path, build_mode))?;
+        }
+        let definitions_path = artifact_path.join("definitions");
+        let recorded_definitions = serde_json::to_string(definitions).unwrap();
+        if fs::read_to_string(&definitions_path).ok().as_deref() != Some(recorded_definitions.as_str()) {
+            retry_output_io(&definitions_path, || fs::write(&definitions_path, &recorded_definitions))?;
+        }
+
         let linker_lib_dependencies = match host.os() {
             Os::Windows => {
                 let mut dependencies = DependencyBuilder::default();
-                // TODO: Speed!!!
-                for lib in &config.link_libraries {
-                    for lib_path in &toolchain_paths.lib_paths {
-                        for entry in fs::read_dir(lib_path).unwrap() {
-                            let entry = entry.unwrap();
-                            if entry.file_type().unwrap().is_file() && entry.file_name().to_str().map(str::to_lowercase) == Some(lib.to_lowercase()) {
-                                dependencies = dependencies.file(entry.path());
-                            }
-                        }
+                let mut index = None;
+                for lib in &link_libraries {
+                    // Prebuilt libraries may be referred to by path
+                    if lib.is_file() {
+                        dependencies = dependencies.file(lib);
+                        continue;
+                    }
+                    let index = match &mut index {
+                        Some(index) => index,
+                        None => {
+                            let dirs: Vec<&PathBuf> = library_paths.iter().chain(&toolchain_paths.lib_paths).collect();
+                            index.insert(LibraryIndex::load_or_build(&artifact_path.join("library_index.json"), &dirs)?)
+                        },
+                    };
+                    match index.find(lib) {
+                        Some(path) => dependencies = dependencies.file(path),
+                        // Otherwise, a misspelled library would be silently ignored until it causes a confusing
+                        // unresolved symbol error at link time
+                        None => return Err(BuildError::LibraryNotFound(lib.clone())),
                     }
                 }
                 dependencies.build()
             },
-            Os::Linux => {
-                vec!["todo.a".into()]
-            }
+            // TODO: find libraries on Linux
+            Os::Linux => Vec::new(),
         };
-        let artifact_path = artifact_path.into();
-        let objs_path = artifact_path.join("obj");
-        let src_deps_path = artifact_path.join("src_deps");
-        let dependency_headers_path = artifact_path.join("dependency_headers");
-        let warning_cache_path = artifact_path.join("warning_cache");
-        fs::create_dir_all(&objs_path)?;
-        fs::create_dir_all(&src_deps_path)?;
-        fs::create_dir_all(&dependency_headers_path)?;
-        fs::create_dir_all(&warning_cache_path)?;
 
         let src_dir_path = project_path.join("src");
-        let assets_dir_path = project_path.join("assets");
+        let assets_dir_path = project_path.join(config.assets_dir.as_deref().unwrap_or_else(|| Path::new("assets")));
 
         Ok(BuildEnvironment {
             config_path,
-            manifest_path: if has_manifest {
-                Some(manifest_path)
-            } else {
-                None
-            },
+            manifest_paths,
 
+            link_libraries,
             linker_lib_dependencies,
+            dependency_libraries: Vec::new(),
+            library_paths,
+            extra_compile_flags,
+            extra_link_flags,
+            env_flags_path,
+            definitions_path,
 
             toolchain_paths,
             config,
             build_options,
+            target,
             definitions,
             project_path,
             artifact_path,
             src_dir_path,
             assets_dir_path,
+            intermediates_path,
             objs_path,
             src_deps_path,
             dependency_headers_path,
             warning_cache_path,
+            package_file_paths: Vec::new(),
 
             file_edit_times: Default::default(),
             unique_compiler_output: Default::default(),
+            num_errors: AtomicUsize::new(0),
+            warning_counts: Default::default(),
             progress_bar: Mutex::new(ProgressBar::new(0).downgrade()),
         })
     }
 
-    fn edit_time(&self, path: impl AsRef<Path>, fallback: FileTime) -> io::Result<FileTime> {
+    /// The last modification time of `path`, or `None` if it doesn't exist
+    fn edit_time(&self, path: impl AsRef<Path>) -> io::Result<Option<FileTime>> {
         let path = path.as_ref();
         let mut edit_times = self.file_edit_times.lock().unwrap();
         if let Some(&edit_time) = edit_times.get(path) {
             Ok(edit_time)
         } else {
             let time = match fs::metadata(path) {
-                Ok(metadata) => FileTime::from_last_modification_time(&metadata),
-                Err(err) if matches!(err.kind(), io::ErrorKind::NotFound) => fallback,
+                Ok(metadata) => Some(FileTime::from_last_modification_time(&metadata)),
+                Err(err) if matches!(err.kind(), io::ErrorKind::NotFound) => None,
                 Err(err) => return Err(err),
             };
             edit_times.insert(path.to_owned(), time);
@@ -280,48 +1146,50 @@ impl<'a> BuildEnvironment<'a> {
         }
     }
 
-    pub fn fail(&self, error: BuildError) -> ! {
-        print!("Build failed: ");
-        match error {
-            BuildError::NoSrcDirectory => println!("src directory does not exist."),
-            BuildError::CantReadSrcDirectory => println!("unable to read src directory."),
-            BuildError::DiscoverSrcDepsError => println!("unable to discover source dependencies."),
-            BuildError::CompilerError => println!("unable to compile."),
-            BuildError::LinkerError => println!("unable to link."),
-
-            BuildError::IoError(io_error) => println!("there was an io error: {:?}.", io_error.kind()),
-        }
-        std::process::exit(1);
-    }
-
+    /// Determines whether the artifacts are out of date with respect to their dependencies. A missing artifact is
+    /// always out of date. A missing dependency is handled according to `missing`.
     fn should_build_artifacts_impl(
         &self,
         dependency_paths: impl IntoIterator<Item=impl AsRef<Path>>,
+        missing: MissingDependency,
         artifact_paths: impl IntoIterator<Item=impl AsRef<Path>> + Clone,
         mut filter: impl FnMut(&Path) -> bool,
-    ) -> io::Result<bool> {
+    ) -> Result<bool, BuildError> {
+        let mut newest_dependency = FileTime::zero();
+        let mut dependency_missing = false;
+        for path in dependency_paths {
+            let path = path.as_ref();
+            match self.edit_time(path)? {
+                Some(edit_time) => newest_dependency = newest_dependency.max(edit_time),
+                None => match missing {
+                    MissingDependency::Fail => return Err(BuildError::MissingDependency(path.to_owned())),
+                    MissingDependency::Rebuild => dependency_missing = true,
+                },
+            }
+        }
         // If the config file has changed, I want to rebuild the whole project, so unconditionally add it
-        // as a dependency.
-        let config_path = self.config_path.clone();
-        let config_edit_time = self.edit_time(config_path, FileTime::now());
-        // TODO: shouldn't really be necessary to collect in a Vec here.
-        let dependencies: Result<Vec<_>, _> = dependency_paths.into_iter()
-            .map(|path| self.edit_time(path, FileTime::now()))
-            .chain(once(config_edit_time))
-            .collect();
-        let dependencies = dependencies?;
-        let newest_dependency = dependencies.into_iter().max().unwrap_or(FileTime::zero());
+        // as a dependency. Likewise for flags from the environment, for the definitions passed to the project, and for
+        // definitions read from a file. These always exist by the time anything is built.
+        let project_dependencies = once(&self.config_path)
+            .chain(once(&self.env_flags_path))
+            .chain(once(&self.definitions_path))
+            .chain(self.build_options.define_from_file.as_ref());
+        for path in project_dependencies {
+            let edit_time = self.edit_time(path)?.ok_or_else(|| BuildError::MissingDependency(path.clone()))?;
+            newest_dependency = newest_dependency.max(edit_time);
+        }
 
         let artifacts: Result<Vec<_>, _> = artifact_paths.clone().into_iter()
             .filter(|path|
                 filter(path.as_ref())
             )
-            .map(|path| self.edit_time(path, FileTime::zero()))
+            .map(|path| self.edit_time(path))
             .collect();
         let artifacts = artifacts?;
-        let oldest_artifact = artifacts.into_iter().min().unwrap_or(FileTime::zero());
+        // A missing artifact is treated as infinitely old
+        let oldest_artifact = artifacts.into_iter().min().flatten().unwrap_or(FileTime::zero());
 
-        let should_build_artifacts = newest_dependency > oldest_artifact;
+        let should_build_artifacts = dependency_missing || newest_dependency > oldest_artifact;
 
         // Invalidate edit times of all artifact paths
         if should_build_artifacts {
@@ -334,37 +1202,124 @@ impl<'a> BuildEnvironment<'a> {
         Ok(should_build_artifacts)
     }
 
-    pub fn should_build_artifact(&self, dependency_paths: impl IntoIterator<Item=impl AsRef<Path>>, artifact_path: impl AsRef<Path> + Clone) -> io::Result<bool> {
-        self.should_build_artifacts_impl(dependency_paths, [artifact_path], |_| true)
+    pub fn should_build_artifact(&self, dependency_paths: impl IntoIterator<Item=impl AsRef<Path>>, missing: MissingDependency, artifact_path: impl AsRef<Path> + Clone) -> Result<bool, BuildError> {
+        self.should_build_artifacts_impl(dependency_paths, missing, [artifact_path], |_| true)
     }
 
     #[allow(unused)]
-    fn should_build_artifacts(&self, dependency_paths: impl IntoIterator<Item=impl AsRef<Path>>, artifact_path: impl AsRef<Path>, extensions: impl IntoIterator<Item=impl AsRef<OsStr>> + Clone) -> io::Result<bool> {
+    fn should_build_artifacts(&self, dependency_paths: impl IntoIterator<Item=impl AsRef<Path>>, missing: MissingDependency, artifact_path: impl AsRef<Path>, extensions: impl IntoIterator<Item=impl AsRef<OsStr>> + Clone) -> Result<bool, BuildError> {
         let artifact_path = artifact_path.as_ref();
         if !artifact_path.exists() { return Ok(true); }
         let artifact_paths: Result<Vec<_>, _> = fs::read_dir(artifact_path)?.map(|entry| entry.map(|entry| entry.path())).collect();
         
         self.should_build_artifacts_impl(
             dependency_paths,
+            missing,
             artifact_paths?,
             |artifact| extensions.clone().into_iter().any(|desired| artifact.extension() == Some(desired.as_ref()))
         )
     }
 
-    fn copy_headers(&self, paths: &SrcPaths, dependency_name: &OsStr, root: &Path, dest_headers_path: &Path) -> Result<(), BuildError> {
+    /// Removes obj, precompiled header, src_deps and warning cache files that were generated from sources which no
+    /// longer exist (e.g., because they were deleted or renamed).
+    fn remove_orphaned_artifacts(&self, paths: &SrcPaths) -> Result<(), BuildError> {
+        remove_orphaned_source_artifacts(self.config, &self.project_path, &self.intermediates_path, &paths.all_src_paths(), &paths.all_asm_paths())?;
+        Ok(())
+    }
+
+    /// Returns an error if `path` is too long for Windows tools to reliably handle. Checking this
+    /// up-front avoids cryptic IO errors from the filesystem and toolchain later on.
+    fn check_path_length(&self, path: &Path) -> Result<(), BuildError> {
+        if !matches!(Platform::host().os(), Os::Windows) {
+            return Ok(());
+        }
+        let absolute_path = if path.is_absolute() {
+            path.to_owned()
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+        if absolute_path.as_os_str().to_string_lossy().encode_utf16().count() >= MAX_PATH {
+            Err(BuildError::PathTooLong(absolute_path))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_artifact_path_lengths(&self, paths: &SrcPaths) -> Result<(), BuildError> {
+        for path in paths.all_src_paths() {
+            self.check_path_length(&self.get_artifact_path(path, &self.objs_path, "obj"))?;
+            self.check_path_length(&self.get_artifact_path(path, &self.src_deps_path, "json"))?;
+            self.check_path_length(&self.get_artifact_path(path, &self.warning_cache_path, "warnings"))?;
+        }
+        for path in paths.all_asm_paths() {
+            self.check_path_length(&AsmTask::obj_path(self, path))?;
+        }
+        Ok(())
+    }
+
+    /// Returns an error if two sources map to the same artifact path (e.g., src/foo.cpp and src/foo.c, which would
+    /// both be compiled to foo.obj). Their objects, header dependencies and warnings would otherwise overwrite each
+    /// other's, which breaks incremental builds in confusing ways.
+    fn check_artifact_path_collisions(&self, paths: &SrcPaths) -> Result<(), BuildError> {
+        let mut artifact_paths = Vec::new();
+        for path in paths.all_src_paths() {
+            artifact_paths.push((path, self.get_artifact_path(path, &self.objs_path, "obj")));
+            artifact_paths.push((path, self.get_artifact_path(path, &self.src_deps_path, "json")));
+            artifact_paths.push((path, self.get_artifact_path(path, &self.warning_cache_path, "warnings")));
+        }
+        for path in paths.all_asm_paths() {
+            artifact_paths.push((path, AsmTask::obj_path(self, path)));
+        }
+
+        let mut sources_by_artifact: HashMap<String, &Path> = HashMap::new();
+        for (src_path, artifact_path) in artifact_paths {
+            let mut key = artifact_path.as_os_str().to_string_lossy().into_owned();
+            // Windows file systems are case-insensitive
+            if matches!(Platform::host().os(), Os::Windows) {
+                key = key.to_lowercase();
+            }
+            if let Some(other_src_path) = sources_by_artifact.insert(key, src_path) {
+                return Err(BuildError::ArtifactPathCollision(other_src_path.to_owned(), src_path.to_owned(), artifact_path));
+            }
+        }
+        Ok(())
+    }
+
+    fn lint_headers(&self, paths: &SrcPaths) -> Result<(), BuildError> {
         for header_path in &paths.header_paths {
-            let copied_header_path = self.get_artifact_path_relative_to(header_path, root, &dest_headers_path);
-            fs::create_dir_all(copied_header_path.parent().unwrap())?;
-            fs::copy(header_path, &copied_header_path)?;
+            let contents = fs::read_to_string(header_path)?;
+            if !has_include_guard(&contents) {
+                println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Warning: header \"{}\" has neither an include guard nor `#pragma once`.", header_path.as_os_str().to_string_lossy());
+            }
         }
         for child in &paths.children {
-            self.copy_headers(child, dependency_name, root, dest_headers_path)?;
+            self.lint_headers(child)?;
         }
         Ok(())
     }
 
-    pub async fn build(&mut self) -> Result<bool, BuildError> {
-        let paths = match SrcPaths::from_root(&self.src_dir_path) {
+    fn copy_headers(&self, paths: &SrcPaths, root: &Path, dest_headers_path: &Path) -> Result<(), BuildError> {
+        for header_path in &paths.header_paths {
+            let copied_header_path = self.get_artifact_path_relative_to(header_path, root, dest_headers_path);
+            self.check_path_length(&copied_header_path)?;
+            create_output_dir(copied_header_path.parent().unwrap())?;
+            retry_output_io(&copied_header_path, || {
+                // fs::copy also copies permissions, so a header that was checked out as read-only would otherwise
+                // make its copy impossible to overwrite
+                make_writable(&copied_header_path)?;
+                fs::copy(header_path, &copied_header_path)?;
+                make_writable(&copied_header_path)
+            })?;
+        }
+        for child in &paths.children {
+            self.copy_headers(child, root, dest_headers_path)?;
+        }
+        Ok(())
+    }
+
+    /// Discovers the project's sources, including extra sources outside of the src directory
+    pub fn discover_sources(&self) -> Result<SrcPaths, BuildError> {
+        let mut paths = match SrcPaths::from_root(&self.src_dir_path) {
             Ok(paths) => paths,
             Err(error) => {
                 if let io::ErrorKind::NotFound = error.kind() {
@@ -374,6 +1329,19 @@ impl<'a> BuildEnvironment<'a> {
                 }
             }
         };
+        for extra_source in &self.config.extra_sources {
+            let path = self.project_path.join(extra_source);
+            let path = canonicalize(&path).map_err(|_| BuildError::MissingExtraSource(path))?;
+            paths.src_paths.push(path);
+        }
+        self.check_artifact_path_lengths(&paths)?;
+        self.check_artifact_path_collisions(&paths)?;
+        Ok(paths)
+    }
+
+    /// Copies the headers of each dependency into the dependency headers directory, so that they can be included as
+    /// <dependency name>/<header path>
+    pub fn copy_dependency_headers(&self) -> Result<(), BuildError> {
         for path in &self.config.dependencies {
             let path = self.project_path.join(path);
             let path = crate::canonicalize(path).unwrap();
@@ -386,35 +1354,140 @@ impl<'a> BuildEnvironment<'a> {
             // project. Ignore any errors, because the destination directory may not exist yet, and
             // because this is not a critical operation.
             let _ = fs::remove_dir_all(&dest_headers_path);
-            self.copy_headers(&paths, project_name, &paths.root, &dest_headers_path)?;
+            self.copy_headers(&paths, &paths.root, &dest_headers_path)?;
         }
-        let pch = paths.src_paths.iter().any(|path| path.file_name() == Some(OsStr::new("pch.cpp")));
-        if pch {
-            let pch_path = self.src_dir_path.join("pch.cpp");
-            let task = CxxTask::compile(&pch_path, PchOption::GeneratePch);
-            if task.previous_valid_run(self)?.is_none() {
-                let progress_bar = ProgressBar::new_spinner()
-                    .with_message("Generating pre-compiled header");
-                progress_bar.enable_steady_tick(50);
-                task.run_guaranteed(self).await?;
-            }
-        };
-        let mut obj_paths = Vec::new();
-        self.compile_sources(&paths, &mut obj_paths, pch).await?;
+        Ok(())
+    }
 
-        let extension = match self.config.output_type {
-            OutputType::ConsoleApp | OutputType::GuiApp => "exe",
-            OutputType::DynamicLibrary => "dll",
-            OutputType::StaticLibrary => "lib",
-        };
-        let product_name = format!("{}.{}", self.config.name, extension);
-        let pdb_name = format!("{}.pdb", self.config.name);
+    /// Returns an error if any of `dependency_libraries` was built in a different mode than this project (e.g., a
+    /// release library in a debug build). That would otherwise tend to crash at run time, rather than fail to link.
+    pub fn check_dependency_library_modes(&self) -> Result<(), BuildError> {
+        let mode = self.build_options.compile_mode.name();
+        for library in &self.dependency_libraries {
+            // Dependency libraries are built into the artifact directories of their projects, which record the mode.
+            // A library without a record predates it, so there's no telling.
+            let build_mode_path = library.with_file_name(BUILD_MODE_FILE);
+            match fs::read_to_string(&build_mode_path) {
+                Ok(library_mode) if library_mode != mode => return Err(BuildError::DependencyModeMismatch(library.clone(), mode)),
+                Ok(_) => {},
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the precompiled header from src/pch.cpp if it exists and is out of date, and returns whether the
+    /// project uses a precompiled header. Must complete before any of the project's other sources are compiled.
+    ///
+    /// Only this environment's artifacts and state (including its progress bar and compiler output) are touched, so
+    /// the precompiled headers of dependencies that are built at once are generated concurrently.
+    async fn generate_pch(&self, paths: &SrcPaths) -> Result<bool, BuildError> {
+        let pch_path = self.src_dir_path.join("pch.cpp");
+        if !paths.src_paths.contains(&pch_path) {
+            return Ok(false);
+        }
+        let task = CxxTask::compile(&pch_path, PchOption::GeneratePch);
+        if task.previous_valid_run(self)?.is_none() {
+            let progress_bar = ProgressBar::new_spinner()
+                .with_message(format!("Generating pre-compiled header for \"{}\"", self.config.name));
+            progress_bar.enable_steady_tick(50);
+            task.run_guaranteed(self).await?;
+            progress_bar.finish_and_clear();
+        }
+        Ok(true)
+    }
+
+    /// Discovers the project's sources, and prepares the artifact directory for compiling them. Walks and copies
+    /// whole directory trees with blocking IO, so should be run via `block_in_place`.
+    fn prepare_sources(&self) -> Result<SrcPaths, BuildError> {
+        self.check_dependency_library_modes()?;
+        let paths = self.discover_sources()?;
+        self.remove_orphaned_artifacts(&paths)?;
+        if self.build_options.lint_headers {
+            self.lint_headers(&paths)?;
+        }
+        self.copy_dependency_headers()?;
+        Ok(paths)
+    }
+
+    /// The sources whose header dependencies haven't been recorded in src_deps since they last changed. Until they
+    /// are, whether a source's object is up to date can't be known without compiling it.
+    fn sources_to_scan<'p>(&self, paths: &'p SrcPaths) -> Result<Vec<&'p Path>, BuildError> {
+        let mut sources = Vec::new();
+        for path in paths.all_src_paths() {
+            if self.discover_src_deps(path)?.is_none() {
+                sources.push(path);
+            }
+        }
+        Ok(sources)
+    }
+
+    /// Does everything `build` does before compiling the project's sources (discovering them, copying dependency
+    /// headers, generating the precompiled header, and finding each source's header dependencies), so that later
+    /// builds start with warm caches. Returns whether the project uses a precompiled header.
+    pub async fn prime(&self) -> Result<bool, BuildError> {
+        let paths = task::block_in_place(|| self.prepare_sources())?;
+        let pch = self.generate_pch(&paths).await?;
+        let pch_option = if pch { PchOption::UsePch } else { PchOption::NoPch };
+        let mut scans: FuturesUnordered<_> = self.sources_to_scan(&paths)?.into_iter()
+            .map(|path| CxxTask::scan_dependencies(self, path, pch_option))
+            .collect();
+        let mut result = Ok(pch);
+        while let Some(scan_result) = scans.next().await {
+            if let Err(error) = scan_result {
+                result = Err(error);
+            }
+        }
+        result
+    }
+
+    pub async fn build(&mut self) -> Result<bool, BuildError> {
+        // The environment borrows from the caller, so it can't be moved into `spawn_blocking`. Instead, let the
+        // runtime move its other tasks (e.g., those reading compiler output) off of this thread while it's blocked.
+        let paths = task::block_in_place(|| self.prepare_sources())?;
+        let pch = self.generate_pch(&paths).await?;
+        let mut obj_paths = Vec::new();
+        let compile_result = self.compile_sources(&paths, &mut obj_paths, pch).await;
+        // Also worth seeing when compilation fails, since the warnings may hint at the cause
+        if self.build_options.warning_summary {
+            self.print_warning_summary();
+        }
+        compile_result?;
+        if self.build_options.time_trace {
+            match self.toolchain_paths.vendor {
+                Vendor::Clang => {
+                    let trace_path = self.merge_time_traces(&obj_paths)?;
+                    println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Wrote time trace to {}", trace_path.as_os_str().to_string_lossy());
+                },
+                Vendor::Msvc => {
+                    println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Warning: `--time-trace` requires a clang-based toolchain. It will be ignored.");
+                },
+            }
+        }
+        // Static libraries are archived with llvm-ar, which can build thin archives, only on Linux hosts. Elsewhere,
+        // they're archived with lib.exe, even for a clang-based toolchain.
+        if self.config.thin_archive && !matches!(Platform::host().os(), Os::Linux) {
+            println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Warning: `thin_archive` is only supported when building on Linux, where static libraries are archived with llvm-ar. It will be ignored.");
+        }
+
+        let product_name = self.product_name();
+        let pdb_name = format!("{}.pdb", self.output_name());
         let product_path = self.artifact_path.join(&product_name);
         let pdb_path = self.artifact_path.join(&pdb_name);
 
+        // Nothing else the product depends on changes with `--subsystem`, so record it in a file that does
+        let subsystem_path = self.artifact_path.join("subsystem");
+        let subsystem = format!("{:?}", self.config.output_type.subsystem(self.build_options.subsystem));
+        if fs::read_to_string(&subsystem_path).ok().as_deref() != Some(subsystem.as_str()) {
+            retry_output_io(&subsystem_path, || fs::write(&subsystem_path, &subsystem))?;
+        }
+
         let dependencies: Vec<_> = obj_paths.clone().iter().cloned()
             .chain(self.linker_lib_dependencies.iter().cloned())
-            .chain(self.manifest_path.iter().cloned())
+            .chain(self.dependency_libraries.iter().cloned())
+            .chain(self.manifest_paths.iter().cloned())
+            .chain(once(subsystem_path))
             .collect();
 
         super::kill_debugger();
@@ -428,14 +1501,31 @@ impl<'a> BuildEnvironment<'a> {
         while super::kill_debugger() == Some(1) {}
         while super::kill_process(&product_name) == Some(1) {}
             
-        let should_relink = self.should_build_artifact(&dependencies, &product_path)?;
+        let should_relink = self.should_build_artifact(&dependencies, MissingDependency::Fail, &product_path)?;
         let built_artifact = if should_relink {
-            self.link(&product_path, obj_paths)?
+            if self.generates_pdb() {
+                self.release_pdb(&pdb_path)?;
+            }
+            let built_artifact = self.link(&product_path, obj_paths)?;
+            if let Some(code_signing) = &self.config.code_signing {
+                if matches!(self.target.os(), Os::Windows) && !matches!(self.config.output_type, OutputType::StaticLibrary) {
+                    if let Err(error) = self.sign(&product_path, code_signing) {
+                        // Otherwise, the unsigned product would be considered up to date by the next build
+                        let _ = fs::remove_file(&product_path);
+                        return Err(error);
+                    }
+                }
+            }
+            built_artifact
         } else {
             true
         };
 
-        let mut package_file_paths = vec![product_path, pdb_path];
+        let mut package_file_paths = vec![product_path];
+        if self.generates_pdb() {
+            package_file_paths.push(pdb_path);
+        }
+        package_file_paths.extend(task::block_in_place(|| self.copy_runtime_libraries())?);
         if self.assets_dir_path.exists() && fs::metadata(&self.assets_dir_path)?.is_dir() {
             if matches!(self.config.output_type, OutputType::StaticLibrary) {
                 println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Warning: {} has an assets directory, which is unsupported in static library projects. It will be ignored.", self.config.name);
@@ -445,16 +1535,208 @@ impl<'a> BuildEnvironment<'a> {
             }
             package_file_paths.push(self.assets_dir_path.clone());
         }
+        self.package_file_paths = package_file_paths;
         Ok(built_artifact)
     }
 
-    /// Goes from a src file path to an artifact path relative to output_dir_path
-    /// (e.g., src/hello/world.cpp -> abs/debug/obj/hello/world.obj)
+    /// Copies the DLLs in `config.runtime_libraries` next to the build product, if they are out of date, and returns
+    /// the paths of the copies. For the root project, these include the runtime libraries of all of its dependencies.
+    fn copy_runtime_libraries(&self) -> Result<Vec<PathBuf>, BuildError> {
+        // Libraries have no use for DLLs at run time; the executable that links them gets a copy
+        if matches!(self.config.output_type, OutputType::StaticLibrary) {
+            return Ok(Vec::new());
+        }
+        let mut dest_paths = Vec::new();
+        for library in &self.config.runtime_libraries {
+            let dest_path = self.artifact_path.join(library.file_name().unwrap());
+            if self.should_build_artifact([library], MissingDependency::Fail, &dest_path)? {
+                retry_output_io(&dest_path, || fs::copy(library, &dest_path))?;
+            }
+            dest_paths.push(dest_path);
+        }
+        Ok(dest_paths)
+    }
+
+    /// Authenticode-signs the product at `product_path` with signtool
+    fn sign(&self, product_path: &Path, code_signing: &CodeSigningOptions) -> Result<(), BuildError> {
+        let signtool_path = self.toolchain_paths.signtool_path.as_ref().ok_or(BuildError::SignToolNotFound)?;
+        let progress_bar = ProgressBar::new_spinner()
+            .with_message(format!("Signing {}", product_path.to_string_lossy()));
+        progress_bar.enable_steady_tick(50);
+
+        let mut args: Vec<OsString> = vec!["sign".into(), "/fd".into(), "SHA256".into()];
+        if let Some(thumbprint) = &code_signing.certificate_thumbprint {
+            args.push("/sha1".into());
+            args.push(thumbprint.into());
+        }
+        if let Some(certificate_file) = &code_signing.certificate_file {
+            args.push("/f".into());
+            args.push(self.project_path.join(certificate_file).into());
+            if let Some(password) = std::env::var_os("ABS_CODE_SIGNING_PASSWORD") {
+                args.push("/p".into());
+                args.push(password);
+            }
+        }
+        if let Some(timestamp_url) = &code_signing.timestamp_url {
+            args.push("/tr".into());
+            args.push(timestamp_url.into());
+            args.push("/td".into());
+            args.push("SHA256".into());
+        }
+        args.push(product_path.into());
+        let result = run_cmd(signtool_path, &args, &self.toolchain_paths.bin_paths, |_| BuildError::SigningError(product_path.to_owned()));
+        progress_bar.finish_and_clear();
+        result
+    }
+
+    /// Whether linking the build product generates a PDB, which it does unless the product is a static library or
+    /// debug info is disabled
+    fn generates_pdb(&self) -> bool {
+        !matches!(self.config.output_type, OutputType::StaticLibrary) &&
+            self.config.cxx_options.debug_info_format_for(self.build_options.compile_mode) != DebugInfoFormat::None
+    }
+
+    /// Makes sure that the linker will be able to write the product's PDB. A debugger may keep the PDB locked even
+    /// after the product has been killed, so if it is locked, it is moved aside instead (which Windows permits for
+    /// files that are open with delete sharing, as debuggers generally open them). PDBs moved aside by previous
+    /// builds are removed once they're no longer locked.
+    fn release_pdb(&self, pdb_path: &Path) -> Result<(), BuildError> {
+        let pdb_name = pdb_path.file_name().unwrap().to_string_lossy();
+        let stale_prefix = format!("{}.", pdb_name);
+        if let Ok(entries) = fs::read_dir(&self.artifact_path) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with(&stale_prefix) && name.ends_with(".old") {
+                    // Still locked if this fails; try again next time
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        match fs::OpenOptions::new().write(true).open(pdb_path) {
+            Ok(_) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(_) => {
+                let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+                let stale_path = self.artifact_path.join(format!("{}{}.old", stale_prefix, timestamp));
+                fs::rename(pdb_path, &stale_path).map_err(|_| BuildError::PdbLocked(pdb_path.to_owned()))?;
+                println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Warning: \"{}\" is locked, probably by a debugger that is still attached. It was moved aside so that a new one can be written.", pdb_path.as_os_str().to_string_lossy());
+                Ok(())
+            },
+        }
+    }
+
+    /// Merges the per-source time traces written by clang's `-ftime-trace` into a single Chrome trace, in which each
+    /// source file appears as a separate process. Returns the path of the merged trace.
+    fn merge_time_traces(&self, obj_paths: &[PathBuf]) -> Result<PathBuf, BuildError> {
+        let mut events = Vec::new();
+        for (pid, obj_path) in obj_paths.iter().enumerate() {
+            // Assembly sources have no trace
+            let trace = match fs::read_to_string(obj_path.with_extension("json")) {
+                Ok(trace) => trace,
+                Err(_) => continue,
+            };
+            let trace: serde_json::Value = match serde_json::from_str(&trace) {
+                Ok(trace) => trace,
+                Err(_) => continue,
+            };
+            let file_events = match trace.get("traceEvents").and_then(serde_json::Value::as_array) {
+                Some(file_events) => file_events,
+                None => continue,
+            };
+            let file_name = obj_path.file_stem().unwrap_or_default().to_string_lossy();
+            events.push(serde_json::json!({
+                "ph": "M",
+                "name": "process_name",
+                "pid": pid,
+                "args": { "name": file_name },
+            }));
+            for event in file_events {
+                let mut event = event.clone();
+                if let Some(event) = event.as_object_mut() {
+                    event.insert("pid".to_string(), pid.into());
+                }
+                events.push(event);
+            }
+        }
+        let trace_path = self.artifact_path.join("time_trace.json");
+        let trace = serde_json::json!({ "traceEvents": events });
+        retry_output_io(&trace_path, || fs::write(&trace_path, trace.to_string()))?;
+        Ok(trace_path)
+    }
+
+    /// Zips the files in `package_file_paths` into abs/{mode}/{name}-{target}.zip, and returns the path of the zip
+    /// file. Must be called after `build()`.
+    pub fn package(&self, include_pdb: bool) -> Result<PathBuf, BuildError> {
+        let mode = self.build_options.compile_mode.name();
+        let zip_name = format!("{}-{}.zip", self.config.name, format!("{:?}", self.target).to_lowercase());
+        let zip_path: PathBuf = ["abs", mode, &zip_name].iter().collect();
+        let paths: Vec<&PathBuf> = self.package_file_paths.iter()
+            .filter(|path| include_pdb || path.extension() != Some(OsStr::new("pdb")))
+            .filter(|path| path.exists())
+            .collect();
+
+        // Pass the paths as a PowerShell array literal. Single quotes are escaped by doubling them.
+        let mut path_array = OsString::from("@(");
+        for (i, path) in paths.iter().enumerate() {
+            if i > 0 {
+                path_array.push(",");
+            }
+            path_array.push("'");
+            path_array.push(path.as_os_str().to_string_lossy().replace('\'', "''"));
+            path_array.push("'");
+        }
+        path_array.push(")");
+        run_ps_cmd(
+            "Compress-Archive",
+            [OsStr::new("-Force"), OsStr::new("-Path"), &path_array, OsStr::new("-DestinationPath"), zip_path.as_os_str()],
+            |_| BuildError::PackageError,
+        )?;
+        Ok(zip_path)
+    }
+
+    /// The include directories for the project, in the order they are searched (after the toolchain's own)
+    pub fn include_paths(&self) -> Vec<PathBuf> {
+        let custom_paths = &self.config.include_paths;
+        let has_placeholders = custom_paths.iter().any(|path| is_include_placeholder(path));
+        let mut paths = Vec::new();
+        if !has_placeholders {
+            match self.config.include_precedence.unwrap_or(IncludePrecedence::Dependencies) {
+                IncludePrecedence::Dependencies => paths.extend([self.dependency_headers_path.clone(), self.src_dir_path.clone()]),
+                IncludePrecedence::Project => paths.extend([self.src_dir_path.clone(), self.dependency_headers_path.clone()]),
+            }
+        }
+        for path in custom_paths {
+            if path == Path::new(SRC_PLACEHOLDER) {
+                paths.push(self.src_dir_path.clone());
+            } else if path == Path::new(DEPENDENCIES_PLACEHOLDER) {
+                paths.push(self.dependency_headers_path.clone());
+            } else {
+                paths.push(self.project_path.join(path));
+            }
+        }
+        // If only one placeholder was used, the other directory is still needed, so search it last
+        for path in [&self.src_dir_path, &self.dependency_headers_path] {
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+        paths
+    }
+
+    /// The configured output name of the build product, without an extension
+    pub fn output_name(&self) -> String {
+        self.config.output_name(self.build_options.compile_mode.name(), self.target)
+    }
+
+    /// The file name of the build product (e.g., my_app.exe)
+    pub fn product_name(&self) -> String {
+        self.config.product_name(self.build_options.compile_mode.name(), self.target)
+    }
+
+    /// Goes from a src file path to an artifact path relative to output_dir_path. See `src_artifact_path`.
     pub fn get_artifact_path(&self, src_path: impl AsRef<Path>, output_dir_path: impl AsRef<Path>, extension: impl AsRef<OsStr>) -> PathBuf {
-        let mut path = self.get_artifact_path_relative_to(src_path, &self.src_dir_path, output_dir_path);
-        let succ = path.set_extension(extension);
-        assert!(succ);
-        path
+        src_artifact_path(self.config, &self.project_path, src_path.as_ref(), output_dir_path.as_ref(), extension)
     }
 
     fn get_artifact_path_relative_to(&self, src_path: impl AsRef<Path>, relative_to: impl AsRef<Path>, output_dir_path: impl AsRef<Path>) -> PathBuf {
@@ -466,12 +1748,82 @@ impl<'a> BuildEnvironment<'a> {
         path
     }
 
+    /// Prints the diagnostics for a src file together, under a header line naming the file
+    pub fn print_diagnostics(&self, src_path: impl AsRef<Path>, diagnostics: &[String]) {
+        if diagnostics.is_empty() {
+            return;
+        }
+        let src_path = src_path.as_ref();
+        let name = src_path.strip_prefix(&self.project_path).unwrap_or(src_path);
+        let mut output = format!("── {} ──", name.to_string_lossy());
+        for diagnostic in diagnostics {
+            output.push('\n');
+            output.push_str(diagnostic);
+        }
+        println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "{}", output);
+    }
+
+    /// Counts the warnings that compiling `src_path` produced, for `--warning-summary`. Unlike printing, this counts
+    /// warnings that were already printed for another source.
+    pub fn record_warnings(&self, src_path: &Path, warnings: &[String]) {
+        if !self.build_options.warning_summary {
+            return;
+        }
+        let mut warning_counts = self.warning_counts.lock().unwrap();
+        for warning in warnings {
+            let code = diagnostic_code(warning).unwrap_or("other");
+            let (occurrences, src_paths) = warning_counts.entry(code.to_string()).or_default();
+            *occurrences += 1;
+            src_paths.insert(src_path.to_owned());
+        }
+    }
+
+    fn print_warning_summary(&self) {
+        let warning_counts = self.warning_counts.lock().unwrap();
+        if warning_counts.is_empty() {
+            return;
+        }
+        let mut codes: Vec<(&String, &(usize, HashSet<PathBuf>))> = warning_counts.iter().collect();
+        codes.sort_by(|(a_code, (a_occurrences, _)), (b_code, (b_occurrences, _))| b_occurrences.cmp(a_occurrences).then(a_code.cmp(b_code)));
+        let total: usize = codes.iter().map(|(_, (occurrences, _))| occurrences).sum();
+        let mut output = format!("Warning summary for \"{}\" ({} warning{}):", self.config.name, total, if total == 1 { "" } else { "s" });
+        for (code, (occurrences, src_paths)) in codes {
+            output.push_str(&format!(
+                "\n    {}: {} occurrence{} across {} file{}",
+                code,
+                occurrences,
+                if *occurrences == 1 { "" } else { "s" },
+                src_paths.len(),
+                if src_paths.len() == 1 { "" } else { "s" },
+            ));
+        }
+        println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "{}", output);
+    }
+
+    fn add_job_to_progress_bar(&self, progress_bar: &mut Option<ProgressBar>) {
+        if let Some(progress_bar) = progress_bar {
+            progress_bar.inc_length(1);
+        } else {
+            let pb = ProgressBar::new(1)
+                .with_style(
+                    ProgressStyle::default_bar().template("{bar} Compiling source files | {pos}/{len}")
+                );
+            *self.progress_bar.lock().unwrap() = pb.downgrade();
+
+            // TODO: this is a hack and shouldn't be necessary. the only time the
+            // progress bar updates is when something changes, and that should already
+            // trigger an update without this line. But it doesn't for some reason...
+            pb.enable_steady_tick(30);
+            *progress_bar = Some(pb);
+        }
+    }
+
     #[async_recursion]
     async fn compile_sources_recursive(
         &'a self,
         paths: &'a SrcPaths,
         obj_paths: &mut Vec<PathBuf>,
-        jobs: &mut Vec<Pin<Box<dyn Future<Output=Result<(), BuildError>> + Send + 'a>>>,
+        jobs: &mut Vec<CompileJob<'a>>,
         progress_bar: &mut Option<ProgressBar>,
         pch: PchOption,
     ) -> Result<(), BuildError> {
@@ -490,52 +1842,52 @@ impl<'a> BuildEnvironment<'a> {
                     .build()
             });
 
-            let task = CxxTask::compile(&path, pch);
+            let task = CxxTask::compile(path, pch);
             if task.previous_valid_run(self)?.is_none() {
-                if let Some(progress_bar) = progress_bar {
-                    progress_bar.inc_length(1);
-                } else {
-                    let pb = ProgressBar::new(1)
-                        .with_style(
-                            ProgressStyle::default_bar().template("{bar} Compiling source files | {pos}/{len}")
-                        );
-                    *self.progress_bar.lock().unwrap() = pb.downgrade();
-    
-                    // TODO: this is a hack and shouldn't be necessary. the only time the
-                    // progress bar updates is when something changes, and that should already
-                    // trigger an update without this line. But it doesn't for some reason...
-                    pb.enable_steady_tick(30);
-                    *progress_bar = Some(pb);
-                };
-                let obj_path = self.get_artifact_path(&path, &self.objs_path, "obj");
+                self.add_job_to_progress_bar(progress_bar);
+                let obj_path = self.get_artifact_path(path, &self.objs_path, "obj");
                 let mut obj_subdir_path = obj_path;
                 obj_subdir_path.pop();
-                fs::create_dir_all(&obj_subdir_path).unwrap();
+                create_output_dir(&obj_subdir_path)?;
     
                 let fut = Box::pin(async move {
                     task.run(self).await.map(|_| ())
                 });
                 jobs.push(fut);
             } else {
+                if self.build_options.dep_files && !self.get_artifact_path(path, &self.objs_path, "d").exists() {
+                    self.write_dep_file(path)?;
+                }
                 let warning_cache_out_of_date = if let Some(dependencies) = &dependencies {
-                    self.should_build_artifact(dependencies, &warning_cache_path)?
+                    self.should_build_artifact(dependencies, MissingDependency::Rebuild, &warning_cache_path)?
                 } else {
                     true
                 };
                 if !warning_cache_out_of_date {
                     if let Ok(warning_cache) = fs::read_to_string(warning_cache_path) {
                         if let Ok(warning_cache) = serde_json::from_str::<WarningCache>(&warning_cache) {
-                            for warning in warning_cache.warnings {
-                                if self.unique_compiler_output.lock().unwrap().insert(warning.lines().next().unwrap().to_string()) {
-                                    println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "{}", warning);
-                                }
-                            }
+                            self.record_warnings(path, &warning_cache.warnings);
+                            let warnings: Vec<String> = warning_cache.warnings.into_iter()
+                                .filter(|warning| self.unique_compiler_output.lock().unwrap().insert(warning.lines().next().unwrap().to_string()))
+                                .collect();
+                            self.print_diagnostics(path, &warnings);
                         }
                     }
                 }
             }
         }
 
+        for path in paths.asm_paths.iter() {
+            let task = AsmTask::assemble(path);
+            obj_paths.push(AsmTask::obj_path(self, path));
+            if task.previous_valid_run(self)?.is_none() {
+                self.add_job_to_progress_bar(progress_bar);
+                jobs.push(Box::pin(async move {
+                    task.run(self).await.map(|_| ())
+                }));
+            }
+        }
+
         for child in &paths.children {
             self.compile_sources_recursive(child, obj_paths, jobs, progress_bar, pch).await?;
         }
@@ -543,9 +1895,9 @@ impl<'a> BuildEnvironment<'a> {
         Ok(())
     }
 
-    pub async fn compile_sources<'b>(
+    pub async fn compile_sources(
         &self,
-        paths: &'b SrcPaths,
+        paths: &SrcPaths,
         obj_paths: &mut Vec<PathBuf>,
         pch: bool,
     ) -> Result<(), BuildError> {
@@ -558,7 +1910,8 @@ impl<'a> BuildEnvironment<'a> {
         let mut succ = 0;
         let mut fail = 0;
         let num_jobs = jobs.len();
-        for job_res in join_all(jobs).await {
+        let mut jobs: FuturesUnordered<_> = jobs.into_iter().collect();
+        while let Some(job_res) = jobs.next().await {
             match job_res {
                 Ok(()) => succ += 1,
                 Err(err) => {
@@ -566,6 +1919,14 @@ impl<'a> BuildEnvironment<'a> {
                     fail += 1;
                 }
             }
+            if let Some(max_errors) = self.build_options.max_errors {
+                let num_errors = self.num_errors.load(Ordering::Relaxed);
+                if num_errors >= max_errors && !jobs.is_empty() {
+                    // Dropping the remaining jobs kills their compilers
+                    println_above_progress_bar_if_visible!(self.progress_bar.lock().unwrap(), "Stopping after {} {}. {} remaining {} cancelled.", num_errors, if num_errors == 1 { "error" } else { "errors" }, jobs.len(), if jobs.len() == 1 { "compile was" } else { "compiles were" });
+                    break;
+                }
+            }
         }
 
         if fail > 0 {
@@ -575,74 +1936,112 @@ impl<'a> BuildEnvironment<'a> {
     }
 
     pub fn discover_src_deps(&self, path: impl AsRef<Path>) -> Result<Option<Vec<PathBuf>>, BuildError> {
-        // TODO: Support MSVC's versioning
-        #[derive(Deserialize)]
-        struct SrcDeps {
-            #[serde(rename = "Data")]
-            data: SrcDepsData,
-        }
-
-        #[derive(Deserialize)]
-        struct SrcDepsData {
-            #[serde(rename = "Includes")]
-            includes: Vec<PathBuf>,
-            #[serde(rename = "PCH")]
-            pch: Option<PathBuf>,
-        }
-
         let path = path.as_ref();
-        let src_deps_json_path = self.get_artifact_path(&path, &self.src_deps_path, "json");
-        if self.should_build_artifact([path], &src_deps_json_path)? {
+        let src_deps_json_path = self.get_artifact_path(path, &self.src_deps_path, "json");
+        if self.should_build_artifact([path], MissingDependency::Fail, &src_deps_json_path)? {
             Ok(None)
         } else {
-            let src_deps_file = File::open(&src_deps_json_path)?;
-            let src_deps_reader = BufReader::new(src_deps_file);
-            let src_deps: SrcDeps = serde_json::from_reader(src_deps_reader)
-                .or(Err(BuildError::DiscoverSrcDepsError))?;
+            Ok(Some(read_src_deps(&src_deps_json_path)?))
+        }
+    }
 
-            let mut dependencies = DependencyBuilder::default()
-                .files(src_deps.data.includes);
-            if let Some(pch) = src_deps.data.pch {
-                dependencies = dependencies.file(pch);
-            }
-            
-            Ok(Some(dependencies.build()))
+    /// Writes a Makefile-style dependency file (e.g., obj/hello/world.d) for the object built from
+    /// the given src file, listing the src file and all of its header dependencies.
+    pub fn write_dep_file(&self, src_path: impl AsRef<Path>) -> Result<(), BuildError> {
+        let (dep_file_path, contents) = self.dep_file(src_path)?;
+        retry_output_io(&dep_file_path, || fs::write(&dep_file_path, &contents))
+    }
+
+    /// The path and contents of the dependency file written by `write_dep_file`
+    pub fn dep_file(&self, src_path: impl AsRef<Path>) -> Result<(PathBuf, String), BuildError> {
+        fn escape(path: &Path) -> String {
+            path.to_string_lossy()
+                .replace('\\', "/")
+                .replace(' ', "\\ ")
+                .replace('#', "\\#")
         }
+
+        let src_path = src_path.as_ref();
+        let dependencies = read_src_deps(self.get_artifact_path(src_path, &self.src_deps_path, "json"))?;
+        let obj_path = self.get_artifact_path(src_path, &self.objs_path, "obj");
+        let mut contents = format!("{}: {}", escape(&obj_path), escape(src_path));
+        for dependency in &dependencies {
+            contents.push_str(" \\\n  ");
+            contents.push_str(&escape(dependency));
+        }
+        contents.push('\n');
+        Ok((self.get_artifact_path(src_path, &self.objs_path, "d"), contents))
     }
-    pub fn link(
-        &mut self,
+    /// The linker (or librarian, for static libraries) to run to produce `output_path`, and the arguments to run it
+    /// with
+    pub fn link_command(
+        &self,
         output_path: impl AsRef<Path>,
-        obj_paths: impl IntoIterator<Item=impl AsRef<Path>> + Clone,
-    ) -> Result<bool, BuildError> {
-        let progress_bar = ProgressBar::new_spinner()
-            .with_message(format!("Linking {}", output_path.as_ref().to_string_lossy()));
-        progress_bar.enable_steady_tick(50);
+        obj_paths: impl IntoIterator<Item=impl AsRef<Path>>,
+    ) -> (&'static str, Vec<OsString>) {
+        self.link_command_on(Platform::host().os(), output_path, obj_paths)
+    }
 
-        let host = Platform::host();
+    /// Like `link_command`, but for the toolchain of `host` rather than that of the actual host
+    pub fn link_command_on(
+        &self,
+        host: Os,
+        output_path: impl AsRef<Path>,
+        obj_paths: impl IntoIterator<Item=impl AsRef<Path>>,
+    ) -> (&'static str, Vec<OsString>) {
         let output_path = output_path.as_ref();
-        let mut args = match host.os() {
+        let mut args = match host {
             Os::Windows => {
                 let mut flags: Vec<OsString> = vec![
                     "/nologo".into(),
                 ];
-                let output_flag = match self.config.output_type {
-                    OutputType::GuiApp => Some("/SUBSYSTEM:WINDOWS"),
-                    OutputType::ConsoleApp => Some("/SUBSYSTEM:CONSOLE"),
-                    OutputType::DynamicLibrary => Some("/DLL"),
-                    OutputType::StaticLibrary => None,
+                let output_flag = match self.config.output_type.subsystem(self.build_options.subsystem) {
+                    Some(Subsystem::Windows) => Some("/SUBSYSTEM:WINDOWS"),
+                    Some(Subsystem::Console) => Some("/SUBSYSTEM:CONSOLE"),
+                    None if matches!(self.config.output_type, OutputType::DynamicLibrary) => Some("/DLL"),
+                    None => None,
                 };
                 if let Some(output_flag) = output_flag {
                     flags.push(output_flag.into());
                 }
+                // The linker picks the entry point based on the subsystem, so when the subsystem is overridden, keep
+                // the one that the sources were written for (as generated by `abs init`)
+                match (self.config.output_type, self.build_options.subsystem) {
+                    (OutputType::GuiApp, Some(Subsystem::Console)) => flags.push("/ENTRY:wWinMainCRTStartup".into()),
+                    (OutputType::ConsoleApp, Some(Subsystem::Windows)) => flags.push("/ENTRY:mainCRTStartup".into()),
+                    _ => {},
+                }
                 if !matches!(self.config.output_type, OutputType::StaticLibrary) {
                     flags.push("/manifest:embed".into());
+                }
+                if self.generates_pdb() {
                     flags.push("/debug".into());
+                    if self.config.strip_pdb_path && !self.config.reproducible {
+                        flags.push("/PDBALTPATH:%_PDB%".into());
+                    }
+                }
+                if !matches!(self.config.output_type, OutputType::StaticLibrary) {
+                    flags.extend(self.config.hardening.link_flags(self.target.architecture()).into_iter().map(OsString::from));
                 }
-                if let Some(manifest_path) = &self.manifest_path {
-                    let mut flag = OsString::from("/manifestinput:");
-                    flag.push(manifest_path);
-                    flags.push(flag);
-                    flags.push("/manifestuac:no".into());
+                if self.config.reproducible {
+                    flags.push("/Brepro".into());
+                    if !matches!(self.config.output_type, OutputType::StaticLibrary) {
+                        // Incremental linking pads and patches the binary in place, which defeats determinism
+                        flags.push("/INCREMENTAL:NO".into());
+                    }
+                    if self.generates_pdb() {
+                        // Only embed the PDB's file name, rather than its absolute path
+                        flags.push("/PDBALTPATH:%_PDB%".into());
+                    }
+                }
+                if let Some(flag) = manifest_uac_flag(self.config, !self.manifest_paths.is_empty()) {
+                    flags.push(flag.into());
+                }
+                if !self.manifest_paths.is_empty() {
+                    // The linker merges all of the manifests
+                    for manifest_path in &self.manifest_paths {
+                        flags.push(cmd_flag("/manifestinput:", manifest_path));
+                    }
                 } else {
                     match self.config.output_type {
                         OutputType::GuiApp => {
@@ -652,13 +2051,39 @@ impl<'a> BuildEnvironment<'a> {
                         OutputType::ConsoleApp | OutputType::DynamicLibrary | OutputType::StaticLibrary => {},
                     }
                 }
-                for path in &self.toolchain_paths.lib_paths {
+                for path in self.library_paths.iter().chain(&self.toolchain_paths.lib_paths) {
                     flags.push(cmd_flag("/LIBPATH:", path));
                 }
+                if !matches!(self.config.output_type, OutputType::StaticLibrary) {
+                    flags.extend(self.extra_link_flags.iter().map(OsString::from));
+                }
                 flags
             },
             Os::Linux => {
-                vec!["todo".into()]
+                if matches!(self.config.output_type, OutputType::StaticLibrary) {
+                    let mut args: Vec<OsString> = vec!["rcs".into()];
+                    if self.config.thin_archive {
+                        args.push("--thin".into());
+                    }
+                    args.push(output_path.into());
+                    args.extend(obj_paths.into_iter().map(|path| path.as_ref().as_os_str().to_owned()));
+                    return ("llvm-ar", args);
+                }
+                // Executables and shared libraries are linked by the compiler driver, which also links the C++ runtime
+                let mut args: Vec<OsString> = Vec::new();
+                if matches!(self.config.output_type, OutputType::DynamicLibrary) {
+                    args.push("-shared".into());
+                }
+                args.push("-o".into());
+                args.push(output_path.into());
+                args.extend(obj_paths.into_iter().map(|path| path.as_ref().as_os_str().to_owned()));
+                for path in self.library_paths.iter().chain(&self.toolchain_paths.lib_paths) {
+                    args.push(cmd_flag("-L", path));
+                }
+                // Libraries named with `-l` are passed through as-is, like paths
+                args.extend(self.link_libraries.iter().chain(&self.dependency_libraries).map(|path| path.as_os_str().to_owned()));
+                args.extend(self.extra_link_flags.iter().map(OsString::from));
+                return ("clang++", args);
             }
         };
         args.push(
@@ -670,16 +2095,664 @@ impl<'a> BuildEnvironment<'a> {
         for path in obj_paths {
             args.push(path.as_ref().as_os_str().to_owned());
         }
-        let res = if matches!(self.config.output_type, OutputType::StaticLibrary) {
-            run_cmd("lib.exe", &args, &self.toolchain_paths.bin_paths, BuildError::LinkerError)?;
-            Ok(output_path.exists())
+        if matches!(self.config.output_type, OutputType::StaticLibrary) {
+            ("lib.exe", args)
         } else {
-            for path in &self.config.link_libraries {
-                args.push(path.into());
+            for path in self.link_libraries.iter().chain(&self.dependency_libraries) {
+                args.push(path.as_os_str().to_owned());
             }
-            run_cmd("link.exe", &args, &self.toolchain_paths.bin_paths, BuildError::LinkerError)?;
+            ("link.exe", args)
+        }
+    }
+
+    pub fn link(
+        &mut self,
+        output_path: impl AsRef<Path>,
+        obj_paths: impl IntoIterator<Item=impl AsRef<Path>>,
+    ) -> Result<bool, BuildError> {
+        let progress_bar = ProgressBar::new_spinner()
+            .with_message(format!("Linking {}", output_path.as_ref().to_string_lossy()));
+        progress_bar.enable_steady_tick(50);
+
+        let output_path = output_path.as_ref();
+        let obj_paths: Vec<PathBuf> = obj_paths.into_iter().map(|path| path.as_ref().to_owned()).collect();
+        let (linker, args) = self.link_command(output_path, &obj_paths);
+        if !matches!(self.config.output_type, OutputType::StaticLibrary) {
+            run_cmd(linker, &args, &self.toolchain_paths.bin_paths, BuildError::LinkerError)?;
+            return Ok(true);
+        }
+
+        // Archiving every object is slow for large libraries, so if only some of the objects have changed since the
+        // last time, just replace their members in the existing archive
+        let inputs_path = self.artifact_path.join("archive_inputs");
+        let inputs_hash = format!("{:016x}", args_hash(&args));
+        let changed_obj_paths = self.changed_archive_members(output_path, &obj_paths, &inputs_path, &inputs_hash);
+        // If archiving fails, the archive may no longer match the recorded inputs
+        let _ = fs::remove_file(&inputs_path);
+        match changed_obj_paths {
+            Some(changed_obj_paths) => {
+                let (archiver, mut args) = self.link_command(output_path, &changed_obj_paths);
+                if matches!(Platform::host().os(), Os::Windows) {
+                    // lib.exe replaces the members of an input library with the objects of the same names
+                    args.push(output_path.into());
+                }
+                run_cmd(archiver, &args, &self.toolchain_paths.bin_paths, BuildError::LinkerError)?;
+            },
+            None => {
+                if matches!(self.toolchain_paths.vendor, Vendor::Clang) {
+                    // llvm-ar adds to an existing archive, which would keep the objects of deleted sources
+                    let _ = fs::remove_file(output_path);
+                }
+                run_cmd(linker, &args, &self.toolchain_paths.bin_paths, BuildError::LinkerError)?;
+            },
+        }
+        if output_path.exists() {
+            // Failing to record the inputs only means that the next change is archived from scratch
+            let _ = fs::write(&inputs_path, inputs_hash);
             Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// The objects that have changed since the static library at `archive_path` was last built, if it can be brought
+    /// up to date by replacing just their members. That's only the case if it was built with the same command, apart
+    /// from the contents of the objects (so, e.g., no sources have been added or removed).
+    fn changed_archive_members(&self, archive_path: &Path, obj_paths: &[PathBuf], inputs_path: &Path, inputs_hash: &str) -> Option<Vec<PathBuf>> {
+        // Replacing members isn't guaranteed to produce exactly the same archive as building it from scratch
+        if self.config.reproducible || fs::read_to_string(inputs_path).ok().as_deref() != Some(inputs_hash) {
+            return None;
+        }
+        let archive_edit_time = FileTime::from_last_modification_time(&fs::metadata(archive_path).ok()?);
+        let changed_obj_paths: Vec<PathBuf> = obj_paths.iter()
+            .filter(|path| {
+                fs::metadata(path)
+                    .map_or(true, |metadata| FileTime::from_last_modification_time(&metadata) > archive_edit_time)
+            })
+            .cloned()
+            .collect();
+        // Otherwise, something other than the objects has changed, so start from scratch
+        Some(changed_obj_paths).filter(|changed_obj_paths| !changed_obj_paths.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_options, config, set_age, TempDir, TestProject};
+
+    #[test]
+    fn flattened_artifacts_of_dotted_stems_dont_collide() {
+        let mut config = config("app");
+        config.flatten_artifacts = true;
+        let project_path = Path::new("/project");
+        let objs_path = Path::new("/project/abs/debug/app/Win64/objs");
+        let a = src_artifact_path(&config, project_path, Path::new("/project/src/a/x.test.cpp"), objs_path, "obj");
+        let b = src_artifact_path(&config, project_path, Path::new("/project/src/b/x.test.cpp"), objs_path, "obj");
+        assert_ne!(a, b);
+        for path in [&a, &b] {
+            assert_eq!(path.parent(), Some(objs_path));
+            let file_name = path.file_name().unwrap().to_str().unwrap();
+            assert!(file_name.starts_with("x.test-"), "{}", file_name);
+            assert!(file_name.ends_with(".obj"), "{}", file_name);
+        }
+    }
+
+    #[test]
+    fn flattened_artifacts_ignore_the_src_extension() {
+        let mut config = config("app");
+        config.flatten_artifacts = true;
+        let project_path = Path::new("/project");
+        let header = src_artifact_path(&config, project_path, Path::new("/project/src/pch.h"), Path::new("/objs"), "pch");
+        let source = src_artifact_path(&config, project_path, Path::new("/project/src/pch.cpp"), Path::new("/objs"), "pch");
+        assert_eq!(header, source);
+    }
+
+    #[test]
+    fn orphaned_source_artifacts_are_removed() {
+        let dir = TempDir::new();
+        let config = config("app");
+        let project_path = dir.path();
+        let intermediates_path = dir.path().join("abs/debug/app/Win64");
+        let live = dir.write("src/live.cpp", "");
+        let asm = dir.write("src/math.asm", "");
+        let live_obj = dir.write("abs/debug/app/Win64/obj/live.obj", "0123");
+        let asm_obj = dir.write("abs/debug/app/Win64/obj/math.asm.obj", "0123");
+        let live_deps = dir.write("abs/debug/app/Win64/src_deps/live.json", "{}");
+        let orphan_obj = dir.write("abs/debug/app/Win64/obj/nested/deleted.obj", "012345");
+        let orphan_warnings = dir.write("abs/debug/app/Win64/warning_cache/deleted.warnings", "[]");
+        // Files with extensions that aren't source artifacts are left alone
+        let pdb = dir.write("abs/debug/app/Win64/obj/app.pdb", "");
+
+        let removed = remove_orphaned_source_artifacts(&config, project_path, &intermediates_path, &[&live], &[&asm]).unwrap();
+        assert_eq!(removed, (2, 8));
+        assert!(!orphan_obj.exists());
+        assert!(!orphan_warnings.exists());
+        assert!(live_obj.exists());
+        assert!(asm_obj.exists());
+        assert!(live_deps.exists());
+        assert!(pdb.exists());
+    }
+
+    #[test]
+    fn missing_intermediates_have_nothing_to_remove() {
+        let dir = TempDir::new();
+        let removed = remove_orphaned_source_artifacts(&config("app"), dir.path(), &dir.path().join("abs"), &[], &[]).unwrap();
+        assert_eq!(removed, (0, 0));
+    }
+
+    #[test]
+    fn uac_execution_level_is_only_embedded_in_executables() {
+        let mut config = config("app");
+        assert_eq!(manifest_uac_flag(&config, false), None);
+        assert_eq!(manifest_uac_flag(&config, true), Some("/manifestuac:no"));
+
+        config.uac_execution_level = Some(UacExecutionLevel::RequireAdministrator);
+        assert_eq!(manifest_uac_flag(&config, false), Some("/manifestuac:level='requireAdministrator' uiAccess='false'"));
+        // The configured level takes precedence over that of custom manifests
+        assert_eq!(manifest_uac_flag(&config, true), Some("/manifestuac:level='requireAdministrator' uiAccess='false'"));
+        config.uac_execution_level = Some(UacExecutionLevel::Disabled);
+        assert_eq!(manifest_uac_flag(&config, false), Some("/manifestuac:no"));
+
+        config.uac_execution_level = Some(UacExecutionLevel::AsInvoker);
+        config.output_type = OutputType::DynamicLibrary;
+        assert_eq!(manifest_uac_flag(&config, false), None);
+    }
+
+    #[test]
+    fn build_errors_display_as_sentence_fragments() {
+        assert_eq!(BuildError::NoSrcDirectory.to_string(), "src directory does not exist");
+        assert_eq!(
+            BuildError::MissingExtraSource(PathBuf::from("gen/hello.cpp")).to_string(),
+            "extra source \"gen/hello.cpp\" does not exist",
+        );
+        let io_error = BuildError::from(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(io_error.to_string(), "there was an io error: NotFound");
+    }
+
+    #[test]
+    fn only_io_build_errors_have_a_source() {
+        use std::error::Error;
+        let io_error = BuildError::from(io::Error::from(io::ErrorKind::NotFound));
+        assert!(io_error.source().is_some());
+        assert!(BuildError::PackageError.source().is_none());
+    }
+
+    #[test]
+    fn assembly_sources_are_discovered_separately() {
+        let dir = TempDir::new();
+        let cpp = dir.write("src/math.cpp", "");
+        let asm = dir.write("src/math.asm", "");
+        let nested_asm = dir.write("src/nested/fast.asm", "");
+        dir.write("src/notes.txt", "");
+
+        let paths = SrcPaths::from_root(dir.path().join("src")).unwrap();
+        assert_eq!(paths.all_src_paths(), [cpp.as_path()]);
+        let mut asm_paths = paths.all_asm_paths();
+        asm_paths.sort();
+        assert_eq!(asm_paths, [asm.as_path(), nested_asm.as_path()]);
+    }
+
+    #[test]
+    fn assembly_objects_dont_collide_with_cxx_objects() {
+        let config = config("app");
+        let project_path = Path::new("/project");
+        let cxx_obj = src_artifact_path(&config, project_path, Path::new("/project/src/math.cpp"), Path::new("/objs"), "obj");
+        let asm_obj = src_artifact_path(&config, project_path, Path::new("/project/src/math.asm"), Path::new("/objs"), "asm.obj");
+        assert_eq!(cxx_obj, Path::new("/objs/math.obj"));
+        assert_eq!(asm_obj, Path::new("/objs/math.asm.obj"));
+    }
+
+    #[test]
+    fn changing_definitions_rebuilds() {
+        let project = TestProject::new("dependency");
+        let src_path = project.dir.write("src/lib.cpp", "");
+        let obj_path = project.artifact_path.join("obj/lib.obj");
+        let is_stale = |definitions: &[(&str, &str)]| {
+            project.env_with_definitions(definitions).should_build_artifact([&src_path], MissingDependency::Fail, &obj_path).unwrap()
         };
-        res
+        let build = || {
+            fs::write(&obj_path, "").unwrap();
+            project.make_fresh(&[&src_path]);
+            set_age(&[&obj_path], 50);
+        };
+
+        assert!(is_stale(&[("FOO", "1")]));
+        build();
+        assert!(!is_stale(&[("FOO", "1")]));
+        assert!(is_stale(&[("FOO", "2")]));
+        build();
+        assert!(!is_stale(&[("FOO", "2")]));
+        assert!(is_stale(&[("FOO", "2"), ("BAR", "1")]));
+    }
+
+    #[test]
+    fn library_index_finds_libraries_case_insensitively_in_search_order() {
+        let dir = TempDir::new();
+        let vendor = dir.write("vendor/Foo.lib", "");
+        let sdk = dir.write("sdk/user32.lib", "");
+        dir.write("sdk/foo.lib", "");
+        let (vendor_dir, sdk_dir, missing_dir) = (dir.path().join("vendor"), dir.path().join("sdk"), dir.path().join("missing"));
+        let index = LibraryIndex::build(&[&vendor_dir, &missing_dir, &sdk_dir]).unwrap();
+        assert_eq!(index.find(Path::new("FOO.lib")), Some(&vendor));
+        assert_eq!(index.find(Path::new("User32.Lib")), Some(&sdk));
+        // Otherwise, the build fails with BuildError::LibraryNotFound
+        assert_eq!(index.find(Path::new("usr32.lib")), None);
+    }
+
+    #[test]
+    fn runtime_libraries_are_copied_next_to_the_product() {
+        let mut project = TestProject::new("app");
+        // e.g., collected from a dependency
+        let dll = project.dir.write("dependency/vendor/foo.dll", "dll");
+        project.config.runtime_libraries = vec![dll];
+        let copied = project.artifact_path.join("foo.dll");
+        assert_eq!(project.env().copy_runtime_libraries().unwrap(), std::slice::from_ref(&copied));
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "dll");
+
+        project.config.output_type = OutputType::StaticLibrary;
+        assert_eq!(project.env().copy_runtime_libraries().unwrap(), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn dependency_libraries_are_linked_after_link_libraries() {
+        let mut project = TestProject::new("app");
+        // Prebuilt libraries may be referred to by path, which doesn't need a library index on any host
+        let prebuilt = project.dir.write("vendor/prebuilt.lib", "");
+        project.config.link_libraries = vec![prebuilt.clone()];
+        let mut env = project.env();
+        // Paths that aren't valid UTF-8 must reach the linker intact
+        #[cfg(unix)]
+        let parent = {
+            use std::os::unix::ffi::OsStrExt;
+            PathBuf::from(OsStr::from_bytes(b"non-utf8-\xff"))
+        };
+        #[cfg(not(unix))]
+        let parent = PathBuf::from("parent");
+        let dependency = project.dir.path().join(parent).join("dependency/abs/debug/dependency/Win64/dependency.lib");
+        env.dependency_libraries = vec![dependency.clone()];
+
+        let obj = project.artifact_path.join("obj/main.obj");
+        let (_, args) = env.link_command(project.artifact_path.join("app.exe"), [&obj]);
+        let position = |path: &Path| args.iter().position(|arg| arg == path.as_os_str());
+        let (obj, prebuilt, dependency) = (position(&obj).unwrap(), position(&prebuilt).unwrap(), position(&dependency).unwrap());
+        assert!(obj < prebuilt && prebuilt < dependency);
+    }
+
+    #[test]
+    fn include_paths_follow_precedence_and_placeholders() {
+        let mut project = TestProject::new("app");
+        let include_paths = |project: &TestProject| {
+            let env = project.env();
+            (env.include_paths(), env.src_dir_path.clone(), env.dependency_headers_path.clone())
+        };
+        let vendor = project.dir.path().join("vendor");
+
+        let (paths, src, dependencies) = include_paths(&project);
+        assert_eq!(paths, [dependencies.clone(), src.clone()]);
+
+        project.config.include_precedence = Some(IncludePrecedence::Project);
+        project.config.include_paths = vec!["vendor".into()];
+        assert_eq!(include_paths(&project).0, [src.clone(), dependencies.clone(), vendor.clone()]);
+
+        // Placeholders override include_precedence
+        project.config.include_paths = vec!["vendor".into(), "{dependencies}".into(), "{src}".into()];
+        assert_eq!(include_paths(&project).0, [vendor.clone(), dependencies.clone(), src.clone()]);
+
+        // A directory without a placeholder is still searched, last
+        project.config.include_paths = vec!["{dependencies}".into(), "vendor".into()];
+        assert_eq!(include_paths(&project).0, [dependencies, vendor, src]);
+    }
+
+    #[test]
+    fn library_index_is_cached_until_a_directory_changes() {
+        let dir = TempDir::new();
+        dir.write("vendor/foo.lib", "");
+        let vendor_dir = dir.path().join("vendor");
+        let cache_path = dir.path().join("library_index.json");
+        set_age(&[&vendor_dir], 100);
+        let index = LibraryIndex::load_or_build(&cache_path, &[&vendor_dir]).unwrap();
+        assert!(index.find(Path::new("foo.lib")).is_some());
+        assert!(cache_path.is_file());
+
+        // Restoring the directory's modification time hides the new library from the cached index
+        let bar = dir.write("vendor/bar.lib", "");
+        set_age(&[&vendor_dir], 100);
+        let index = LibraryIndex::load_or_build(&cache_path, &[&vendor_dir]).unwrap();
+        assert_eq!(index.find(Path::new("bar.lib")), None);
+
+        set_age(&[&vendor_dir], 50);
+        let index = LibraryIndex::load_or_build(&cache_path, &[&vendor_dir]).unwrap();
+        assert_eq!(index.find(Path::new("bar.lib")), Some(&bar));
+    }
+
+    #[test]
+    fn definitions_files_have_one_definition_per_line() {
+        let path = Path::new("defines.txt");
+        let contents = "# Comment\n\nFOO=1\n  BAR = a b  \nENABLED\nEMPTY=\n";
+        assert_eq!(parse_definitions(path, contents).unwrap(), [("FOO", "1"), ("BAR", "a b"), ("ENABLED", "1"), ("EMPTY", "")]);
+        assert!(matches!(parse_definitions(path, "FOO=1\nTWO WORDS=1\n"), Err(BuildError::InvalidDefinition(_, 2))));
+        assert!(matches!(parse_definitions(path, "=1"), Err(BuildError::InvalidDefinition(_, 1))));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn priming_copies_dependency_headers() {
+        let mut project = TestProject::new("app");
+        project.config.dependencies = vec!["dependency".into()];
+        let src_path = project.dir.write("src/main.cpp", "");
+        project.dir.write("dependency/src/dependency.h", "#pragma once");
+        project.dir.write("dependency/src/dependency.cpp", "");
+        let env = project.env();
+        // The source's dependencies are already known, so priming doesn't need a compiler to scan it
+        let src_deps_path = env.get_artifact_path(&src_path, &env.src_deps_path, "json");
+        fs::write(&src_deps_path, r#"{ "Version": "1.1", "Data": { "Includes": [] } }"#).unwrap();
+        project.make_fresh(&[&src_path]);
+        set_age(&[&src_deps_path], 50);
+
+        // Discovery and copying run via `block_in_place`, which is only allowed on the multi-threaded runtime
+        assert!(!env.prime().await.unwrap());
+        let headers_path = env.dependency_headers_path.join("dependency");
+        assert!(headers_path.join("dependency.h").is_file());
+        assert!(!headers_path.join("dependency.cpp").exists());
+    }
+
+    #[test]
+    fn pdbs_are_only_generated_for_linked_products_with_debug_info() {
+        let mut project = TestProject::new("app");
+        project.config.cxx_options.release_debug_info_format = Some(DebugInfoFormat::None);
+        let generates_pdb = |project: &mut TestProject, args: &[&str]| {
+            project.build_options = build_options(args);
+            project.env().generates_pdb()
+        };
+        assert!(generates_pdb(&mut project, &["debug"]));
+        assert!(!generates_pdb(&mut project, &["release"]));
+        assert!(!generates_pdb(&mut project, &["minsizerel"]));
+        assert!(generates_pdb(&mut project, &["relwithdebinfo"]));
+
+        project.config.cxx_options.debug_info_format = DebugInfoFormat::Embedded;
+        assert!(generates_pdb(&mut project, &["debug"]));
+        project.config.cxx_options.debug_info_format = DebugInfoFormat::None;
+        assert!(!generates_pdb(&mut project, &["debug"]));
+
+        project.config.cxx_options.debug_info_format = DebugInfoFormat::Pdb;
+        project.config.output_type = OutputType::StaticLibrary;
+        assert!(!generates_pdb(&mut project, &["debug"]));
+    }
+
+    #[test]
+    fn flags_are_split_at_unquoted_unescaped_whitespace() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("   ", &[]),
+            ("/wd4996  /DTRACE\t/O2 ", &["/wd4996", "/DTRACE", "/O2"]),
+            (r#"/I"C:\Program Files\lib" '/DNAME=a b'"#, &[r"/IC:\Program Files\lib", "/DNAME=a b"]),
+            (r#"/DQUOTE='"' "it's""#, &["/DQUOTE=\"", "it's"]),
+            (r"/IC:\My\ Libraries\include /DA", &[r"/IC:\My Libraries\include", "/DA"]),
+            (r#"/DMESSAGE=\"hi\""#, &[r#"/DMESSAGE="hi""#]),
+            // An empty quoted flag is still a flag
+            (r#"/DEMPTY= """#, &["/DEMPTY=", ""]),
+            // A trailing backslash escapes nothing
+            (r"C:\dir\", &[r"C:\dir\"]),
+        ];
+        for &(value, flags) in cases {
+            assert_eq!(split_flags(value), flags, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn sources_built_to_the_same_artifact_are_reported() {
+        let project = TestProject::new("app");
+        project.dir.write("src/main.cpp", "");
+        // Assembly objects have their own extension
+        project.dir.write("src/main.asm", "");
+        let env = project.env();
+        assert!(env.discover_sources().is_ok());
+
+        project.dir.write("src/main.cc", "");
+        match env.discover_sources() {
+            Err(BuildError::ArtifactPathCollision(first, second, artifact)) => {
+                let mut sources = [first, second];
+                sources.sort();
+                assert_eq!(sources, [env.src_dir_path.join("main.cc"), env.src_dir_path.join("main.cpp")]);
+                assert_eq!(artifact.file_stem(), Some(OsStr::new("main")));
+            },
+            result => panic!("expected a collision, but got {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn missing_dependencies_fail_or_rebuild_as_requested() {
+        let project = TestProject::new("app");
+        let src_path = project.dir.write("src/main.cpp", "");
+        let header_path = project.dir.path().join("src/generated.h");
+        let obj_path = project.artifact_path.join("main.obj");
+        let should_build = |dependencies: &[&Path], missing| project.env().should_build_artifact(dependencies, missing, &obj_path);
+
+        // A missing artifact is always out of date
+        assert!(should_build(&[&src_path], MissingDependency::Fail).unwrap());
+        fs::write(&obj_path, "").unwrap();
+        project.make_fresh(&[&src_path]);
+        set_age(&[&obj_path], 50);
+        assert!(!should_build(&[&src_path], MissingDependency::Fail).unwrap());
+        assert!(!should_build(&[&src_path], MissingDependency::Rebuild).unwrap());
+
+        // A required dependency is an error, rather than a reason to rebuild forever
+        assert!(matches!(should_build(&[&src_path, &header_path], MissingDependency::Fail), Err(BuildError::MissingDependency(path)) if path == header_path));
+        // A recorded dependency that has since been removed rebuilds the artifact
+        assert!(should_build(&[&src_path, &header_path], MissingDependency::Rebuild).unwrap());
+        // Once the artifact is rebuilt, the removed dependency is no longer recorded
+        assert!(!should_build(&[&src_path], MissingDependency::Rebuild).unwrap());
+
+        // Newer dependencies rebuild the artifact, whether or not they're required
+        set_age(&[&src_path], 10);
+        assert!(should_build(&[&src_path], MissingDependency::Fail).unwrap());
+    }
+
+    #[test]
+    fn only_changed_objects_are_rearchived() {
+        let mut project = TestProject::new("dependency");
+        project.config.output_type = OutputType::StaticLibrary;
+        let env = project.env();
+        let a = project.dir.write("abs/debug/dependency/Win64/obj/a.obj", "");
+        let b = project.dir.write("abs/debug/dependency/Win64/obj/b.obj", "");
+        let archive_path = project.dir.write("abs/debug/dependency/Win64/dependency.lib", "");
+        let inputs_path = project.artifact_path.join("archive_inputs");
+        let inputs_hash = |env: &BuildEnvironment, obj_paths: &[PathBuf]| format!("{:016x}", args_hash(&env.link_command(&archive_path, obj_paths).1));
+        let all_objs = [a.clone(), b.clone()];
+        fs::write(&inputs_path, inputs_hash(&env, &all_objs)).unwrap();
+        set_age(&[&a, &b], 100);
+        set_age(&[&archive_path], 50);
+
+        // Nothing to replace, so the archive is rebuilt (if it needs to be at all) from scratch
+        assert_eq!(env.changed_archive_members(&archive_path, &all_objs, &inputs_path, &inputs_hash(&env, &all_objs)), None);
+        set_age(&[&a], 10);
+        assert_eq!(env.changed_archive_members(&archive_path, &all_objs, &inputs_path, &inputs_hash(&env, &all_objs)), Some(vec![a.clone()]));
+        // Removing a source changes the archive command, so its member must be dropped by starting from scratch
+        assert_eq!(env.changed_archive_members(&archive_path, &all_objs[..1], &inputs_path, &inputs_hash(&env, &all_objs[..1])), None);
+        fs::remove_file(&archive_path).unwrap();
+        assert_eq!(env.changed_archive_members(&archive_path, &all_objs, &inputs_path, &inputs_hash(&env, &all_objs)), None);
+        drop(env);
+
+        // Replacing members isn't reproducible
+        let archive_path = project.dir.write("abs/debug/dependency/Win64/dependency.lib", "");
+        set_age(&[&archive_path], 50);
+        project.config.reproducible = true;
+        let env = project.env();
+        assert_eq!(env.changed_archive_members(&archive_path, &all_objs, &inputs_path, &inputs_hash(&env, &all_objs)), None);
+    }
+
+    #[test]
+    fn link_failures_report_the_exit_code() {
+        #[cfg(windows)]
+        let (shell, args) = (std::env::var("ComSpec").unwrap(), ["/C", "exit 101"]);
+        #[cfg(not(windows))]
+        let (shell, args) = ("/bin/sh", ["-c", "exit 101"]);
+        let error = run_cmd(shell, args, &[], BuildError::LinkerError).unwrap_err();
+        assert_eq!(error.to_string(), "unable to link (linker exited with code 101)");
+
+        // e.g., an access violation
+        assert_eq!(BuildError::CompilerError(Some(0xC0000005u32 as i32)).to_string(), "unable to compile (compiler exited with code 0xC0000005)");
+        assert_eq!(BuildError::LinkerError(None).to_string(), "unable to link");
+    }
+
+    #[test]
+    fn primed_sources_dont_need_scanning() {
+        let project = TestProject::new("app");
+        let main = project.dir.write("src/main.cpp", "#include \"util.h\"");
+        let util = project.dir.write("src/util/util.cpp", "");
+        let header = project.dir.write("src/util.h", "");
+        let paths = SrcPaths::from_root(project.dir.path().join("src")).unwrap();
+        // Edit times are cached for the life of the environment, so each check needs a new one, as each build would
+        let mut to_scan = project.env().sources_to_scan(&paths).unwrap();
+        to_scan.sort();
+        assert_eq!(to_scan, [main.as_path(), util.as_path()]);
+
+        // What scanning each source with /sourceDependencies records
+        let scanned = project.env();
+        for (src_path, includes) in [(&main, vec![&header]), (&util, vec![])] {
+            let src_deps_path = scanned.get_artifact_path(src_path, &scanned.src_deps_path, "json");
+            fs::create_dir_all(src_deps_path.parent().unwrap()).unwrap();
+            fs::write(&src_deps_path, serde_json::json!({ "Version": "1.1", "Data": { "Includes": includes } }).to_string()).unwrap();
+            set_age(&[&src_deps_path], 50);
+        }
+        project.make_fresh(&[&main, &util, &header]);
+        assert_eq!(project.env().sources_to_scan(&paths).unwrap(), Vec::<&Path>::new());
+        // So the next build knows each source's headers before compiling it
+        assert_eq!(project.env().discover_src_deps(&main).unwrap(), Some(vec![header.clone()]));
+
+        set_age(&[&util], 10);
+        assert_eq!(project.env().sources_to_scan(&paths).unwrap(), [util.as_path()]);
+    }
+
+    #[test]
+    fn locked_outputs_are_retried_a_few_times() {
+        let locked = || io::Error::from(io::ErrorKind::PermissionDenied);
+        let mut attempts = 0;
+        let result = retry_output_io("app.pdb", || {
+            attempts += 1;
+            if attempts < 3 { Err(locked()) } else { Ok(attempts) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        attempts = 0;
+        let result = retry_output_io("app.pdb", || -> io::Result<()> {
+            attempts += 1;
+            Err(locked())
+        });
+        assert!(matches!(result, Err(BuildError::OutputPermissionDenied(path)) if path == Path::new("app.pdb")));
+        assert_eq!(attempts, 5);
+
+        // Only locks are worth waiting out
+        attempts = 0;
+        let result = retry_output_io("app.pdb", || -> io::Result<()> {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(matches!(result, Err(BuildError::IoError(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn locked_outputs_are_retried_without_blocking() {
+        let mut attempts = 0;
+        let result = retry_output_io_async("app.obj.warnings", || {
+            attempts += 1;
+            if attempts < 2 { Err(io::Error::from(io::ErrorKind::PermissionDenied)) } else { Ok(()) }
+        }).await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn only_scratch_directories_marked_for_the_artifact_directory_are_used() {
+        let dir = TempDir::new();
+        let artifact_path = dir.path().join("abs/debug/app/Win64");
+        let other_artifact_path = dir.path().join("abs/debug/other/Win64");
+        fs::create_dir_all(&artifact_path).unwrap();
+        fs::create_dir_all(&other_artifact_path).unwrap();
+        assert_eq!(intermediates_path(&artifact_path), artifact_path);
+
+        // e.g., a stale or edited record that names a directory abs didn't create, which mustn't be cleaned
+        let scratch_path = dir.path().join("scratch/app-0123456789abcdef/abs/debug/app/Win64");
+        fs::create_dir_all(&scratch_path).unwrap();
+        fs::write(artifact_path.join(SCRATCH_PATH_FILE), scratch_path.to_string_lossy().as_bytes()).unwrap();
+        assert_eq!(intermediates_path(&artifact_path), artifact_path);
+        let marker_path = scratch_path.join(SCRATCH_MARKER_FILE);
+        fs::write(&marker_path, other_artifact_path.to_string_lossy().as_bytes()).unwrap();
+        assert_eq!(intermediates_path(&artifact_path), artifact_path);
+
+        fs::write(&marker_path, artifact_path.to_string_lossy().as_bytes()).unwrap();
+        assert_eq!(intermediates_path(&artifact_path), scratch_path);
+    }
+
+    #[test]
+    fn msvc_path_arguments_round_trip_unchanged() {
+        let mut project = TestProject::new("app");
+        let root = project.dir.path().join("My Prôject");
+        project.config_path = project.dir.write("My Prôject/abs.json", "{}");
+        project.artifact_path = root.join("abs/debug/app/Win64");
+        project.config.library_paths = vec!["vendor/lib".into()];
+        let src_path = project.dir.write("My Prôject/src/main.cpp", "");
+        let mut env = project.env();
+        let dependency = root.join("dependency/abs/debug/dependency/Win64/dependency.lib");
+        env.dependency_libraries = vec![dependency.clone()];
+        let flag = |flag: &str, path: &Path| {
+            let mut flag = OsString::from(flag);
+            flag.push(path);
+            flag
+        };
+
+        let (flags, obj_path) = CxxTask::compile_flags_on(Os::Windows, &env, &src_path, PchOption::NoPch).unwrap();
+        assert_eq!(obj_path, project.artifact_path.join("obj/main.obj"));
+        assert!(flags.build().contains(&flag("/Fo", &obj_path)));
+
+        let output_path = project.artifact_path.join("app.exe");
+        let (_, args) = env.link_command_on(Os::Windows, &output_path, [&obj_path]);
+        assert!(args.contains(&flag("/out:", &output_path)));
+        assert!(args.contains(&flag("/LIBPATH:", &root.join("vendor/lib"))));
+        assert!(args.contains(&obj_path.into_os_string()));
+        assert!(args.contains(&dependency.into_os_string()));
+    }
+
+    #[test]
+    fn reproducible_links_only_name_pdbs_that_are_generated() {
+        let mut project = TestProject::new("app");
+        project.config.reproducible = true;
+        let pdb_alt_paths = |project: &TestProject| {
+            let (_, args) = project.env().link_command_on(Os::Windows, project.artifact_path.join("app.exe"), Vec::<PathBuf>::new());
+            args.iter().filter(|arg| *arg == "/PDBALTPATH:%_PDB%").count()
+        };
+        assert_eq!(pdb_alt_paths(&project), 1);
+        project.config.strip_pdb_path = true;
+        assert_eq!(pdb_alt_paths(&project), 1);
+
+        project.config.cxx_options.debug_info_format = DebugInfoFormat::None;
+        assert_eq!(pdb_alt_paths(&project), 0);
+    }
+
+    #[test]
+    fn dependency_libraries_must_be_built_in_the_same_mode() {
+        let mut app = TestProject::new("app");
+        app.build_options = build_options(&["release"]);
+        let mut dependency = TestProject::new("dependency");
+        dependency.config.output_type = OutputType::StaticLibrary;
+        let check_modes = |dependency: &TestProject| {
+            let mut env = app.env();
+            env.dependency_libraries = vec![dependency.artifact_path.join("dependency.lib")];
+            env.check_dependency_library_modes()
+        };
+
+        // Setting up the dependency's build records its mode in its artifact directory, wherever that is
+        dependency.env();
+        assert!(matches!(check_modes(&dependency), Err(BuildError::DependencyModeMismatch(_, "release"))));
+        dependency.build_options = build_options(&["release"]);
+        dependency.env();
+        assert!(check_modes(&dependency).is_ok());
+
+        // A library built before the mode was recorded can't be checked
+        dependency.build_options = build_options(&["debug"]);
+        dependency.env();
+        fs::remove_file(dependency.artifact_path.join(BUILD_MODE_FILE)).unwrap();
+        assert!(check_modes(&dependency).is_ok());
     }
 }
@@ -4,14 +4,16 @@
 use std::ffi::{OsStr, OsString};
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::time::Duration;
 
 use tokio::process::Command;
 use tokio::io::{BufReader, AsyncBufReadExt};
 use tokio::sync::mpsc;
 use tokio::task;
+use tokio::time;
 
 use crate::toolchain_paths::{ToolchainPaths, Vendor};
-use crate::proj_config::CxxStandard;
+use crate::proj_config::{CxxStandard, DebugInfoFormat};
 
 #[derive(Debug)]
 pub enum OutputLine {
@@ -19,57 +21,146 @@ pub enum OutputLine {
     Stderr(String),
 }
 
-pub async fn run_cmd(name: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr>>, bin_paths: &[PathBuf], output_channel: mpsc::UnboundedSender<OutputLine>) -> bool {
+/// How a command run by `run_cmd` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmdResult {
+    Succeeded,
+    /// The command failed, with the exit code it returned, if any (there is none if it couldn't be started)
+    Failed(Option<i32>),
+    /// The command ran for longer than its timeout, and was killed
+    TimedOut,
+}
+
+pub async fn run_cmd(name: impl AsRef<OsStr>, args: impl IntoIterator<Item=impl AsRef<OsStr>>, bin_paths: &[PathBuf], timeout: Option<Duration>, output_channel: mpsc::UnboundedSender<OutputLine>) -> CmdResult {
+    let name = name.as_ref();
     let mut path = if let Some(path) = std::env::var_os("PATH") {
         path
     } else {
-        return false
+        let _ = output_channel.send(OutputLine::Stderr("Unable to read the PATH environment variable".to_string()));
+        return CmdResult::Failed(None)
     };
-    for i in 0..bin_paths.len() {
-        if cfg!(os = "windows") {
+    for bin_path in bin_paths {
+        if cfg!(target_os = "windows") {
             path.push(";");
         } else {
             path.push(":");
         }
-        path.push(bin_paths[i].as_os_str());
+        path.push(bin_path.as_os_str());
     }
     let child = Command::new(name)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .args(args)
         .env("PATH", path)
+        // Otherwise, a command whose future is dropped (e.g., because of `--max-errors`) would keep running
+        .kill_on_drop(true)
         .spawn();
 
     let mut child = match child {
         Ok(child) => child,
-        Err(_) => return false,
+        Err(error) => {
+            let _ = output_channel.send(OutputLine::Stderr(format!("Unable to run {}: {}", name.to_string_lossy(), error)));
+            return CmdResult::Failed(None)
+        },
     };
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
 
-    let output_channel_copy = output_channel.clone();
-    let stdout_reader = task::spawn(async move {
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
-
-        while let Ok(Some(line)) = lines.next_line().await {
-            let _ = output_channel_copy.send(OutputLine::Stdout(line));
+    // Both pipes are read by the same task, so that the lines of each stream are sent in the order they were written
+    let mut reader = task::spawn(async move {
+        let (mut stdout, mut stderr) = (BufReader::new(stdout), BufReader::new(stderr));
+        let (mut stdout_line, mut stderr_line) = (Vec::new(), Vec::new());
+        let (mut stdout_open, mut stderr_open) = (true, true);
+        while stdout_open || stderr_open {
+            tokio::select! {
+                result = stdout.read_until(b'\n', &mut stdout_line), if stdout_open => {
+                    stdout_open = send_output_line(result, &mut stdout_line, OutputLine::Stdout, &output_channel);
+                },
+                result = stderr.read_until(b'\n', &mut stderr_line), if stderr_open => {
+                    stderr_open = send_output_line(result, &mut stderr_line, OutputLine::Stderr, &output_channel);
+                },
+            }
         }
     });
 
-    let stderr_reader = task::spawn(async move {
-        let reader = BufReader::new(stderr);
-        let mut lines = reader.lines();
+    let completion = async {
+        let _ = (&mut reader).await;
+        child.wait().await
+    };
+    let status = match timeout {
+        Some(timeout) => match time::timeout(timeout, completion).await {
+            Ok(status) => status,
+            Err(_) => {
+                // Otherwise, the process would be left running after ABS exits
+                let _ = child.kill().await;
+                reader.abort();
+                return CmdResult::TimedOut;
+            },
+        },
+        None => completion.await,
+    };
+    match status {
+        Ok(status) if status.success() => CmdResult::Succeeded,
+        Ok(status) => CmdResult::Failed(status.code()),
+        Err(_) => CmdResult::Failed(None),
+    }
+}
 
-        while let Ok(Some(line)) = lines.next_line().await {
-            let _ = output_channel.send(OutputLine::Stderr(line));
-        }
-    });
+/// Sends the line that `read_until` finished reading into `line` (if any) to `output_channel`, and returns whether
+/// there's more to read. `read_until` keeps what it has read so far in `line` when it's cancelled by `select!`, so the
+/// last line of a stream may be finished by a call that reads nothing more.
+fn send_output_line(result: std::io::Result<usize>, line: &mut Vec<u8>, output_line: fn(String) -> OutputLine, output_channel: &mpsc::UnboundedSender<OutputLine>) -> bool {
+    if !line.is_empty() {
+        let _ = output_channel.send(output_line(decode_output_line(line)));
+        line.clear();
+    }
+    matches!(result, Ok(len) if len > 0)
+}
+
+/// Decodes a line of output from a command, without its line ending. Tools like cl.exe write their output in the
+/// console's code page rather than UTF-8, so on non-English systems, output that isn't valid UTF-8 is decoded from
+/// that code page instead.
+fn decode_output_line(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    match std::str::from_utf8(line) {
+        Ok(line) => line.to_string(),
+        Err(_) => decode_console_code_page(line)
+            .unwrap_or_else(|| String::from_utf8_lossy(line).into_owned()),
+    }
+}
 
-    let (_stdout, _stderr) = tokio::join!(stdout_reader, stderr_reader);
+#[cfg(target_os = "windows")]
+fn decode_console_code_page(bytes: &[u8]) -> Option<String> {
+    use std::convert::TryFrom;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetConsoleOutputCP() -> u32;
+        fn MultiByteToWideChar(code_page: u32, flags: u32, multi_byte: *const u8, multi_byte_len: i32, wide: *mut u16, wide_len: i32) -> i32;
+    }
+
+    let len = i32::try_from(bytes.len()).ok()?;
+    unsafe {
+        // Without a console, this returns 0, which is CP_ACP (the system's ANSI code page)
+        let code_page = GetConsoleOutputCP();
+        let wide_len = MultiByteToWideChar(code_page, 0, bytes.as_ptr(), len, std::ptr::null_mut(), 0);
+        if wide_len <= 0 {
+            return None;
+        }
+        let mut wide = vec![0u16; wide_len as usize];
+        let wide_len = MultiByteToWideChar(code_page, 0, bytes.as_ptr(), len, wide.as_mut_ptr(), wide_len);
+        if wide_len <= 0 {
+            return None;
+        }
+        wide.truncate(wide_len as usize);
+        Some(String::from_utf16_lossy(&wide))
+    }
+}
 
-    child.wait().await
-        .map(|code| code.success()).unwrap_or(false)
+#[cfg(not(target_os = "windows"))]
+fn decode_console_code_page(_bytes: &[u8]) -> Option<String> {
+    None
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -79,7 +170,16 @@ pub enum CompilerOutput {
     Error(String),
 }
 
-pub async fn compile_cxx(toolchain_paths: &ToolchainPaths, compile_flags: CompileFlags, output_channel: mpsc::UnboundedSender<CompilerOutput>) -> bool {
+/// The code of a compiler diagnostic (e.g., "C4996" for "foo.cpp(12): warning C4996: ..."), if it has one
+pub fn diagnostic_code(diagnostic: &str) -> Option<&str> {
+    let first_line = diagnostic.lines().next()?;
+    let (_, rest) = ["warning ", "error "].iter()
+        .find_map(|kind| first_line.split_once(&format!(": {}", kind)).or_else(|| first_line.split_once(&format!(": fatal {}", kind))))?;
+    let code = rest.split(|c: char| c == ':' || c.is_whitespace()).next()?;
+    Some(code).filter(|code| !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+pub async fn compile_cxx(toolchain_paths: &ToolchainPaths, compile_flags: CompileFlags, timeout: Option<Duration>, output_channel: mpsc::UnboundedSender<CompilerOutput>) -> CmdResult {
     let (output_tx, mut output_rx) = mpsc::unbounded_channel();
     task::spawn(async move {
         #[derive(Debug)]
@@ -107,48 +207,65 @@ pub async fn compile_cxx(toolchain_paths: &ToolchainPaths, compile_flags: Compil
             None
         }
         while let Some(line) = output_rx.recv().await {
-            if !cfg!(os = "windows") {
+            if !cfg!(target_os = "windows") {
                 let output = match line {
                     OutputLine::Stdout(line) => CompilerOutput::Error(format!("STDOUT: {}", line)),
                     OutputLine::Stderr(line) => CompilerOutput::Error(format!("STDERR: {}", line)),
                 };
                 let _ = output_channel.send(output);
             } else {
-                if let OutputLine::Stdout(line) = line {
-                    let output = match state {
-                        ParseState::NoFileName => {
-                            state = ParseState::Neutral;
-                            CompilerOutput::Begun { first_line: line }
-                        },
-                        ParseState::Neutral => {
-                            if let Some(transition) = state_transition(&line) {
-                                state = transition;
-                                chunk = line;
-                            } else {
-                                // Just keeping this around for now to catch unexpected types of input during development
-                                debug_assert!(false, "unexpected line format");
-                            }
-                            continue;
-                        },
-                        ParseState::InWarning | ParseState::InError => {
-                            if let Some(transition) = state_transition(&line) {
-                                let val = match state {
-                                    ParseState::InWarning => CompilerOutput::Warning(chunk),
-                                    ParseState::InError => CompilerOutput::Error(chunk),
-                                    _ => unreachable!("impossible state"),
-                                };
-                                chunk = line;
-                                state = transition;
-                                val
-                            } else {
-                                chunk.push('\n');
-                                chunk.push_str(&line);
-                                continue
-                            }
-                        },
-                    };
+                match line {
+                    OutputLine::Stdout(line) => {
+                        let output = match state {
+                            ParseState::NoFileName => {
+                                state = ParseState::Neutral;
+                                CompilerOutput::Begun { first_line: line }
+                            },
+                            ParseState::Neutral => {
+                                if let Some(transition) = state_transition(&line) {
+                                    state = transition;
+                                    chunk = line;
+                                } else {
+                                    // Just keeping this around for now to catch unexpected types of input during development
+                                    debug_assert!(false, "unexpected line format");
+                                }
+                                continue;
+                            },
+                            ParseState::InWarning | ParseState::InError => {
+                                if let Some(transition) = state_transition(&line) {
+                                    let val = match state {
+                                        ParseState::InWarning => CompilerOutput::Warning(chunk),
+                                        ParseState::InError => CompilerOutput::Error(chunk),
+                                        _ => unreachable!("impossible state"),
+                                    };
+                                    chunk = line;
+                                    state = transition;
+                                    val
+                                } else {
+                                    chunk.push('\n');
+                                    chunk.push_str(&line);
+                                    continue
+                                }
+                            },
+                        };
     
-                    let _ = output_channel.send(output);
+                        let _ = output_channel.send(output);
+                    },
+                    OutputLine::Stderr(line) => {
+                        // MSVC writes some diagnostics, like command line errors, to stderr. These are
+                        // always a single line, so they bypass the multi-line parsing above.
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        let is_warning = matches!(state_transition(&line), Some(ParseState::InWarning))
+                            || line.contains("Command line warning");
+                        let output = if is_warning {
+                            CompilerOutput::Warning(line)
+                        } else {
+                            CompilerOutput::Error(line)
+                        };
+                        let _ = output_channel.send(output);
+                    },
                 }
             }
         }
@@ -168,7 +285,7 @@ pub async fn compile_cxx(toolchain_paths: &ToolchainPaths, compile_flags: Compil
         Vendor::Msvc => "cl.exe",
         Vendor::Clang => "clang",
     };
-    run_cmd(compiler_name, compile_flags.build(), &toolchain_paths.bin_paths, output_tx).await
+    run_cmd(compiler_name, compile_flags.build(), &toolchain_paths.bin_paths, timeout, output_tx).await
 }
 
 pub enum CompileFlag {
@@ -176,6 +293,7 @@ pub enum CompileFlag {
     CxxStandard(CxxStandard),
     Rtti(bool),
     AsyncAwait(bool),
+    DebugInfoFormat(DebugInfoFormat),
     SrcPath(PathBuf),
     ObjPath(PathBuf),
     PchPath {
@@ -189,6 +307,14 @@ pub enum CompileFlag {
     IncludePath(PathBuf),
 }
 
+/// Combinations of flags that cl.exe rejects (often with a cryptic D8016 error) or that make no sense together. If
+/// any flag from the first group appears along with any flag from the second, the explanation applies.
+const CONFLICTING_FLAGS: &[(&[&str], &[&str], &str)] = &[
+    (&["/RTC1", "/RTCs", "/RTCu", "/RTCc"], &["/O1", "/O2", "/Ox"], "run-time error checks can't be combined with optimization"),
+    (&["/MDd", "/MTd"], &["/O1", "/O2", "/Ox"], "the debug C runtime isn't meant for optimized builds"),
+    (&["/MD", "/MT"], &["/MDd", "/MTd"], "only one C runtime may be used"),
+];
+
 #[must_use]
 pub struct CompileFlags {
     flags: Vec<CompileFlag>,
@@ -240,6 +366,10 @@ impl CompileFlags {
         self.pushing(CompileFlag::AsyncAwait(enabled))
     }
 
+    pub fn debug_info_format(self, format: DebugInfoFormat) -> Self {
+        self.pushing(CompileFlag::DebugInfoFormat(format))
+    }
+
     pub fn src_path(self, path: impl Into<PathBuf>) -> Self {
         self.pushing(CompileFlag::SrcPath(path.into()))
     }
@@ -268,7 +398,19 @@ impl CompileFlags {
         self.extending(paths.into_iter().map(|path| CompileFlag::IncludePath(path.into())))
     }
 
-    fn build(&self) -> Vec<OsString> {
+    /// Describes the first known-bad combination of flags, if any
+    pub fn conflict(&self) -> Option<String> {
+        let flags: Vec<String> = self.build().iter()
+            .map(|flag| flag.to_string_lossy().into_owned())
+            .collect();
+        let find = |group: &[&str]| flags.iter().find(|flag| group.contains(&flag.as_str()));
+        CONFLICTING_FLAGS.iter().find_map(|&(first, second, explanation)| {
+            let (first, second) = (find(first)?, find(second)?);
+            Some(format!("{} and {}: {}", first, second, explanation))
+        })
+    }
+
+    pub fn build(&self) -> Vec<OsString> {
         let mut flags = Vec::new();
         for flag in &self.flags {
             match *flag {
@@ -277,9 +419,10 @@ impl CompileFlags {
                     match standard {
                         CxxStandard::Cxx11 | CxxStandard::Cxx14 => flags.push("/std:c++14".into()),
                         CxxStandard::Cxx17 => flags.push("/std:c++17".into()),
-                        CxxStandard::Cxx20 => {
-                            flags.push("/std:c++latest".into());
-                        }
+                        CxxStandard::Cxx20 => flags.push("/std:c++20".into()),
+                        // MSVC only implements C++23 as a preview so far
+                        CxxStandard::Cxx23 => flags.push("/std:c++23preview".into()),
+                        CxxStandard::Latest => flags.push("/std:c++latest".into()),
                     }
                 },
                 CompileFlag::Rtti(enabled) => if enabled {
@@ -290,6 +433,15 @@ impl CompileFlags {
                 CompileFlag::AsyncAwait(enabled) => if enabled {
                     flags.push("/await".into());
                 },
+                CompileFlag::DebugInfoFormat(format) => match format {
+                    DebugInfoFormat::Pdb => {
+                        flags.push("/Zi".into());
+                        // Allow multiple compiler processes to write to the shared PDB
+                        flags.push("/FS".into());
+                    },
+                    DebugInfoFormat::Embedded => flags.push("/Z7".into()),
+                    DebugInfoFormat::None => {},
+                },
                 CompileFlag::SrcPath(ref path) => {
                     flags.push(path.into());
                 },
@@ -320,3 +472,50 @@ impl CompileFlags {
         flags
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_flags_are_described() {
+        let flags = CompileFlags::empty().singles(["/c", "/RTC1", "/MDd"]);
+        assert_eq!(flags.conflict(), None);
+        let flags = flags.single("/O2");
+        assert_eq!(flags.conflict().as_deref(), Some("/RTC1 and /O2: run-time error checks can't be combined with optimization"));
+        let flags = CompileFlags::empty().singles(["/MD", "/MTd"]);
+        assert_eq!(flags.conflict().as_deref(), Some("/MD and /MTd: only one C runtime may be used"));
+    }
+
+    #[test]
+    fn standards_map_to_msvc_flags() {
+        for (standard, flag) in [
+            (CxxStandard::Cxx11, "/std:c++14"),
+            (CxxStandard::Cxx14, "/std:c++14"),
+            (CxxStandard::Cxx17, "/std:c++17"),
+            (CxxStandard::Cxx20, "/std:c++20"),
+            (CxxStandard::Cxx23, "/std:c++23preview"),
+            (CxxStandard::Latest, "/std:c++latest"),
+        ] {
+            assert_eq!(CompileFlags::empty().cxx_standard(standard).build(), [OsString::from(flag)], "{:?}", standard);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn each_output_stream_is_sent_in_order() {
+        let script = "for i in 1 2 3 4 5; do echo out$i; echo err$i >&2; done; printf partial";
+        let (output_tx, mut output_rx) = mpsc::unbounded_channel();
+        let result = run_cmd("/bin/sh", ["-c", script], &[], None, output_tx).await;
+        assert_eq!(result, CmdResult::Succeeded);
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        while let Some(line) = output_rx.recv().await {
+            match line {
+                OutputLine::Stdout(line) => stdout.push(line),
+                OutputLine::Stderr(line) => stderr.push(line),
+            }
+        }
+        assert_eq!(stdout, ["out1", "out2", "out3", "out4", "out5", "partial"]);
+        assert_eq!(stderr, ["err1", "err2", "err3", "err4", "err5"]);
+    }
+}
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use clap::Parser;
 
-use super::proj_config::{Platform, OutputType};
+use super::proj_config::{Platform, OutputType, Subsystem};
 
 #[derive(Parser)]
 pub struct CmdOptions {
@@ -17,27 +17,312 @@ pub enum Subcommand {
 
         #[clap(short, long, default_value="console_app")]
         output_type: OutputType,
+
+        /// Don't link the libraries that are linked by default for the output type (e.g., user32.lib and
+        /// comctl32.lib for a GUI app)
+        #[clap(long)]
+        no_default_libraries: bool,
+
+        /// Don't link comctl32.lib or initialize common controls in a GUI app
+        #[clap(long)]
+        no_common_controls: bool,
+
+        /// An additional library to link. May be passed more than once
+        #[clap(long="link-library", value_name="LIBRARY", multiple_occurrences=true, number_of_values=1)]
+        link_libraries: Vec<String>,
+
+        /// A directory whose files are copied into the new project instead of the built-in sources, with `{name}`
+        /// replaced by the project name. If it contains an abs.json, that replaces the generated project file
+        #[clap(long, value_name="PATH")]
+        template: Option<PathBuf>,
     },
     Build(BuildOptions),
-    Run(BuildOptions),
+    Run(RunOptions),
     Debug(BuildOptions),
-    Clean,
+    /// Build the project, then zip the build product and its assets into abs/{mode}/{name}-{target}.zip
+    Package(PackageOptions),
+    /// Generate a build file for another build tool, which compiles and links the project the way `build` would
+    Generate(GenerateOptions),
+    /// Run include-what-you-use over the project's sources with the flags they're built with, and print its
+    /// suggested #include changes for each source
+    Iwyu(BuildOptions),
+    /// Discover sources, copy dependency headers, generate precompiled headers and record the headers each source
+    /// includes for the project and its dependencies, without building anything else. Useful for warming caches
+    /// before a build
+    Prime(BuildOptions),
+    /// Remove everything that has been built, or with any of the flags, just those caches of each project
+    Clean {
+        /// Remove object files and precompiled headers, so that every source is recompiled
+        #[clap(long)]
+        objects: bool,
+
+        /// Remove the cached warnings of each source
+        #[clap(long)]
+        warnings: bool,
+
+        /// Remove the recorded header dependencies of each source, so that every source is recompiled
+        #[clap(long)]
+        src_deps: bool,
+
+        /// Remove the cached index of the libraries in the library search paths
+        #[clap(long)]
+        library_index: bool,
+    },
+    /// Remove the artifacts that building the project would no longer use, such as those of deleted sources, former
+    /// dependencies and unsupported targets, and report the space reclaimed
+    Gc,
     Kill,
+    Info,
+    /// Format all sources and headers with clang-format, respecting the project's .clang-format file
+    Fmt {
+        /// Don't modify any files, but fail if any of them need to be formatted
+        #[clap(long)]
+        check: bool,
+    },
+    /// List the project's supported targets, and whether each can be run on the host
+    Targets {
+        /// Print the list as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Check the project file and its dependency graph for problems, without building
+    Validate {
+        /// Also print notes about things that aren't problems, but may be worth tidying up
+        #[clap(short, long)]
+        verbose: bool,
+    },
+    /// Print the toolchain that ABS finds for a target, for troubleshooting
+    Toolchain {
+        #[clap(short, long, default_value="host")]
+        target: RawTarget,
+    },
+    /// Explain a common MSVC compiler or linker error code (e.g., LNK2019)
+    Explain {
+        code: String,
+    },
+    /// Describe the inputs and outputs of each project in the dependency graph, without building
+    Describe {
+        #[clap(default_value="debug")]
+        compile_mode: CompileMode,
+
+        /// Either `text` or `json`
+        #[clap(long, default_value="text")]
+        format: DescribeFormat,
+    },
+    /// Print the include tree of a source file, as of the last time it was compiled, with the size of each header
+    Includes {
+        file: PathBuf,
+
+        #[clap(default_value="debug")]
+        compile_mode: CompileMode,
+    },
 }
 
-#[derive(Parser)]
+impl Subcommand {
+    /// The build options of subcommands that build the project
+    pub fn build_options_mut(&mut self) -> Option<&mut BuildOptions> {
+        match self {
+            Subcommand::Build(build_options) | Subcommand::Debug(build_options) | Subcommand::Iwyu(build_options) | Subcommand::Prime(build_options) |
+                Subcommand::Run(RunOptions { build_options, .. }) | Subcommand::Package(PackageOptions { build_options, .. }) | Subcommand::Generate(GenerateOptions { build_options, .. }) => Some(build_options),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Parser, Clone)]
 pub struct BuildOptions {
     #[clap(default_value="debug")]
     pub compile_mode: CompileMode,
 
-    #[clap(short, long, default_value="host")]
-    pub target: RawTarget,
+    /// Build in both debug and release mode, one after the other, instead of in a single mode
+    #[clap(long, conflicts_with="compile-mode")]
+    pub both: bool,
+
+    /// The target to build for: one of the project's supported targets, `all` or `host`. Defaults to the project's
+    /// `default_target` if it has one, and `host` otherwise
+    #[clap(short, long)]
+    pub target: Option<RawTarget>,
+
+    /// Warn about headers that have neither an include guard nor `#pragma once`
+    #[clap(long)]
+    pub lint_headers: bool,
+
+    /// Continue building independent projects after a project fails to build
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// Write a Makefile-style dependency (.d) file alongside each object file
+    #[clap(long)]
+    pub dep_files: bool,
+
+    /// Link an executable for the given subsystem (console or windows), regardless of its output type. E.g., a GUI
+    /// app linked for the console subsystem gets a console window for its output.
+    #[clap(long)]
+    pub subsystem: Option<Subsystem>,
+
+    /// With a clang-based toolchain, record where compile time goes for each source file, and merge the results into
+    /// a single time_trace.json file (viewable in chrome://tracing) in the artifact directory
+    #[clap(long)]
+    pub time_trace: bool,
+
+    /// After compiling each project, summarize its warnings by diagnostic code (e.g., "C4996: 12 occurrences across 4
+    /// files"), in addition to printing them as usual
+    #[clap(long)]
+    pub warning_summary: bool,
+
+    /// Read additional preprocessor definitions from a file, one `NAME=VALUE` (or just `NAME`, which defines it as 1)
+    /// per line. Blank lines and lines starting with `#` are ignored
+    #[clap(long, value_name="PATH")]
+    pub define_from_file: Option<PathBuf>,
+
+    /// Stop compiling a source file, and fail the build, if it takes longer than this many seconds
+    #[clap(long, value_name="SECONDS")]
+    pub compile_timeout: Option<u64>,
+
+    /// Stop compiling, and fail the build, once this many distinct errors have been reported, rather than waiting for
+    /// every source to finish
+    #[clap(long, value_name="N")]
+    pub max_errors: Option<usize>,
+
+    /// Build intermediate files (objects, compiler PDBs and caches) under this directory instead of the artifact
+    /// directory, e.g., to keep them on a faster disk. The build product is still written to the artifact directory.
+    /// Intermediate files are kept between builds, so the same directory should be passed each time
+    #[clap(long, value_name="PATH")]
+    pub scratch_dir: Option<PathBuf>,
+}
+
+impl BuildOptions {
+    /// The options for each mode to build in, which is both debug and release mode with `--both`. The artifacts of
+    /// each mode are kept separately, so the modes are built one after the other as if by separate invocations.
+    pub fn per_mode(&self) -> Vec<BuildOptions> {
+        if self.both {
+            [CompileMode::Debug, CompileMode::Release].iter()
+                .map(|&compile_mode| BuildOptions { compile_mode, ..self.clone() })
+                .collect()
+        } else {
+            vec![self.clone()]
+        }
+    }
+
+    /// The target to build for a project with the given `default_target`. An explicit `--target` takes precedence.
+    pub fn target(&self, default_target: Option<Platform>) -> Target {
+        match self.target {
+            Some(target) => Target::from(target),
+            None => default_target.map_or(Target::Host, Target::Platform),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct RunOptions {
+    #[clap(flatten)]
+    pub build_options: BuildOptions,
+
+    /// For a console app, wait for Enter to be pressed after the program exits, so that its output can be read
+    #[clap(long)]
+    pub pause: bool,
+
+    /// Wait for the program to exit even if it's a GUI app, and exit with its exit code
+    #[clap(long)]
+    pub wait: bool,
+
+    /// An environment variable to set for the program, as NAME=VALUE. Takes precedence over `run_env` in the project
+    /// file. May be passed more than once
+    #[clap(long, value_name="NAME=VALUE", multiple_occurrences=true, number_of_values=1)]
+    pub env: Vec<EnvVar>,
+}
+
+/// An environment variable to set for the program being run, given as `NAME=VALUE`
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok(EnvVar { name: name.to_string(), value: value.to_string() }),
+            _ => Err("expected NAME=VALUE"),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct PackageOptions {
+    #[clap(flatten)]
+    pub build_options: BuildOptions,
+
+    /// Exclude debug symbols (the .pdb file) from the package
+    #[clap(long)]
+    pub no_pdb: bool,
+}
+
+#[derive(Parser)]
+pub struct GenerateOptions {
+    /// The kind of build file to generate. Currently, only `ninja` is supported
+    pub generator: Generator,
+
+    #[clap(flatten)]
+    pub build_options: BuildOptions,
+}
+
+#[derive(Parser, Clone, Copy)]
+pub enum Generator {
+    Ninja,
+}
+
+impl FromStr for Generator {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ninja" => Ok(Generator::Ninja),
+            _ => Err("no match"),
+        }
+    }
 }
 
 #[derive(Parser, Clone, Copy)]
+pub enum DescribeFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for DescribeFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DescribeFormat::Text),
+            "json" => Ok(DescribeFormat::Json),
+            _ => Err("no match"),
+        }
+    }
+}
+
+#[derive(Parser, Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum CompileMode {
     Debug,
     Release,
+    RelWithDebInfo,
+    MinSizeRel,
+}
+
+impl CompileMode {
+    pub const ALL: [CompileMode; 4] = [
+        CompileMode::Debug,
+        CompileMode::Release,
+        CompileMode::RelWithDebInfo,
+        CompileMode::MinSizeRel,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CompileMode::Debug => "debug",
+            CompileMode::Release => "release",
+            CompileMode::RelWithDebInfo => "relwithdebinfo",
+            CompileMode::MinSizeRel => "minsizerel",
+        }
+    }
 }
 
 impl FromStr for CompileMode {
@@ -46,6 +331,8 @@ impl FromStr for CompileMode {
         match s {
             "debug" => Ok(CompileMode::Debug),
             "release" => Ok(CompileMode::Release),
+            "relwithdebinfo" => Ok(CompileMode::RelWithDebInfo),
+            "minsizerel" => Ok(CompileMode::MinSizeRel),
             _ => Err("no match"),
         }
     }
@@ -76,7 +363,7 @@ impl FromStr for RawTarget {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Target {
     Platform(Platform),
     All,
@@ -100,3 +387,52 @@ impl FromStr for OutputType {
         serde_json::from_str(&format!(r#""{}""#, s))
     }
 }
+
+impl FromStr for Subsystem {
+    type Err = serde_json::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(&format!(r#""{}""#, s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_options_of_building_subcommands() {
+        let define_from_file = |args: &[&str]| {
+            let mut options = CmdOptions::parse_from(std::iter::once("abs").chain(args.iter().copied()));
+            options.sub_command.build_options_mut().map(|build_options| build_options.define_from_file.clone())
+        };
+        for sub_command in ["build", "debug", "iwyu", "prime", "run", "package"] {
+            assert_eq!(define_from_file(&[sub_command, "--define-from-file", "defines.txt"]), Some(Some(PathBuf::from("defines.txt"))));
+        }
+        assert_eq!(define_from_file(&["generate", "ninja", "--define-from-file", "defines.txt"]), Some(Some(PathBuf::from("defines.txt"))));
+        assert_eq!(define_from_file(&["build"]), Some(None));
+        assert_eq!(define_from_file(&["clean"]), None);
+    }
+
+    #[test]
+    fn explicit_targets_take_precedence_over_the_default_target() {
+        let target = |args: &[&str], default_target| crate::test_util::build_options(args).target(default_target);
+        assert_eq!(target(&[], None), Target::Host);
+        assert_eq!(target(&[], Some(Platform::Win32)), Target::Platform(Platform::Win32));
+        assert_eq!(target(&["--target", "win64"], Some(Platform::Win32)), Target::Platform(Platform::Win64));
+        assert_eq!(target(&["--target", "host"], Some(Platform::Win32)), Target::Host);
+        assert_eq!(target(&["--target", "all"], Some(Platform::Win32)), Target::All);
+    }
+
+    #[test]
+    fn both_builds_debug_then_release() {
+        let modes = |args: &[&str]| crate::test_util::build_options(args).per_mode().iter()
+            .map(|build_options| build_options.compile_mode)
+            .collect::<Vec<_>>();
+        assert_eq!(modes(&[]), [CompileMode::Debug]);
+        assert_eq!(modes(&["release"]), [CompileMode::Release]);
+        assert_eq!(modes(&["--both"]), [CompileMode::Debug, CompileMode::Release]);
+        // The mode is chosen by `--both`, so it can't also be passed
+        let result = BuildOptions::try_parse_from(["build", "release", "--both"]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,94 @@
+// Descriptions of the inputs and outputs of projects, for build and caching systems that orchestrate ABS builds.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::build::{SrcPaths, src_artifact_path, read_src_deps, intermediates_path};
+use crate::cmd_options::CompileMode;
+use crate::proj_config::{ProjectConfig, OutputType, Platform, Os};
+
+#[derive(Serialize)]
+pub struct ProjectDescription {
+    pub name: String,
+    pub config_path: PathBuf,
+    pub output_type: OutputType,
+    /// The names of the projects this one depends on directly
+    pub dependencies: Vec<String>,
+    /// C++ and assembly sources, including extra sources
+    pub sources: Vec<PathBuf>,
+    pub headers: Vec<PathBuf>,
+    pub targets: Vec<TargetDescription>,
+}
+
+/// The outputs of building a project for a particular target
+#[derive(Serialize)]
+pub struct TargetDescription {
+    pub target: Platform,
+    pub artifact_path: PathBuf,
+    pub product_path: PathBuf,
+    pub objects: Vec<ObjectDescription>,
+}
+
+#[derive(Serialize)]
+pub struct ObjectDescription {
+    pub source: PathBuf,
+    pub object: PathBuf,
+    /// The headers the source included when it was last compiled, or `None` if it hasn't been compiled yet
+    pub includes: Option<Vec<PathBuf>>,
+}
+
+/// Describes the project at `config_path` as it would be built in `mode`, for each of its supported targets. Only
+/// reads files; in particular, header dependencies come from the previous build, if any.
+pub fn describe_project(config: &ProjectConfig, config_path: &Path, dependencies: Vec<String>, mode: CompileMode) -> io::Result<ProjectDescription> {
+    let project_path = config_path.parent().unwrap();
+    let paths = SrcPaths::from_root(project_path.join("src"))?;
+    let mut cxx_sources: Vec<PathBuf> = paths.all_src_paths().into_iter().map(Path::to_path_buf).collect();
+    cxx_sources.extend(config.extra_sources.iter().map(|path| project_path.join(path)));
+    let asm_sources: Vec<PathBuf> = paths.all_asm_paths().into_iter().map(Path::to_path_buf).collect();
+
+    let mut targets = Vec::new();
+    for &target in &config.supported_targets {
+        let artifact_path = config.artifact_path(mode.name(), target);
+        let intermediates_path = intermediates_path(&artifact_path);
+        let objs_path = intermediates_path.join("obj");
+        let src_deps_path = intermediates_path.join("src_deps");
+        let obj_extension = match target.os() {
+            Os::Windows => "obj",
+            Os::Linux => "o",
+        };
+        let mut objects = Vec::new();
+        for source in &cxx_sources {
+            let src_deps_json_path = src_artifact_path(config, project_path, source, &src_deps_path, "json");
+            objects.push(ObjectDescription {
+                source: source.clone(),
+                object: src_artifact_path(config, project_path, source, &objs_path, obj_extension),
+                includes: read_src_deps(&src_deps_json_path).ok(),
+            });
+        }
+        for source in &asm_sources {
+            objects.push(ObjectDescription {
+                source: source.clone(),
+                object: src_artifact_path(config, project_path, source, &objs_path, "asm.obj"),
+                includes: None,
+            });
+        }
+        targets.push(TargetDescription {
+            target,
+            product_path: artifact_path.join(config.product_name(mode.name(), target)),
+            artifact_path,
+            objects,
+        });
+    }
+
+    Ok(ProjectDescription {
+        name: config.name.clone(),
+        config_path: config_path.to_owned(),
+        output_type: config.output_type,
+        dependencies,
+        sources: cxx_sources.into_iter().chain(asm_sources).collect(),
+        headers: paths.all_header_paths().into_iter().map(Path::to_path_buf).collect(),
+        targets,
+    })
+}
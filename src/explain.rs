@@ -0,0 +1,111 @@
+// Explanations of common MSVC compiler and linker diagnostics, with advice specific to ABS projects.
+
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "C1010",
+        "Unexpected end of file while looking for precompiled header.\n\
+        ABS compiles every source file against the precompiled header when src/pch.cpp exists. Make sure each \
+        source file begins with `#include \"pch.h\"`, or remove src/pch.cpp to disable precompiled headers.",
+    ),
+    (
+        "C1083",
+        "Cannot open include file.\n\
+        Check the spelling and path of the #include. Headers are looked up relative to the project's src directory. \
+        Headers from a dependency are available as \"<dependency name>/<path relative to its src directory>\"; make \
+        sure the dependency is listed in the `dependencies` array of abs.json.",
+    ),
+    (
+        "C1128",
+        "Number of sections exceeded object file format limit.\n\
+        Template-heavy sources can exceed the default limit. ABS recompiles such a source with `/bigobj` \
+        automatically; add `\"big_obj\": true` to `cxx_options` in abs.json to use it from the start.",
+    ),
+    (
+        "C2011",
+        "Type redefinition.\n\
+        This usually means a header was included more than once without an include guard or `#pragma once`. Run \
+        `abs build --lint-headers` to find headers that lack one.",
+    ),
+    (
+        "C2039",
+        "Name is not a member of the class or namespace.\n\
+        Check the spelling, and make sure the header declaring the member is included. Some standard library \
+        members also require a newer `cxx_options.standard` in abs.json.",
+    ),
+    (
+        "C2065",
+        "Undeclared identifier.\n\
+        Check the spelling, and make sure the header declaring the identifier is included. If the identifier is a \
+        Win32 API, it may require a newer `windows_target_version` in abs.json.",
+    ),
+    (
+        "C2429",
+        "Language feature requires a compiler flag.\n\
+        The feature requires a newer C++ standard. Raise `cxx_options.standard` in abs.json (e.g., to \"c++20\").",
+    ),
+    (
+        "C7555",
+        "Use of designated initializers requires at least C++20.\n\
+        Set `cxx_options.standard` to \"c++20\" or newer in abs.json.",
+    ),
+    (
+        "LNK1104",
+        "Cannot open file.\n\
+        If the file is a library, make sure its name in `link_libraries` is spelled correctly. If the file is the \
+        build product itself, it may still be open in another program; try `abs kill` to close the debugger.",
+    ),
+    (
+        "LNK1112",
+        "Module machine type conflicts with the target machine type.\n\
+        An object or library was built for a different architecture than the current target. Make sure every \
+        dependency lists the current target in its `supported_targets`, and try `abs clean` if the problem persists.",
+    ),
+    (
+        "LNK1168",
+        "Cannot open the output file for writing.\n\
+        The build product is probably still running or loaded by a debugger. Close it (or run `abs kill`) and try \
+        again.",
+    ),
+    (
+        "LNK1201",
+        "Error writing to the program database (PDB).\n\
+        The PDB is probably locked by a debugger that is still attached. ABS moves a locked PDB aside before linking \
+        when it can; otherwise, detach or close the debugger (or run `abs kill`) and try again.",
+    ),
+    (
+        "LNK2001",
+        "Unresolved external symbol.\n\
+        A symbol was referenced but never defined. See LNK2019.",
+    ),
+    (
+        "LNK2005",
+        "Symbol already defined in another object.\n\
+        A function or variable is defined in more than one source file, often because it was defined in a header \
+        that is included in several places. Mark it `inline`, or move the definition into a single source file.",
+    ),
+    (
+        "LNK2019",
+        "Unresolved external symbol referenced in a function.\n\
+        The symbol is declared but its definition was not linked. If it comes from a system library, add the library \
+        (e.g., \"user32.lib\") to `link_libraries` in abs.json. If it comes from another ABS project, add that project \
+        to `dependencies`. Otherwise, make sure the source file defining it is in the src directory.",
+    ),
+    (
+        "LNK4098",
+        "Default library conflicts with use of other libraries.\n\
+        Objects were compiled against different C runtimes, usually because a prebuilt library was built in a \
+        different mode. Build all projects and libraries in the same mode (e.g., debug or release).",
+    ),
+];
+
+/// Looks up the explanation for an MSVC diagnostic code (e.g., "LNK2019"), ignoring case.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS.iter()
+        .find(|(known_code, _)| known_code.eq_ignore_ascii_case(code.trim()))
+        .map(|&(_, explanation)| explanation)
+}
+
+/// All codes for which an explanation is available
+pub fn known_codes() -> impl Iterator<Item=&'static str> {
+    EXPLANATIONS.iter().map(|&(code, _)| code)
+}
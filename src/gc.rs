@@ -0,0 +1,261 @@
+// Removal of artifacts that no build of the current projects would use (e.g., those of deleted sources, former
+// dependencies or dropped targets), which otherwise accumulate in the abs directory until it's cleaned. Also removal
+// of individual caches, for `abs clean`.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::build::{self, SrcPaths};
+use crate::cmd_options::CompileMode;
+use crate::proj_config::ProjectConfig;
+
+/// The number of files that were removed, and their total size in bytes
+#[derive(Default)]
+pub struct Reclaimed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    fn add(&mut self, (files, bytes): (u64, u64)) {
+        self.files += files;
+        self.bytes += bytes;
+    }
+}
+
+/// The directories directly inside `path`, or none if it doesn't exist
+fn subdirectories(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+fn remove_dir(path: &Path, reclaimed: &mut Reclaimed) -> io::Result<()> {
+    match crate::dir_usage(path) {
+        Ok(usage) => reclaimed.add(usage),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    }
+    fs::remove_dir_all(path)
+}
+
+/// Removes an artifact directory, along with its intermediate files if they were built under `--scratch-dir`
+fn remove_artifact_dir(artifact_path: &Path, reclaimed: &mut Reclaimed) -> io::Result<()> {
+    let intermediates_path = build::intermediates_path(artifact_path);
+    if intermediates_path != artifact_path {
+        remove_dir(&intermediates_path, reclaimed)?;
+    }
+    remove_dir(artifact_path, reclaimed)
+}
+
+/// Removes the files in an artifact directory that are still built to, but that the next build wouldn't use
+fn prune_artifact_dir(config: &ProjectConfig, config_path: &Path, artifact_path: &Path, reclaimed: &mut Reclaimed) -> io::Result<()> {
+    let project_path = config_path.parent().unwrap();
+    let intermediates_path = build::intermediates_path(artifact_path);
+
+    match SrcPaths::from_root(project_path.join("src")) {
+        Ok(paths) => {
+            let extra_sources: Vec<PathBuf> = config.extra_sources.iter()
+                .filter_map(|path| crate::canonicalize(project_path.join(path)).ok())
+                .collect();
+            let mut src_paths = paths.all_src_paths();
+            src_paths.extend(extra_sources.iter().map(PathBuf::as_path));
+            reclaimed.add(build::remove_orphaned_source_artifacts(config, project_path, &intermediates_path, &src_paths, &paths.all_asm_paths())?);
+        },
+        // Without its src directory (e.g., a dependency that hasn't been checked out), there's no telling which of
+        // the project's source artifacts are orphaned, so leave them be rather than fail the whole collection
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+        Err(error) => return Err(error),
+    }
+
+    // Headers are copied to a directory named after the directory of each dependency
+    let dependency_dir_names: HashSet<OsString> = config.dependencies.iter()
+        .filter_map(|path| crate::canonicalize(project_path.join(path)).ok())
+        .filter_map(|path| path.file_name().map(ToOwned::to_owned))
+        .collect();
+    for headers_path in subdirectories(&intermediates_path.join("dependency_headers"))? {
+        if !dependency_dir_names.contains(headers_path.file_name().unwrap()) {
+            remove_dir(&headers_path, reclaimed)?;
+        }
+    }
+
+    // PDBs that were moved aside because a debugger had them locked
+    for entry in fs::read_dir(artifact_path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.contains(".pdb.") && name.ends_with(".old") {
+            let size = entry.metadata()?.len();
+            // Still locked if this fails; the next build tries again
+            if fs::remove_file(entry.path()).is_ok() {
+                reclaimed.add((1, size));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes everything under abs/{mode} in `root_path` (the directory builds are run from) that building `projects`
+/// (each given as its config and canonical config path) wouldn't use: the artifact directories of other projects and
+/// of unsupported targets, and the artifacts of sources and dependencies that no longer exist.
+pub fn collect_garbage<'a>(root_path: &Path, projects: impl IntoIterator<Item=(&'a ProjectConfig, &'a Path)>) -> io::Result<Reclaimed> {
+    let projects: HashMap<&str, (&ProjectConfig, &Path)> = projects.into_iter()
+        .map(|(config, config_path)| (config.name.as_str(), (config, config_path)))
+        .collect();
+    let mut reclaimed = Reclaimed::default();
+    for mode in CompileMode::ALL {
+        let mode_path = root_path.join("abs").join(mode.name());
+        for project_dir in subdirectories(&mode_path)? {
+            let project = projects.get(&*project_dir.file_name().unwrap().to_string_lossy()).copied();
+            for artifact_path in subdirectories(&project_dir)? {
+                match project {
+                    Some((config, config_path)) if config.supported_targets.iter().any(|&target| artifact_path == root_path.join(config.artifact_path(mode.name(), target))) => {
+                        prune_artifact_dir(config, config_path, &artifact_path, &mut reclaimed)?;
+                    },
+                    _ => remove_artifact_dir(&artifact_path, &mut reclaimed)?,
+                }
+            }
+            if project.is_none() {
+                remove_dir(&project_dir, &mut reclaimed)?;
+            }
+        }
+    }
+    Ok(reclaimed)
+}
+
+/// A cache in each artifact directory that `abs clean` can remove on its own, leaving the rest of the build in place
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cache {
+    /// Object files and precompiled headers, so every source is recompiled
+    Objects,
+    /// The warnings of each source, which are reported again when it's up to date
+    Warnings,
+    /// The headers that each source includes, so every source is recompiled
+    SrcDeps,
+    /// The libraries found in the library search paths
+    LibraryIndex,
+}
+
+impl Cache {
+    pub fn description(self) -> &'static str {
+        match self {
+            Cache::Objects => "objects",
+            Cache::Warnings => "cached warnings",
+            Cache::SrcDeps => "recorded header dependencies",
+            Cache::LibraryIndex => "library index",
+        }
+    }
+
+    /// Where the cache is kept for the artifact directory `artifact_path`
+    fn path(self, artifact_path: &Path) -> PathBuf {
+        match self {
+            Cache::Objects => build::intermediates_path(artifact_path).join("obj"),
+            Cache::Warnings => build::intermediates_path(artifact_path).join("warning_cache"),
+            Cache::SrcDeps => build::intermediates_path(artifact_path).join("src_deps"),
+            Cache::LibraryIndex => artifact_path.join("library_index.json"),
+        }
+    }
+}
+
+/// Removes `cache` from every artifact directory under abs/{mode} in `root_path` (the directory builds are run from)
+pub fn remove_cache(root_path: &Path, cache: Cache) -> io::Result<Reclaimed> {
+    let mut reclaimed = Reclaimed::default();
+    for mode in CompileMode::ALL {
+        for project_dir in subdirectories(&root_path.join("abs").join(mode.name()))? {
+            for artifact_path in subdirectories(&project_dir)? {
+                let path = cache.path(&artifact_path);
+                match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => remove_dir(&path, &mut reclaimed)?,
+                    Ok(metadata) => {
+                        fs::remove_file(&path)?;
+                        reclaimed.add((1, metadata.len()));
+                    },
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+    }
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{config, TempDir};
+
+    #[test]
+    fn garbage_is_removed_and_live_artifacts_are_kept() {
+        let dir = TempDir::new();
+        let mut app = config("app");
+        app.dependencies = vec!["../dependency".into()];
+        let app_config_path = dir.write("app/abs.json", "{}");
+        dir.write("app/src/live.cpp", "");
+        let live_obj = dir.write("app/abs/debug/app/Win64/obj/live.obj", "0123");
+        let live_deps = dir.write("app/abs/debug/app/Win64/src_deps/live.json", "{}");
+        let orphaned_obj = dir.write("app/abs/debug/app/Win64/obj/deleted.obj", "012345");
+        let orphaned_deps = dir.write("app/abs/debug/app/Win64/src_deps/deleted.json", "{}");
+        let product = dir.write("app/abs/debug/app/Win64/app.exe", "");
+        let old_pdb = dir.write("app/abs/debug/app/Win64/app.pdb.1.old", "01234567");
+        // Win32 isn't a supported target of the app, and "former" is no longer a project at all
+        dir.write("app/abs/release/app/Win32/app.exe", "0");
+        dir.write("app/abs/release/former/Win64/former.lib", "0");
+        // A dependency whose src directory is missing, so whether its objects are orphaned can't be told
+        let dependency = config("dependency");
+        let dependency_config_path = dir.write("dependency/abs.json", "{}");
+        let dependency_obj = dir.write("app/abs/debug/dependency/Win64/obj/lib.obj", "");
+
+        let root_path = dir.path().join("app");
+        let reclaimed = collect_garbage(&root_path, [(&app, app_config_path.as_path()), (&dependency, dependency_config_path.as_path())]).unwrap();
+        for path in [&live_obj, &live_deps, &product, &dependency_obj] {
+            assert!(path.is_file(), "{:?} was removed", path);
+        }
+        for path in [&orphaned_obj, &orphaned_deps, &old_pdb] {
+            assert!(!path.exists(), "{:?} wasn't removed", path);
+        }
+        assert!(!root_path.join("abs/release/app/Win32").exists());
+        assert!(!root_path.join("abs/release/former").exists());
+        assert_eq!((reclaimed.files, reclaimed.bytes), (5, 6 + 2 + 8 + 1 + 1));
+    }
+
+    #[test]
+    fn caches_are_removed_independently() {
+        let dir = TempDir::new();
+        let obj = dir.write("abs/debug/app/Win64/obj/main.obj", "0123");
+        let pch = dir.write("abs/release/app/Win64/obj/pch.pch", "01");
+        let src_deps = dir.write("abs/debug/app/Win64/src_deps/main.json", "{}");
+        let warnings = dir.write("abs/debug/app/Win64/warning_cache/main.json", "[]");
+        let library_index = dir.write("abs/debug/app/Win64/library_index.json", "{}");
+        let product = dir.write("abs/debug/app/Win64/app.exe", "");
+
+        let reclaimed = remove_cache(dir.path(), Cache::Objects).unwrap();
+        assert_eq!((reclaimed.files, reclaimed.bytes), (2, 6));
+        assert!(!obj.exists() && !pch.exists());
+        for path in [&src_deps, &warnings, &library_index, &product] {
+            assert!(path.is_file(), "{:?} was removed", path);
+        }
+
+        let reclaimed = remove_cache(dir.path(), Cache::LibraryIndex).unwrap();
+        assert_eq!((reclaimed.files, reclaimed.bytes), (1, 2));
+        assert!(!library_index.exists());
+        for path in [&src_deps, &warnings, &product] {
+            assert!(path.is_file(), "{:?} was removed", path);
+        }
+
+        // Nothing left to remove
+        let reclaimed = remove_cache(dir.path(), Cache::Objects).unwrap();
+        assert_eq!((reclaimed.files, reclaimed.bytes), (0, 0));
+    }
+}
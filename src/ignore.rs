@@ -0,0 +1,198 @@
+// Support for .absignore files, which exclude files and directories from source discovery using the same syntax as
+// .gitignore files.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+struct Pattern {
+    glob: Vec<char>,
+    /// Re-includes paths matched by earlier patterns (`!pattern`)
+    negated: bool,
+    /// Only matches directories (`pattern/`)
+    dir_only: bool,
+    /// Matches against the whole path relative to the project root, rather than just the file name (i.e., the
+    /// pattern contains a slash other than a trailing one)
+    anchored: bool,
+}
+
+pub struct IgnoreRules {
+    /// The directory that the patterns are relative to
+    base: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Loads the rules from the .absignore file in `project_path`. If there is none, nothing is ignored.
+    pub fn load(project_path: &Path) -> io::Result<IgnoreRules> {
+        let contents = match fs::read_to_string(project_path.join(".absignore")) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+        Ok(IgnoreRules::parse(project_path, &contents))
+    }
+
+    fn parse(base: &Path, contents: &str) -> IgnoreRules {
+        let mut patterns = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let glob = if cfg!(target_os = "windows") { line.to_lowercase() } else { line.to_owned() };
+            patterns.push(Pattern { glob: glob.chars().collect(), negated, dir_only, anchored });
+        }
+        IgnoreRules { base: base.to_owned(), patterns }
+    }
+
+    /// Whether `path` (a file, or a directory if `is_dir`) should be skipped. As with .gitignore, the last pattern
+    /// that matches decides.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let relative_path = match path.strip_prefix(&self.base) {
+            Ok(relative_path) => relative_path,
+            Err(_) => return false,
+        };
+        let mut relative_path = relative_path.to_string_lossy().replace('\\', "/");
+        // Windows file names are case-insensitive, so patterns should be too
+        if cfg!(target_os = "windows") {
+            relative_path = relative_path.to_lowercase();
+        }
+        let relative_path: Vec<char> = relative_path.chars().collect();
+        let name_start = relative_path.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            let text = if pattern.anchored { &relative_path[..] } else { &relative_path[name_start..] };
+            if glob_matches(&pattern.glob, text) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Matches `text` against a glob, where `*` matches anything but a slash, `**` matches anything (including nothing,
+/// when followed by a slash), `?` matches any one character but a slash, and `[...]` matches one character in a set
+fn glob_matches(glob: &[char], text: &[char]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            // Zero or more whole directories
+            glob_matches(rest, text) ||
+                text.iter().enumerate()
+                    .filter(|&(_, &c)| c == '/')
+                    .any(|(i, _)| glob_matches(rest, &text[i + 1..]))
+        },
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_matches(rest, &text[i..])),
+        ['*', rest @ ..] => {
+            let segment_len = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=segment_len).any(|i| glob_matches(rest, &text[i..]))
+        },
+        ['?', rest @ ..] => matches!(text, [c, ..] if *c != '/') && glob_matches(rest, &text[1..]),
+        ['[', rest @ ..] => {
+            let end = match rest.iter().skip(1).position(|&c| c == ']') {
+                Some(end) => end + 1,
+                // Without a closing bracket, the bracket is literal
+                None => return matches!(text, ['[', ..]) && glob_matches(rest, &text[1..]),
+            };
+            let (class, rest) = (&rest[..end], &rest[end + 1..]);
+            let (negated, class) = match class {
+                ['!' | '^', class @ ..] => (true, class),
+                class => (false, class),
+            };
+            let c = match text.first() {
+                Some(&c) if c != '/' => c,
+                _ => return false,
+            };
+            let mut in_class = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    in_class |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    in_class |= class[i] == c;
+                    i += 1;
+                }
+            }
+            in_class != negated && glob_matches(rest, &text[1..])
+        },
+        ['\\', escaped, rest @ ..] => matches!(text, [c, ..] if c == escaped) && glob_matches(rest, &text[1..]),
+        [literal, rest @ ..] => matches!(text, [c, ..] if c == literal) && glob_matches(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, text: &str) -> bool {
+        let glob: Vec<char> = glob.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_matches(&glob, &text)
+    }
+
+    #[test]
+    fn globs_match_like_gitignore() {
+        let cases = [
+            ("*.cpp", "main.cpp", true),
+            ("*.cpp", "main.h", false),
+            ("*.cpp", "src/main.cpp", false),
+            ("*", "", true),
+            ("**/*.cpp", "main.cpp", true),
+            ("**/*.cpp", "src/nested/main.cpp", true),
+            ("src/**", "src/nested/main.cpp", true),
+            ("src/**/main.cpp", "src/main.cpp", true),
+            ("src/**/main.cpp", "src/a/b/main.cpp", true),
+            ("src/**/main.cpp", "other/src/main.cpp", false),
+            ("?.cpp", "a.cpp", true),
+            ("?.cpp", "ab.cpp", false),
+            ("src?main.cpp", "src/main.cpp", false),
+            ("[ab].cpp", "b.cpp", true),
+            ("[a-c].cpp", "d.cpp", false),
+            ("[!a-c].cpp", "d.cpp", true),
+            ("[a.cpp", "[a.cpp", true),
+            ("\\*.cpp", "*.cpp", true),
+            ("\\*.cpp", "a.cpp", false),
+        ];
+        for (glob, text, expected) in cases {
+            assert_eq!(matches(glob, text), expected, "{:?} against {:?}", glob, text);
+        }
+    }
+
+    #[test]
+    fn patterns_are_anchored_and_limited_to_directories_as_written() {
+        let base = Path::new("project");
+        let rules = IgnoreRules::parse(base, "# comment\n/generated\nbuild/\n*.bak\n!keep.bak\n");
+        let ignored = |path: &str, is_dir| rules.is_ignored(&base.join(path), is_dir);
+        // A leading slash anchors the pattern to the project root
+        assert!(ignored("generated", false));
+        assert!(!ignored("src/generated", false));
+        // A trailing slash only matches directories, at any depth
+        assert!(ignored("build", true));
+        assert!(ignored("src/build", true));
+        assert!(!ignored("build", false));
+        // The last matching pattern wins
+        assert!(ignored("src/old.bak", false));
+        assert!(!ignored("src/keep.bak", false));
+        // Paths outside the project are never ignored
+        assert!(!rules.is_ignored(Path::new("elsewhere/old.bak"), false));
+    }
+}
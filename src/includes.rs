@@ -0,0 +1,128 @@
+// Reconstruction of a source file's include tree, for tracking down include bloat.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct IncludeNode {
+    pub path: PathBuf,
+    /// The size of the file itself, in bytes
+    pub size: u64,
+    /// The size of the file plus everything it includes (for the first time), in bytes
+    pub total_size: u64,
+    pub children: Vec<IncludeNode>,
+}
+
+impl IncludeNode {
+    /// Every node below this one, in depth-first order
+    pub fn descendants(&self) -> Vec<&IncludeNode> {
+        let mut descendants = Vec::new();
+        for child in &self.children {
+            descendants.push(child);
+            descendants.extend(child.descendants());
+        }
+        descendants
+    }
+}
+
+/// Builds the include tree of `src_path` from `includes`, the flat list of headers that the compiler reported for it
+/// via /sourceDependencies. The compiler doesn't report which header included which, so the nesting is reconstructed
+/// by following the #include directives in each file and matching them to the reported headers. As with
+/// /showIncludes, each header only appears where it was first included.
+///
+/// Returns the tree, along with any reported headers that couldn't be placed in it (e.g., because they were included
+/// via a macro).
+pub fn include_tree(src_path: &Path, includes: &[PathBuf]) -> (IncludeNode, Vec<PathBuf>) {
+    let normalized_includes: Vec<String> = includes.iter().map(|path| normalize(path)).collect();
+    let mut visited = vec![false; includes.len()];
+    let root = build_node(src_path, includes, &normalized_includes, &mut visited);
+    let unplaced = includes.iter()
+        .zip(&visited)
+        .filter(|(_, &visited)| !visited)
+        .map(|(path, _)| path.clone())
+        .collect();
+    (root, unplaced)
+}
+
+fn build_node(path: &Path, includes: &[PathBuf], normalized_includes: &[String], visited: &mut [bool]) -> IncludeNode {
+    let contents = fs::read(path).unwrap_or_default();
+    let size = contents.len() as u64;
+    let parent_dir = path.parent().map(normalize).unwrap_or_default();
+
+    let mut children = Vec::new();
+    for name in include_directives(&String::from_utf8_lossy(&contents)) {
+        let suffix = format!("/{}", normalize(Path::new(&name)));
+        let mut candidates = normalized_includes.iter()
+            .enumerate()
+            .filter(|(_, include)| include.ends_with(&suffix));
+        // Quoted includes are searched for relative to the including file first, so prefer a match from its
+        // directory
+        let index = candidates.clone()
+            .find(|(_, include)| include.starts_with(&parent_dir))
+            .or_else(|| candidates.next())
+            .map(|(i, _)| i);
+        if let Some(i) = index {
+            if !visited[i] {
+                visited[i] = true;
+                children.push(build_node(&includes[i], includes, normalized_includes, visited));
+            }
+        }
+    }
+
+    let total_size = size + children.iter().map(|child| child.total_size).sum::<u64>();
+    IncludeNode { path: path.to_owned(), size, total_size, children }
+}
+
+/// Normalizes a path for comparison, since MSVC reports include paths in lowercase
+fn normalize(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").to_lowercase()
+}
+
+/// The names in the `#include "..."` and `#include <...>` directives in `contents`, in order
+fn include_directives(contents: &str) -> Vec<String> {
+    contents.lines()
+        .filter_map(|line| {
+            let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim_start();
+            let terminator = match rest.chars().next()? {
+                '"' => '"',
+                '<' => '>',
+                _ => return None,
+            };
+            let rest = &rest[1..];
+            let end = rest.find(terminator)?;
+            Some(rest[..end].to_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn include_directives_are_found_with_either_delimiter() {
+        let contents = "#include \"a.h\"\n  #  include <sys/b.h>\n// #include \"comment.h\"\n#define INCLUDE <c.h>\n#include INCLUDE\n#pragma once\n";
+        assert_eq!(include_directives(contents), ["a.h", "sys/b.h"]);
+    }
+
+    #[test]
+    fn include_trees_are_nested_and_only_list_each_header_once() {
+        let dir = TempDir::new();
+        let main = dir.write("src/main.cpp", "#include \"a.h\"\n#include \"b.h\"\n");
+        let a = dir.write("src/a.h", "#include \"b.h\"\n");
+        let b = dir.write("src/b.h", "int b;");
+        // e.g., included via a macro
+        let macro_included = dir.write("include/macro.h", "");
+        let (tree, unplaced) = include_tree(&main, &[a.clone(), b, macro_included.clone()]);
+
+        assert_eq!(tree.children.len(), 1);
+        let a_node = &tree.children[0];
+        assert_eq!(a_node.path, a);
+        assert_eq!(a_node.children.len(), 1);
+        assert_eq!(a_node.children[0].size, 6);
+        assert_eq!(a_node.total_size, a_node.size + 6);
+        assert_eq!(tree.total_size, tree.size + a_node.total_size);
+        assert_eq!(tree.descendants().len(), 2);
+        assert_eq!(unplaced, [macro_included]);
+    }
+}
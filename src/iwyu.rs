@@ -0,0 +1,90 @@
+// Integration with include-what-you-use (https://include-what-you-use.org), which suggests #includes to add to and
+// remove from each source, given the exact flags that ABS compiles it with.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use futures::future::join_all;
+use tokio::sync::mpsc;
+
+use crate::build::{BuildEnvironment, BuildError, PchOption};
+use crate::build_manager::{run_cmd, OutputLine};
+use crate::task::CxxTask;
+use crate::toolchain_paths::Vendor;
+
+/// Compiler flags that only affect the outputs of compilation, or that clang-cl doesn't understand
+const IGNORED_FLAG_PREFIXES: &[&str] = &["/Fo", "/Fd", "/sourceDependencies", "/pathmap:", "/await"];
+
+/// Finds include-what-you-use in the directories of the PATH environment variable
+fn find_iwyu() -> Option<PathBuf> {
+    let name = if cfg!(target_os = "windows") { "include-what-you-use.exe" } else { "include-what-you-use" };
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+impl BuildEnvironment<'_> {
+    /// Runs include-what-you-use over each of the project's C++ sources, and prints its suggestions grouped by source.
+    /// Sources whose includes are already correct are omitted.
+    ///
+    /// Dependencies' headers are copied first, just as they would be at the start of a build.
+    pub async fn include_what_you_use(&self) -> Result<(), BuildError> {
+        let iwyu_path = match &self.config.iwyu_path {
+            Some(path) => {
+                let path = self.project_path.join(path);
+                if !path.is_file() {
+                    return Err(BuildError::IwyuNotFound(Some(path)));
+                }
+                path
+            },
+            None => find_iwyu().ok_or(BuildError::IwyuNotFound(None))?,
+        };
+        let iwyu_path = iwyu_path.as_path();
+
+        self.check_dependency_library_modes()?;
+        let paths = self.discover_sources()?;
+        self.copy_dependency_headers()?;
+
+        let src_paths = paths.all_src_paths();
+        let mut jobs = Vec::new();
+        for &path in &src_paths {
+            // Precompiled headers are specific to the compiler, so include pch.h like any other header
+            let (flags, _) = CxxTask::compile_flags(self, path, PchOption::NoPch)?;
+            let mut args: Vec<OsString> = Vec::new();
+            if matches!(self.toolchain_paths.vendor, Vendor::Msvc) {
+                args.push("--driver-mode=cl".into());
+            }
+            args.extend(
+                flags.build().into_iter()
+                    .filter(|flag| !IGNORED_FLAG_PREFIXES.iter().any(|prefix| flag.to_string_lossy().starts_with(prefix)))
+            );
+            jobs.push(async move {
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                // include-what-you-use exits with a failure code whenever it has suggestions, so the result isn't
+                // meaningful
+                run_cmd(iwyu_path, &args, &self.toolchain_paths.bin_paths, None, tx).await;
+                let mut lines = Vec::new();
+                while let Some(line) = rx.recv().await {
+                    match line {
+                        OutputLine::Stdout(line) | OutputLine::Stderr(line) => lines.push(line),
+                    }
+                }
+                (path, lines)
+            });
+        }
+
+        let mut num_with_suggestions = 0;
+        for (path, lines) in join_all(jobs).await {
+            let suggestions: Vec<String> = lines.into_iter()
+                .filter(|line| !line.trim().is_empty() && !line.contains("has correct #includes/fwd-decls"))
+                .collect();
+            if !suggestions.is_empty() {
+                num_with_suggestions += 1;
+                self.print_diagnostics(path, &[suggestions.join("\n")]);
+            }
+        }
+        println!("include-what-you-use has suggestions for {} of {} sources in \"{}\".", num_with_suggestions, src_paths.len(), self.config.name);
+        Ok(())
+    }
+}
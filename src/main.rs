@@ -1,13 +1,14 @@
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::path::{Path, PathBuf, Component, Prefix};
 use std::fs::{self, File};
 use std::io::ErrorKind as IoErrorKind;
-use std::io::{BufReader, Write, Result as IoResult};
+use std::io::{self, BufReader, Write, Result as IoResult};
 use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::collections::HashSet;
 use std::collections::HashMap;
 use std::io::Cursor;
+use std::future::Future;
 
 use clap::Parser;
 
@@ -18,15 +19,24 @@ mod build_manager;
 mod toolchain_paths;
 mod task;
 mod progress_bar;
+mod explain;
+mod ninja;
+mod describe;
+mod includes;
+mod iwyu;
+mod ignore;
+mod gc;
+#[cfg(test)]
+mod test_util;
 
-use proj_config::{ProjectConfig, OutputType, CxxOptions, Platform};
-use cmd_options::{CmdOptions, CompileMode, Subcommand, Target, BuildOptions};
-use build::BuildEnvironment;
+use proj_config::{ProjectConfig, WorkspaceConfig, OutputType, CxxOptions, CxxIncompatibility, HardeningOptions, CodeSigningOptions, ModeStandards, Platform, Os, Subsystem};
+use cmd_options::{CmdOptions, Subcommand, Target, BuildOptions, RunOptions, PackageOptions, GenerateOptions, Generator, CompileMode, DescribeFormat};
+use build::{BuildEnvironment, BuildError, SrcPaths};
 use toolchain_paths::ToolchainPaths;
 
 pub fn kill_process(path: impl AsRef<Path>) -> Option<i32> {
     Command::new("taskkill")
-        .args(&[OsStr::new("/F"), OsStr::new("/IM"), path.as_ref().as_os_str()])
+        .args([OsStr::new("/F"), OsStr::new("/IM"), path.as_ref().as_os_str()])
         .output()
         .map(|output| output.status.code())
         .unwrap_or(None)
@@ -54,9 +64,622 @@ pub fn canonicalize(p: impl AsRef<Path>) -> IoResult<PathBuf> {
     }
 }
 
+/// Walks up from the current directory to find the nearest directory containing an abs.json or abs-workspace.json
+/// file, similar to how cargo finds Cargo.toml.
+fn find_project_root() -> IoResult<Option<PathBuf>> {
+    let current_dir = std::env::current_dir()?;
+    Ok(find_project_root_from(&current_dir))
+}
+
+/// Walks up from `dir` to find the nearest directory containing an abs.json or abs-workspace.json file
+fn find_project_root_from(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join("abs.json").is_file() || dir.join("abs-workspace.json").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Dependencies' headers are included as `<directory name>/header.h`, so the directory name must be something that
+/// every preprocessor and filesystem treats the same way
+fn is_valid_include_prefix(name: &str) -> bool {
+    !name.is_empty() &&
+        !name.starts_with('.') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The platforms on which a `link_libraries` entry can be linked
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LibraryKind {
+    /// A library file with a .lib extension (in any case), which only Windows linkers accept
+    Windows,
+    /// A library named with the `-l` linker flag (e.g., -lpthread), which only non-Windows linkers accept
+    Unix,
+    /// Anything else, such as the path of a prebuilt library
+    Any,
+}
+
+fn library_kind(lib: &Path) -> LibraryKind {
+    if lib.as_os_str().to_string_lossy().starts_with("-l") {
+        LibraryKind::Unix
+    } else if lib.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("lib")) {
+        LibraryKind::Windows
+    } else {
+        LibraryKind::Any
+    }
+}
+
+/// Removes repeated problems, which can occur when the same project is reached via several paths in the dependency
+/// graph. Preserves the order in which problems were first found.
+fn dedup_problems(problems: &mut Vec<String>) {
+    let mut seen = HashSet::new();
+    problems.retain(|problem| seen.insert(problem.clone()));
+}
+
+/// Returns a problem for each field in the project file at `config_path` that ABS doesn't recognize. These are
+/// otherwise silently ignored, so a misspelled field name would go unnoticed.
+fn unknown_fields(config_path: &Path) -> Vec<String> {
+    let config: serde_json::Value = match File::open(config_path).ok().and_then(|file| serde_json::from_reader(BufReader::new(file)).ok()) {
+        Some(config) => config,
+        None => return vec![],
+    };
+    let config_path = config_path.as_os_str().to_string_lossy();
+    let mut problems = Vec::new();
+    if let Some(fields) = config.as_object() {
+        for (field, value) in fields {
+            if !ProjectConfig::FIELD_NAMES.contains(&field.as_str()) {
+                problems.push(format!("{} contains unknown field \"{}\".", config_path, field));
+            } else if field == "cxx_options" {
+                for cxx_field in value.as_object().into_iter().flat_map(|cxx_options| cxx_options.keys()) {
+                    if !CxxOptions::FIELD_NAMES.contains(&cxx_field.as_str()) {
+                        problems.push(format!("{} contains unknown field \"cxx_options.{}\".", config_path, cxx_field));
+                    } else if cxx_field == "mode_standards" {
+                        for mode in value[cxx_field].as_object().into_iter().flat_map(|mode_standards| mode_standards.keys()) {
+                            if !ModeStandards::FIELD_NAMES.contains(&mode.as_str()) {
+                                problems.push(format!("{} contains unknown field \"cxx_options.mode_standards.{}\".", config_path, mode));
+                            }
+                        }
+                    }
+                }
+            } else if field == "hardening" {
+                for hardening_field in value.as_object().into_iter().flat_map(|hardening| hardening.keys()) {
+                    if !HardeningOptions::FIELD_NAMES.contains(&hardening_field.as_str()) {
+                        problems.push(format!("{} contains unknown field \"hardening.{}\".", config_path, hardening_field));
+                    }
+                }
+            } else if field == "code_signing" {
+                for signing_field in value.as_object().into_iter().flat_map(|code_signing| code_signing.keys()) {
+                    if !CodeSigningOptions::FIELD_NAMES.contains(&signing_field.as_str()) {
+                        problems.push(format!("{} contains unknown field \"code_signing.{}\".", config_path, signing_field));
+                    }
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Copies the files in the directory tree rooted at `template_path` into `dest_path`, replacing `{name}` with `name` in
+/// those that are text. Fails if either directory contains the other, since the copy would then read its own output.
+fn copy_template(template_path: &Path, dest_path: &Path, name: &str) -> IoResult<()> {
+    fs::create_dir_all(dest_path)?;
+    let (template_path, dest_path) = (canonicalize(template_path)?, canonicalize(dest_path)?);
+    if dest_path.starts_with(&template_path) || template_path.starts_with(&dest_path) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the template and the new project's directories must not contain each other"));
+    }
+    copy_template_files(&template_path, &dest_path, name)
+}
+
+fn copy_template_files(template_path: &Path, dest_path: &Path, name: &str) -> IoResult<()> {
+    fs::create_dir_all(dest_path)?;
+    for entry in fs::read_dir(template_path)? {
+        let entry = entry?;
+        let dest_path = dest_path.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_template_files(&entry.path(), &dest_path, name)?;
+        } else {
+            let contents = fs::read(entry.path())?;
+            match String::from_utf8(contents) {
+                Ok(text) => fs::write(&dest_path, text.replace("{name}", name))?,
+                // Binary files (e.g., icons) are copied as-is
+                Err(error) => fs::write(&dest_path, error.into_bytes())?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the number of files in the directory tree rooted at `path`, and their total size in bytes.
+pub fn dir_usage(path: &Path) -> IoResult<(u64, u64)> {
+    let mut files = 0;
+    let mut bytes = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (child_files, child_bytes) = dir_usage(&entry.path())?;
+            files += child_files;
+            bytes += child_bytes;
+        } else {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+/// The settings that a project is adapted to each root project it's built for, along with the definitions passed to
+/// it by that root, in a form that can be compared between roots
+fn workspace_settings(config: &ProjectConfig, definitions: &[(&str, &str)]) -> String {
+    serde_json::to_string(&(&config.cxx_options, &config.hardening, definitions)).unwrap()
+}
+
+/// Describes the toolchain found for `target`, with one line per detail or path, for `abs toolchain`
+fn describe_toolchain(target: Platform, toolchain_paths: &ToolchainPaths) -> String {
+    use std::fmt::Write;
+
+    fn describe_path(output: &mut String, path: &Path) {
+        let missing = if path.exists() { "" } else { " (missing)" };
+        writeln!(output, "    {}{}", path.as_os_str().to_string_lossy(), missing).unwrap();
+    }
+    let mut output = String::new();
+    writeln!(output, "Target: {:?}", target).unwrap();
+    for (description, value) in &toolchain_paths.details {
+        writeln!(output, "{}: {}", description, value).unwrap();
+    }
+    if !toolchain_paths.debugger_path.as_os_str().is_empty() {
+        writeln!(output, "Debugger:").unwrap();
+        describe_path(&mut output, &toolchain_paths.debugger_path);
+    }
+    for (name, path) in [("clang-format", &toolchain_paths.clang_format_path), ("signtool", &toolchain_paths.signtool_path)] {
+        writeln!(output, "{}:", name).unwrap();
+        match path {
+            Some(path) => describe_path(&mut output, path),
+            None => writeln!(output, "    (not found)").unwrap(),
+        }
+    }
+    for (name, paths) in [("Include paths", &toolchain_paths.include_paths), ("Library paths", &toolchain_paths.lib_paths), ("Binary paths", &toolchain_paths.bin_paths)] {
+        writeln!(output, "{}:", name).unwrap();
+        for path in paths {
+            describe_path(&mut output, path);
+        }
+    }
+    output
+}
+
+/// Reports how a program run with `--pause` exited, then waits for Enter to be pressed
+fn pause_after_exit(exit_code: Option<i32>, input: &mut impl io::BufRead, output: &mut impl Write) -> IoResult<()> {
+    match exit_code {
+        Some(code) => write!(output, "\nProcess exited with code {}. ", code)?,
+        None => write!(output, "\nProcess was terminated. ")?,
+    }
+    write!(output, "Press Enter to continue...")?;
+    output.flush()?;
+    input.read_line(&mut String::new())?;
+    Ok(())
+}
+
+/// Whether `product_path` was built from inputs with the fingerprint `fingerprint`, which is recorded in
+/// `fingerprint_path` after each successful build. If the fingerprint couldn't be computed, it must be rebuilt.
+fn is_up_to_date(fingerprint_path: &Path, fingerprint: Option<&str>, product_path: &Path) -> bool {
+    fingerprint.is_some() &&
+        fs::read_to_string(fingerprint_path).ok().as_deref() == fingerprint &&
+        product_path.is_file()
+}
+
+/// Runs `build` on each of `projects` at once, and returns the results in the same order. Each build still runs its
+/// own steps in order (e.g., a project's sources are only compiled once its precompiled header has been generated), but
+/// the builds overlap with each other.
+async fn build_concurrently<P, F: Future>(projects: impl IntoIterator<Item=P>, build: impl FnMut(P) -> F) -> Vec<F::Output> {
+    futures::future::join_all(projects.into_iter().map(build)).await
+}
+
+/// The definitions to compile `config` with, apart from those read from `--define-from-file`. `extra_definitions`
+/// are those passed to it by the root project, if it's a dependency. The project's own `definitions` only apply to
+/// it, and never to the projects that depend on it.
+fn project_definitions<'a>(config: &'a ProjectConfig, extra_definitions: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+    // TODO: make these configurable
+    let mut definitions = vec![("_WINDOWS", ""), ("WIN32", ""), ("UNICODE", ""), ("_USE_MATH_DEFINES", "")];
+    if let Some(windows_target_version) = config.windows_target_version {
+        definitions.extend(windows_target_version.definitions());
+    }
+    definitions.extend(config.own_definitions());
+    definitions.extend_from_slice(extra_definitions);
+    definitions
+}
+
+/// Formats a number with thousands separators (e.g., 1234 -> "1,234").
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Formats a size in bytes in human-readable form (e.g., "2.3 GB").
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// The progress of a build of one or more root projects, each in one or more targets and modes
+#[derive(Default)]
+struct BuildState {
+    /// Maps each (project name, target, mode) that has already been built to its build product
+    built_projects: HashMap<(String, Platform, CompileMode), Option<PathBuf>>,
+    /// The root project for which each (project name, target, mode) in `built_projects` was built, and the settings it
+    /// was adapted to that root with (see `workspace_settings()`)
+    built_for: HashMap<(String, Platform, CompileMode), (String, String)>,
+    /// (project name, target, mode) combinations that failed to build, in order
+    failed_projects: Vec<(String, Platform, CompileMode)>,
+    /// Ninja files generated for each project, in the order they were generated
+    ninja_files: Vec<PathBuf>,
+    /// The outcome of each (root project name, target, mode) combination, in order, along with the reason for each
+    /// failure
+    outcomes: Vec<(String, Platform, CompileMode, Option<String>)>,
+}
+
+impl BuildState {
+    /// Records that `key` is being built for `root_name` with `settings`. Every build of a project for a target and
+    /// mode shares the same artifacts, so returns an error if it was already built for another root project that needs
+    /// different settings.
+    fn build_for(&mut self, key: &(String, Platform, CompileMode), root_name: &str, settings: String) -> Result<(), String> {
+        match self.built_for.get(key) {
+            Some((built_root_name, built_settings)) => if *built_settings != settings {
+                return Err(format!("\"{}\" is needed by both \"{}\" and \"{}\" for target {:?} in {} mode, but they build it with different C++ options, hardening options or definitions. Please make their settings for it match and try again.", key.0, built_root_name, root_name, key.1, key.2.name()));
+            },
+            None => {
+                self.built_for.insert(key.clone(), (root_name.to_string(), settings));
+            },
+        }
+        Ok(())
+    }
+
+    /// Records that `key` failed to build, so that the projects depending on it are skipped
+    fn fail(&mut self, key: (String, Platform, CompileMode)) {
+        if !self.failed_projects.contains(&key) {
+            self.failed_projects.push(key);
+        }
+    }
+
+    /// Whether `key` has failed to build
+    fn has_failed(&self, key: &(String, Platform, CompileMode)) -> bool {
+        self.failed_projects.contains(key)
+    }
+
+    /// The outcome of each combination of root project, target and mode that was attempted. Empty when there was only
+    /// one, since the outcome of a single build is already clear.
+    fn summary(&self) -> Vec<String> {
+        if self.outcomes.len() <= 1 {
+            return Vec::new();
+        }
+        let mut lines = vec!["Summary:".to_string()];
+        for (name, target, mode, failure) in &self.outcomes {
+            match failure {
+                None => lines.push(format!("    \"{}\" for target {:?} in {} mode: ok", name, target, mode.name())),
+                Some(reason) => lines.push(format!("    \"{}\" for target {:?} in {} mode: FAILED ({})", name, target, mode.name(), reason)),
+            }
+        }
+        lines
+    }
+
+    fn print_summary(&self) {
+        let summary = self.summary();
+        if !summary.is_empty() {
+            println!("{}\n", summary.join("\n"));
+        }
+    }
+
+    /// Lists the projects that failed to build, if any did
+    fn failure_report(&self) -> Option<String> {
+        if self.failed_projects.is_empty() {
+            return None;
+        }
+        let mut report = "The following projects failed to build:".to_string();
+        for (name, target, mode) in &self.failed_projects {
+            report.push_str(&format!("\n    \"{}\" for target {:?} in {} mode", name, target, mode.name()));
+        }
+        Some(report)
+    }
+}
+
+/// Reads the project file in `root_path`, adding any problems with its contents to `problems`. Returns `None`
+/// if the file couldn't be read or parsed at all.
+fn read_config(root_path: &Path, problems: &mut Vec<String>) -> Option<(PathBuf, ProjectConfig)> {
+    let config_path = root_path.join("abs.json");
+    let config_file = match File::open(&config_path) {
+        Ok(file) => BufReader::new(file),
+        Err(error) => {
+            let mut err_msg = Cursor::new(Vec::new());
+            let root_path_str = root_path.as_os_str().to_string_lossy();
+            write!(err_msg, "Unable to read project file in ").unwrap();
+            if root_path_str == "." {
+                write!(err_msg, "the current directory").unwrap();
+            } else {
+                write!(err_msg, "directory \"{}\"", root_path_str).unwrap();
+            }
+            problems.push(format!("{}: {}.", String::from_utf8_lossy(&err_msg.into_inner()), error));
+            return None;
+        },
+    };
+    let config: ProjectConfig = match serde_json::from_reader(config_file) {
+        Ok(config) => config,
+        Err(error) => {
+            problems.push(format!("Failed to parse project file {}: {}", config_path.as_os_str().to_string_lossy(), error));
+            return None;
+        },
+    };
+
+    // Validate supported targets list
+    if config.supported_targets.is_empty() {
+        problems.push(format!("{} contains an empty list of supported targets. Please add at least one and try again.\nAvailable options: win32, win64.", config_path.as_os_str().to_string_lossy()));
+    }
+    // TODO: speed
+    let unique_supported_targets: HashSet<_> = config.supported_targets.iter().cloned().collect();
+    if unique_supported_targets.len() < config.supported_targets.len() {
+        problems.push(format!("{} contains one or more duplicates in its list of supported targets. Please ensure that each target is unique.\nThe supported platforms listed are: {:?}", config_path.as_os_str().to_string_lossy(), config.supported_targets));
+    }
+    if config.thin_archive && !matches!(config.output_type, OutputType::StaticLibrary) {
+        problems.push(format!("{} enables `thin_archive`, but only static libraries can be built as thin archives.", config_path.as_os_str().to_string_lossy()));
+    }
+    if let Some(code_signing) = &config.code_signing {
+        match (&code_signing.certificate_thumbprint, &code_signing.certificate_file) {
+            (None, None) => problems.push(format!("{} enables `code_signing`, but specifies no certificate. Please add either `certificate_thumbprint` or `certificate_file` and try again.", config_path.as_os_str().to_string_lossy())),
+            (Some(_), Some(_)) => problems.push(format!("{} specifies both `certificate_thumbprint` and `certificate_file` for `code_signing`. Please remove one and try again.", config_path.as_os_str().to_string_lossy())),
+            (None, Some(certificate_file)) => if !root_path.join(certificate_file).is_file() {
+                problems.push(format!("Certificate file \"{}\" for `code_signing` in {} does not exist.", certificate_file.as_os_str().to_string_lossy(), config_path.as_os_str().to_string_lossy()));
+            },
+            (Some(_), None) => {},
+        }
+    }
+    if let Some(default_target) = config.default_target {
+        if !config.supported_targets.contains(&default_target) {
+            problems.push(format!("{} has a default target of {:?}, which is not one of its supported targets.\nThe supported platforms listed are: {:?}", config_path.as_os_str().to_string_lossy(), default_target, config.supported_targets));
+        }
+    }
+
+    // Validate that the project's Windows-specific settings make sense for all of its targets
+    let non_windows_targets: Vec<Platform> = config.supported_targets.iter().cloned()
+        .filter(|target| !matches!(target.os(), Os::Windows))
+        .collect();
+    if !non_windows_targets.is_empty() {
+        if matches!(config.output_type, OutputType::GuiApp) {
+            problems.push(format!("{} describes a GUI app, which is only supported on Windows, but it lists non-Windows supported targets: {:?}. Please remove them and try again.", config_path.as_os_str().to_string_lossy(), non_windows_targets));
+        }
+        let windows_libraries: Vec<&PathBuf> = config.link_libraries.iter()
+            .filter(|lib| matches!(library_kind(lib), LibraryKind::Windows))
+            .collect();
+        if !windows_libraries.is_empty() {
+            problems.push(format!("{} links to Windows-only libraries {:?}, but it lists non-Windows supported targets: {:?}. Please remove either the libraries or the targets and try again.", config_path.as_os_str().to_string_lossy(), windows_libraries, non_windows_targets));
+        }
+    }
+    let windows_targets: Vec<Platform> = config.supported_targets.iter().cloned()
+        .filter(|target| matches!(target.os(), Os::Windows))
+        .collect();
+    if !windows_targets.is_empty() {
+        let unix_libraries: Vec<&PathBuf> = config.link_libraries.iter()
+            .filter(|lib| matches!(library_kind(lib), LibraryKind::Unix))
+            .collect();
+        if !unix_libraries.is_empty() {
+            problems.push(format!("{} links to libraries {:?} by `-l` name, which is only understood by non-Windows linkers, but it lists Windows supported targets: {:?}. Please list Windows libraries by file name (e.g., user32.lib), or move the `-l` names to `target_link_libraries`, and try again.", config_path.as_os_str().to_string_lossy(), unix_libraries, windows_targets));
+        }
+    }
+
+    Some((config_path, config))
+}
+
+struct Project {
+    config_path: PathBuf,
+    config: ProjectConfig,
+    dep_names: Vec<String>,
+    visited: bool,
+}
+
+impl Project {
+    /// The project's name along with the path it was loaded from, for problems involving dependencies. The name
+    /// a dependency declares for itself needn't match its directory name, so the name alone can be surprising.
+    fn display_name(&self) -> String {
+        format!("\"{}\" ({})", self.config.name, self.config_path.as_os_str().to_string_lossy())
+    }
+}
+
+/// Loads the root project (whose canonical config path is `config_path`) and all of its dependencies, keyed by
+/// name. Problems with the dependency graph, such as loops and name collisions, are added to `problems`.
+fn load_project_graph(config_path: PathBuf, config: &ProjectConfig, problems: &mut Vec<String>) -> HashMap<String, Project> {
+    let mut projects = HashMap::new();
+    projects.insert(config.name.clone(), Project { config_path: config_path.clone(), config: config.clone(), dep_names: Vec::new(), visited: false });
+    accumulate_dependencies(&mut projects, config_path, config, &mut vec![config.name.clone()], problems);
+    projects
+}
+
+/// Loads the dependency graph of the project at `config_path` into `projects`, adding any problems found to
+/// `problems`. `chain` holds the names of the projects from the root down to (and including) this one, for finding
+/// loops.
+fn accumulate_dependencies(projects: &mut HashMap<String, Project>, config_path: PathBuf, config: &ProjectConfig, chain: &mut Vec<String>, problems: &mut Vec<String>) {
+    let mut root_path = config_path.clone();
+    root_path.pop();
+
+    let mut canonical_deps = Vec::new();
+    for dep in &config.dependencies {
+        if dep.components().count() == 0 {
+            problems.push(format!("Empty path found as dependency in project \"{}\"", config.name));
+            continue;
+        }
+        let dep = root_path.join(dep);
+        match canonicalize(&dep) {
+            // Usually a copy-paste mistake, such as `"dependencies": ["."]`
+            Ok(canon) if canon == root_path => problems.push(format!("Project \"{}\" cannot depend on itself", config.name)),
+            Ok(canon) => canonical_deps.push(canon),
+            Err(error) => problems.push(format!("Failed to get canonical path for dependency \"{}\": {}", dep.as_os_str().to_string_lossy(), error)),
+        }
+    }
+    let unique_deps: HashSet<&PathBuf> = canonical_deps.iter().collect();
+    if unique_deps.len() < canonical_deps.len() {
+        problems.push(format!("{} contains one or more duplicates in its dependencies array", config_path.as_os_str().to_string_lossy()));
+    }
+    let mut dep_names = Vec::new();
+    for dependency in &canonical_deps {
+        let include_prefix = dependency.file_name().unwrap_or_default().to_string_lossy();
+        if !is_valid_include_prefix(&include_prefix) {
+            problems.push(format!("The directory name of dependency \"{}\" can't be used to include its headers (as `<{}/header.h>`). Please rename the directory to use only ASCII letters, digits, '_', '-' and '.'.", dependency.as_os_str().to_string_lossy(), include_prefix));
+        }
+        let (dep_config_path, dep_config) = match read_config(dependency, problems) {
+            Some(result) => result,
+            None => continue,
+        };
+        let proj = projects
+            .entry(dep_config.name.clone())
+            .or_insert_with(|| {
+                Project {
+                    config_path: dep_config_path.clone(),
+                    config: dep_config.clone(),
+                    dep_names: Vec::new(),
+                    visited: false,
+                }
+            });
+        if dep_config_path != proj.config_path {
+            problems.push(format!("Two projects in dependency graph found with the same name, \"{}\": {} and {}", proj.config.name, proj.config_path.as_os_str().to_string_lossy(), dep_config_path.as_os_str().to_string_lossy()));
+            continue;
+        }
+        if let Some(start) = chain.iter().position(|name| *name == dep_config.name) {
+            let names: Vec<String> = chain[start..].iter()
+                .chain(std::iter::once(&dep_config.name))
+                .map(|name| format!("\"{}\"", name))
+                .collect();
+            problems.push(format!("Loop found in dependency graph: {}.", names.join(" -> ")));
+            continue;
+        }
+        dep_names.push(proj.config.name.clone());
+
+        chain.push(dep_config.name.clone());
+        accumulate_dependencies(projects, dep_config_path, &dep_config, chain, problems);
+        chain.pop();
+    }
+
+    projects.get_mut(&config.name).unwrap().dep_names = dep_names;
+}
+
+/// Checks that each project the root passes definitions to is actually in its dependency graph
+fn validate_dependency_defines(projects: &HashMap<String, Project>, root_config: &ProjectConfig, problems: &mut Vec<String>) {
+    let mut names: Vec<&String> = root_config.dependency_defines.keys().collect();
+    names.sort();
+    for name in names {
+        if *name == root_config.name || !projects.contains_key(name) {
+            problems.push(format!("{} passes definitions to \"{}\", which is not one of its dependencies.", root_config.name, name));
+        }
+    }
+}
+
+/// Checks that the C++ options of each dependency of the root project are compatible with its own in each of
+/// `modes`
+fn validate_cxx_options(projects: &HashMap<String, Project>, root_config: &ProjectConfig, modes: &[CompileMode], problems: &mut Vec<String>) {
+    let mut names: Vec<&String> = projects.keys().filter(|&name| *name != root_config.name).collect();
+    names.sort();
+    for name in names {
+        let dep = &projects[name];
+        let dep_cxx_options = &dep.config.cxx_options;
+        // Each distinct incompatibility, along with the modes in which it occurs
+        let mut incompatibilities: Vec<(CxxIncompatibility, Vec<&str>)> = Vec::new();
+        for &mode in modes {
+            if let Err(incompatibility) = dep_cxx_options.check_compatible_with(&root_config.cxx_options, mode) {
+                match incompatibilities.iter_mut().find(|(existing, _)| *existing == incompatibility) {
+                    Some((_, incompatible_modes)) => incompatible_modes.push(mode.name()),
+                    None => incompatibilities.push((incompatibility, vec![mode.name()])),
+                }
+            }
+        }
+        for (incompatibility, incompatible_modes) in incompatibilities {
+            if incompatible_modes.len() == modes.len() {
+                problems.push(format!("The C++ options of {} are incompatible with those of the root project \"{}\" ({}).", dep.display_name(), root_config.name, incompatibility));
+            } else {
+                problems.push(format!("The C++ options of {} are incompatible with those of the root project \"{}\" in {} mode{} ({}).", dep.display_name(), root_config.name, incompatible_modes.join(", "), if incompatible_modes.len() == 1 { "" } else { "s" }, incompatibility));
+            }
+        }
+    }
+}
+
+/// Checks that the dependencies of project `name` are compatible with it, adding any problems found to
+/// `problems`, and collects the libraries they link to into `link_libraries` (or `target_link_libraries`, for
+/// libraries that are specific to a target), the directories in which to search
+/// for them into `library_paths`, and the DLLs they need at run time into `runtime_libraries`.
+fn validate_dependencies(projects: &mut HashMap<String, Project>, link_libraries: &mut HashSet<PathBuf>, target_link_libraries: &mut HashMap<Platform, Vec<PathBuf>>, library_paths: &mut Vec<PathBuf>, runtime_libraries: &mut Vec<PathBuf>, name: &str, problems: &mut Vec<String>) {
+    let proj = projects.get_mut(name).unwrap();
+    if proj.visited {
+        return;
+    }
+    // Mark the project as visited up front, so that a loop in the dependency graph can't cause infinite recursion
+    proj.visited = true;
+    let supported_targets = proj.config.supported_targets.clone();
+
+    for dep in proj.dep_names.clone() {
+        validate_dependencies(projects, link_libraries, target_link_libraries, library_paths, runtime_libraries, &dep, problems);
+        let dep = projects.get(&dep).unwrap();
+        if !matches!(dep.config.output_type, OutputType::StaticLibrary) {
+            let dep_type = match dep.config.output_type {
+                OutputType::GuiApp => "GUI app",
+                OutputType::ConsoleApp => "console app",
+                OutputType::DynamicLibrary => "dynamic library",
+                OutputType::StaticLibrary => panic!(),
+            };
+            let proj = projects.get(name).unwrap();
+            problems.push(format!("Project \"{}\" depends on {}, a {}. Only static library dependencies are supported at this time.", proj.config.name, dep.display_name(), dep_type));
+        }
+        for platform in &supported_targets {
+            if !dep.config.supported_targets.contains(platform) {
+                problems.push(format!("{} claims to support target {:?}, but its dependency {} does not.", name, platform, dep.display_name()));
+            }
+        }
+    }
+
+    let proj = projects.get(name).unwrap();
+    // Library paths are relative to the project that specifies them, but the root project does the linking
+    let project_path = proj.config_path.parent().unwrap();
+    for lib in &proj.config.link_libraries {
+        // Prebuilt libraries may be referred to by path, rather than found in a library directory
+        let path = project_path.join(lib);
+        link_libraries.insert(if path.is_file() { path } else { lib.clone() });
+    }
+    for (&target, libs) in &proj.config.target_link_libraries {
+        let target_libs = target_link_libraries.entry(target).or_default();
+        for lib in libs {
+            let path = project_path.join(lib);
+            let lib = if path.is_file() { path } else { lib.clone() };
+            if !target_libs.contains(&lib) {
+                target_libs.push(lib);
+            }
+        }
+    }
+    for path in &proj.config.library_paths {
+        let path = project_path.join(path);
+        if !library_paths.contains(&path) {
+            library_paths.push(path);
+        }
+    }
+    for path in &proj.config.runtime_libraries {
+        let path = project_path.join(path);
+        if !path.is_file() {
+            problems.push(format!("Runtime library \"{}\" of project {} does not exist.", path.as_os_str().to_string_lossy(), proj.display_name()));
+        } else if !runtime_libraries.contains(&path) {
+            runtime_libraries.push(path);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let options = CmdOptions::parse();
+    let mut options = CmdOptions::parse();
     macro_rules! _task_failed {
         () => {
             println!("\nABS process failed.");
@@ -69,8 +692,90 @@ async fn main() {
             _task_failed!();
         }}
     }
-    let (config, artifact_path, toolchain_paths) = match &options.sub_command {
-        Subcommand::Init { project_root, output_type } => {
+    /// Prints each unique problem in `problems` and fails, if there are any
+    fn fail_on_problems(problems: &mut Vec<String>) {
+        if problems.is_empty() {
+            return;
+        }
+        dedup_problems(problems);
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+        _task_failed!();
+    }
+    fn load_config(root_path: &Path) -> (PathBuf, ProjectConfig) {
+        let mut problems = Vec::new();
+        let result = read_config(root_path, &mut problems);
+        fail_on_problems(&mut problems);
+        result.unwrap()
+    }
+    /// Reads abs-workspace.json, if the current directory contains one (and isn't itself a project)
+    fn load_workspace() -> Option<WorkspaceConfig> {
+        let workspace_path = Path::new("abs-workspace.json");
+        if !workspace_path.is_file() || Path::new("abs.json").is_file() {
+            return None;
+        }
+        let workspace_file = File::open(workspace_path)
+            .unwrap_or_else(|error| fail_immediate!("Unable to read workspace file: {}.", error));
+        let workspace: WorkspaceConfig = serde_json::from_reader(BufReader::new(workspace_file))
+            .unwrap_or_else(|error| fail_immediate!("Failed to parse workspace file: {}", error));
+        if workspace.members.is_empty() {
+            fail_immediate!("abs-workspace.json contains an empty list of members. Please add at least one and try again.");
+        }
+        Some(workspace)
+    }
+
+    /// Loads the current project, or each member of the current workspace, along with all of their dependencies, keyed
+    /// by name. Fails if there are problems with any dependency graph.
+    fn load_all_projects() -> HashMap<String, Project> {
+        let roots = match load_workspace() {
+            Some(workspace) => workspace.members,
+            None => vec![PathBuf::from(".")],
+        };
+        let mut problems = Vec::new();
+        let mut projects = HashMap::<String, Project>::new();
+        for root in &roots {
+            let (config_path, config) = load_config(root);
+            let config_path = canonicalize(&config_path)
+                .unwrap_or_else(|_| fail_immediate!("Failed to get canonical path for project config file"));
+            // Shared dependencies are only loaded once
+            if projects.contains_key(&config.name) {
+                continue;
+            }
+            projects.insert(config.name.clone(), Project { config_path: config_path.clone(), config: config.clone(), dep_names: Vec::new(), visited: false });
+            accumulate_dependencies(&mut projects, config_path, &config, &mut vec![config.name.clone()], &mut problems);
+        }
+        fail_on_problems(&mut problems);
+        projects
+    }
+
+    // Paths passed on the command line are relative to the directory ABS was run from, so they must be resolved before
+    // changing to the project root below
+    if let Some(BuildOptions { define_from_file: Some(path), .. }) = options.sub_command.build_options_mut() {
+        *path = canonicalize(&path)
+            .unwrap_or_else(|error| fail_immediate!("Unable to read definitions file \"{}\": {}.", path.as_os_str().to_string_lossy(), error));
+    }
+
+    // Commands that operate on an existing project may be run from anywhere inside it. Changing to the project root
+    // makes all relative paths (src, assets, dependencies, artifacts, etc.) resolve against it.
+    if matches!(options.sub_command, Subcommand::Build(_) | Subcommand::Run(_) | Subcommand::Debug(_) | Subcommand::Package(_) | Subcommand::Generate(_) | Subcommand::Iwyu(_) | Subcommand::Prime(_) | Subcommand::Fmt { .. } | Subcommand::Clean { .. } | Subcommand::Gc | Subcommand::Info | Subcommand::Targets { .. } | Subcommand::Validate { .. } | Subcommand::Describe { .. }) {
+        match find_project_root() {
+            Ok(Some(project_root)) => if let Err(error) = std::env::set_current_dir(&project_root) {
+                fail_immediate!("Unable to change to project directory \"{}\": {}.", project_root.as_os_str().to_string_lossy(), error);
+            },
+            // Fall through, so that the usual error is reported when the project file can't be found
+            Ok(None) => {},
+            Err(error) => fail_immediate!("Unable to get the current directory: {}.", error),
+        }
+    }
+
+    let (config, run_path, toolchain_paths) = match &options.sub_command {
+        Subcommand::Init { project_root, output_type, no_default_libraries, no_common_controls, link_libraries: extra_link_libraries, template } => {
+            if let Some(template) = template {
+                if !template.is_dir() {
+                    fail_immediate!("Template directory \"{}\" does not exist.", template.as_os_str().to_string_lossy());
+                }
+            }
             let project_root: Cow<Path> = project_root.as_ref()
                 .map(|path| Cow::from(path.as_path()))
                 .unwrap_or_else(||
@@ -82,24 +787,66 @@ async fn main() {
             if config_path.is_file() {
                 fail_immediate!("ABS project already exists.");
             } else {
-                let link_libraries = match output_type {
+                let mut link_libraries = match output_type {
+                    _ if *no_default_libraries => vec![],
                     OutputType::ConsoleApp | OutputType::DynamicLibrary | OutputType::StaticLibrary => vec![],
+                    OutputType::GuiApp if *no_common_controls => vec!["user32.lib".to_string()],
                     OutputType::GuiApp => vec!["user32.lib".to_string(), "comctl32.lib".to_string()],
                 };
+                for library in extra_link_libraries {
+                    if !link_libraries.iter().any(|existing| existing.eq_ignore_ascii_case(library)) {
+                        link_libraries.push(library.clone());
+                    }
+                }
+                // Only initialize common controls in the generated code if they will actually be linked
+                let uses_common_controls = link_libraries.iter()
+                    .any(|library| library.eq_ignore_ascii_case("comctl32.lib"));
                 let config = ProjectConfig {
                     name: project_root.file_name().unwrap()
                         .to_str().expect("Project name must be representable in UTF-8")
                         .to_string(),
+                    description: None,
+                    authors: vec![],
+                    version: None,
                     cxx_options: CxxOptions::default(),
+                    pin_cxx_options: false,
                     output_type: *output_type,
-                    link_libraries,
+                    link_libraries: link_libraries.into_iter().map(PathBuf::from).collect(),
+                    target_link_libraries: HashMap::new(),
+                    default_target: None,
+                    thin_archive: false,
+                    code_signing: None,
+                    iwyu_path: None,
+                    run_env: HashMap::new(),
                     supported_targets: vec![Platform::Win32, Platform::Win64],
                     dependencies: vec![],
+                    windows_target_version: None,
+                    output_name: None,
+                    uac_execution_level: None,
+                    flatten_artifacts: false,
+                    extra_sources: vec![],
+                    assets_dir: None,
+                    definitions: vec![],
+                    dependency_defines: HashMap::new(),
+                    reproducible: false,
+                    strip_pdb_path: false,
+                    include_precedence: None,
+                    include_paths: vec![],
+                    library_paths: vec![],
+                    runtime_libraries: vec![],
+                    manifests: vec![],
+                    hardening: HardeningOptions::default(),
                 };
                 let project_file = File::create(&config_path)
                     .unwrap_or_else(|error| fail_immediate!("Unable to open project file for writing: {}.", error));
                 serde_json::to_writer_pretty(project_file, &config).unwrap();
 
+                if let Some(template) = template {
+                    copy_template(template, &project_root, &config.name)
+                        .unwrap_or_else(|error| fail_immediate!("Unable to copy template \"{}\": {}.", template.as_os_str().to_string_lossy(), error));
+                    return;
+                }
+
                 let mut src_path = project_root.join("src");
                 fs::create_dir_all(&src_path).unwrap();
                 src_path.push("main.cpp");
@@ -120,14 +867,11 @@ int main() {{
                         write!(
                             file,
 r##"#include <windows.h>
-#include <commctrl.h>
-
+{common_controls_include}
 LRESULT CALLBACK WindowProc(HWND hwnd, UINT uMsg, WPARAM wParam, LPARAM lParam);
 
 int WINAPI wWinMain(HINSTANCE hInstance, HINSTANCE hPrevInstance, PWSTR pCmdLine, int nCmdShow) {{
-    InitCommonControls();
-
-    const wchar_t CLASS_NAME[] = L"{0} Window Class";
+{common_controls_init}    const wchar_t CLASS_NAME[] = L"{0} Window Class";
     WNDCLASS wc = {{
         .lpfnWndProc = WindowProc,
         .hInstance = hInstance,
@@ -181,6 +925,8 @@ LRESULT CALLBACK WindowProc(HWND hwnd, UINT uMsg, WPARAM wParam, LPARAM lParam)
 }}
 "##,
                             config.name,
+                            common_controls_include = if uses_common_controls { "#include <commctrl.h>\n" } else { "" },
+                            common_controls_init = if uses_common_controls { "    InitCommonControls();\n\n" } else { "" },
                         ).unwrap()
                     },
                     OutputType::DynamicLibrary => {
@@ -209,290 +955,474 @@ void print_hello_world() {{
                 return;
             }
         },
-        Subcommand::Build(build_options) | Subcommand::Run(build_options) | Subcommand::Debug(build_options) => {
-            fn load_config(root_path: &Path) -> (PathBuf, ProjectConfig) {
-                let config_path = root_path.join("abs.json");
-                let config_file = match File::open(&config_path) {
-                    Ok(file) => BufReader::new(file),
-                    Err(error) => {
-                        let mut err_msg = Cursor::new(Vec::new());
-                        let root_path_str = root_path.as_os_str().to_string_lossy();
-                        write!(err_msg, "Unable to read project file in ").unwrap();
-                        if root_path_str == "." {
-                            write!(err_msg, "the current directory").unwrap();
-                        } else {
-                            write!(err_msg, "directory \"{}\"", root_path_str).unwrap();
-                        }
-                        fail_immediate!("{}: {}.", String::from_utf8_lossy(&err_msg.into_inner()), error);
-                    },
+        Subcommand::Build(build_options) | Subcommand::Run(RunOptions { build_options, .. }) | Subcommand::Debug(build_options) | Subcommand::Package(PackageOptions { build_options, .. }) | Subcommand::Generate(GenerateOptions { build_options, .. }) | Subcommand::Iwyu(build_options) | Subcommand::Prime(build_options) => {
+            /// What to do with each project
+            #[derive(Clone, Copy)]
+            enum Action<'a> {
+                Build,
+                Package(&'a PackageOptions),
+                GenerateNinja,
+                IncludeWhatYouUse,
+                Prime,
+            }
+
+            /// Builds the project in `root_path` and its dependencies. Returns `None` if there is
+            /// nothing to run afterwards (i.e., when building for all targets, or when the build failed
+            /// with `--keep-going`). `workspace_member` is whether the project is being built as one member of a
+            /// workspace.
+            async fn build_root(root_path: &Path, workspace_member: bool, sub_command: &Subcommand, build_options: &BuildOptions, state: &mut BuildState) -> Option<(ProjectConfig, PathBuf, ToolchainPaths)> {
+                let (config_path, config) = load_config(root_path);
+                let action = match sub_command {
+                    Subcommand::Package(package) => Action::Package(package),
+                    Subcommand::Generate(GenerateOptions { generator: Generator::Ninja, .. }) => Action::GenerateNinja,
+                    Subcommand::Iwyu(_) => Action::IncludeWhatYouUse,
+                    Subcommand::Prime(_) => Action::Prime,
+                    _ => Action::Build,
                 };
-                let config: ProjectConfig = serde_json::from_reader(config_file)
-                    .unwrap_or_else(|error| fail_immediate!("Failed to parse project file: {}", error));
 
-                // Validate supported targets list
-                if config.supported_targets.is_empty() {
-                    fail_immediate!("{} contains an empty list of supported targets. Please add at least one and try again.\nAvailable options: win32, win64.", config_path.as_os_str().to_string_lossy());
+                if matches!(config.output_type, OutputType::DynamicLibrary | OutputType::StaticLibrary) && matches!(sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
+                    let sub_command_name = match sub_command {
+                        Subcommand::Run(_) => "run",
+                        Subcommand::Debug(_) => "debug",
+                        _ => unreachable!(),
+                    };
+                    fail_immediate!("`{}` subcommand not supported for library projects. Consider using the `build` subcommand and linking the result in another executable.", sub_command_name);
                 }
-                // TODO: speed
-                let unique_supported_targets: HashSet<_> = config.supported_targets.iter().cloned().collect();
-                if unique_supported_targets.len() < config.supported_targets.len() {
-                    fail_immediate!("{} contains one or more duplicates in its list of supported targets. Please ensure that each target is unique.\nThe supported platforms listed are: {:?}", config_path.as_os_str().to_string_lossy(), config.supported_targets);
+                if build_options.subsystem.is_some() && config.output_type.subsystem(None).is_none() {
+                    // A workspace may mix libraries and apps, so the override only applies to the apps
+                    if workspace_member {
+                        println!("Warning: `--subsystem` doesn't apply to library project \"{}\", because libraries aren't linked for a subsystem.", config.name);
+                    } else {
+                        fail_immediate!("`--subsystem` is not supported for library projects, because libraries aren't linked for a subsystem.");
+                    }
                 }
 
-                (config_path, config)
-            }
-            let (config_path, config) = load_config(Path::new("."));
-
-            if matches!(config.output_type, OutputType::DynamicLibrary | OutputType::StaticLibrary) && matches!(options.sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
-                let sub_command_name = match options.sub_command {
-                    Subcommand::Run(_) => "run",
-                    Subcommand::Debug(_) => "debug",
-                    _ => unreachable!(),
+                let config_path = match canonicalize(config_path) {
+                    Ok(canon) => canon,
+                    Err(_) => fail_immediate!("Failed to get canonical path for project config file"),
                 };
-                fail_immediate!("`{}` subcommand not supported for library projects. Consider using the `build` subcommand and linking the result in another executable.", sub_command_name);
-            }
 
-            struct Project {
-                config_path: PathBuf,
-                config: ProjectConfig,
-                ref_count: u32,
-                dep_names: Vec<String>,
-                visited: bool,
-            }
+                let mut problems = Vec::new();
+                let mut projects = load_project_graph(config_path, &config, &mut problems);
+                fail_on_problems(&mut problems);
 
-            let mut projects = HashMap::<String, Project>::new();
-            let config_path = match canonicalize(config_path) {
-                Ok(canon) => canon,
-                Err(_) => fail_immediate!("Failed to get canonical path for project config file"),
-            };
-            projects.insert(config.name.clone(), Project { config_path: config_path.clone(), config: config.clone(), ref_count: 1, dep_names: Vec::new(), visited: false });
+                let mut link_libraries = HashSet::<PathBuf>::new();
+                let mut target_link_libraries = HashMap::new();
+                let mut library_paths = Vec::new();
+                let mut runtime_libraries = Vec::new();
+                validate_dependencies(&mut projects, &mut link_libraries, &mut target_link_libraries, &mut library_paths, &mut runtime_libraries, &config.name, &mut problems);
+                validate_cxx_options(&projects, &config, &[build_options.compile_mode], &mut problems);
+                validate_dependency_defines(&projects, &config, &mut problems);
+                fail_on_problems(&mut problems);
 
-            fn accumulate_dependencies(projects: &mut HashMap<String, Project>, config_path: PathBuf, config: &ProjectConfig) {
-                let mut root_path = config_path.clone();
-                root_path.pop();
+                async fn build_all<'a>(target: Platform, build_options: &BuildOptions, action: Action<'_>, dependencies: impl IntoIterator<Item=&'a mut Project>, root_project: &mut Project, link_libraries: &[PathBuf], state: &mut BuildState) -> Option<(PathBuf, ToolchainPaths)> {
+                    fn artifact_path(target: Platform, build_options: &BuildOptions, config: &ProjectConfig) -> PathBuf {
+                        config.artifact_path(build_options.compile_mode.name(), target)
+                    }
+                    async fn build(target: Platform, build_options: &BuildOptions, config: &ProjectConfig, config_path: &Path, extra_definitions: &[(&str, &str)], dependency_libraries: &[PathBuf], action: Action<'_>) -> Result<(Option<PathBuf>, ToolchainPaths), BuildError> {
+                        let mode = build_options.compile_mode.name();
+                        let verb = match action {
+                            Action::GenerateNinja => "Generating a Ninja build file for",
+                            Action::IncludeWhatYouUse => "Running include-what-you-use on",
+                            Action::Prime => "Priming caches for",
+                            Action::Build | Action::Package(_) => "Building",
+                        };
+                        println!("{} \"{}\" for target {:?} in {} mode", verb, config.name, target, mode);
+    
+                        let toolchain_paths = ToolchainPaths::find(target)?;
+                        let artifact_path = artifact_path(target, build_options, config);
 
-                let canonical_deps: Vec<PathBuf> = config.dependencies.iter()
-                    .map(|dep| {
-                        if dep.components().count() == 0 {
-                            fail_immediate!("Empty path found as dependency in project \"{}\"", config.name);
+                        let mut definitions = project_definitions(config, extra_definitions);
+                        let definitions_file = build_options.define_from_file.as_ref()
+                            .map(|path| {
+                                fs::read_to_string(path)
+                                    .map(|contents| (path, contents))
+                                    .map_err(|_| BuildError::UnreadableDefinitionsFile(path.clone()))
+                            })
+                            .transpose()?;
+                        if let Some((path, contents)) = &definitions_file {
+                            definitions.extend(build::parse_definitions(path, contents)?);
                         }
-                        let dep = root_path.join(dep);
-                        match canonicalize(&dep) {
-                            Ok(canon) => canon,
-                            Err(error) => fail_immediate!("Failed to get canonical path for dependency \"{}\": {}", dep.as_os_str().to_string_lossy(), error),
+        
+                        let mut env = BuildEnvironment::new(
+                            config,
+                            config_path,
+                            build_options,
+                            target,
+                            &toolchain_paths,
+                            &definitions,
+                            &artifact_path,
+                        )?;
+                        env.dependency_libraries = dependency_libraries.to_vec();
+        
+                        if let Action::GenerateNinja = action {
+                            env.generate_ninja()?;
+                            return Ok((Some(artifact_path.join(env.product_name())), toolchain_paths));
                         }
-                    }).collect();
-                let unique_deps: HashSet<&PathBuf> = canonical_deps.iter().collect();
-                if unique_deps.len() < canonical_deps.len() {
-                    fail_immediate!("{} contains one or more duplicates in its dependencies array", config_path.as_os_str().to_string_lossy());
-                }
-                let mut dep_names = Vec::new();
-                for dependency in &canonical_deps {
-                    let (dep_config_path, dep_config) = load_config(dependency);
-                    let proj = projects
-                        .entry(dep_config.name.clone())
-                        .or_insert_with(|| {
-                            Project {
-                                config_path: dep_config_path.clone(),
-                                config: dep_config.clone(),
-                                ref_count: 0,
-                                dep_names: Vec::new(),
-                                visited: false,
-                            }
-                        });
-                    proj.ref_count += 1;
-                    // TODO: This is a massive hack! Should think of a more principled way of finding loops.
-                    if proj.ref_count > 100 {
-                        fail_immediate!("Loop found in dependency graph.");
-                    }
-                    if dep_config_path != proj.config_path {
-                        fail_immediate!("Two projects in dependency graph found with the same name, \"{}\"", proj.config.name);
-                    }
-                    dep_names.push(proj.config.name.clone());
-
-                    accumulate_dependencies(projects, dep_config_path, &dep_config);
-                }
-
-                projects.get_mut(&config.name).unwrap().dep_names = dep_names;
-            }
-            accumulate_dependencies(&mut projects, config_path.clone(), &config);
-
-            let mut link_libraries = HashSet::<String>::new();
-            let cxx_options = config.cxx_options;
-            fn validate_dependencies(projects: &mut HashMap<String, Project>, link_libraries: &mut HashSet<String>, name: &str, root_cxx_options: CxxOptions, root_name: &str) {
-                let proj = projects.get(name).unwrap();
-                let supported_targets = proj.config.supported_targets.clone();
-                if proj.visited {
-                    return;
-                }
-
-                for dep in proj.dep_names.clone() {
-                    validate_dependencies(projects, link_libraries, &dep, root_cxx_options, root_name);
-                    let dep = projects.get(&dep).unwrap();
-                    if !matches!(dep.config.output_type, OutputType::StaticLibrary) {
-                        let dep_type = match dep.config.output_type {
-                            OutputType::GuiApp => "GUI app",
-                            OutputType::ConsoleApp => "console app",
-                            OutputType::DynamicLibrary => "dynamic library",
-                            OutputType::StaticLibrary => panic!(),
+                        if let Action::IncludeWhatYouUse = action {
+                            env.include_what_you_use().await?;
+                            return Ok((Some(artifact_path.join(env.product_name())), toolchain_paths));
+                        }
+                        if let Action::Prime = action {
+                            env.prime().await?;
+                            return Ok((Some(artifact_path.join(env.product_name())), toolchain_paths));
+                        }
+        
+                        let produced_artifact = env.build().await?;
+                        let product_path = if produced_artifact {
+                            Some(artifact_path.join(env.product_name()))
+                        } else {
+                            None
                         };
-                        let proj = projects.get(name).unwrap();
-                        fail_immediate!("Project \"{}\" depends on \"{}\", a {}. Only static library dependencies are supported at this time.", proj.config.name, dep.config.name, dep_type);
-                    }
-                    if !dep.config.cxx_options.is_compatible_with(&root_cxx_options) {
-                        fail_immediate!("{}'s C++ options are incompatible with those of the root project \"{}\".", dep.config.name, name);
+                        if let Action::Package(package) = action {
+                            let zip_path = env.package(!package.no_pdb)?;
+                            println!("Packaged \"{}\" to {}", config.name, zip_path.as_os_str().to_string_lossy());
+                        }
+                        Ok((product_path, toolchain_paths))
+    
                     }
-                    for platform in &supported_targets {
-                        if !dep.config.supported_targets.contains(platform) {
-                            fail_immediate!("{} claims to support target {:?}, but its dependency {} does not.", name, platform, dep.config.name);
+                    let mut dependency_keys = Vec::new();
+                    let mut dependency_failed = false;
+                    let mut to_build = Vec::new();
+                    for project in dependencies {
+                        let key = (project.config.name.clone(), target, build_options.compile_mode);
+                        if state.has_failed(&key) {
+                            dependency_failed = true;
+                            continue;
+                        }
+                        project.config.adapt_to_workspace(&root_project.config);
+                        let definitions = root_project.config.dependency_definitions(&project.config.name);
+                        if let Err(error) = state.build_for(&key, &root_project.config.name, workspace_settings(&project.config, &definitions)) {
+                            fail_immediate!("{}", error);
+                        }
+                        // Dependencies shared between workspace members only need to be built once
+                        if !state.built_projects.contains_key(&key) {
+                            let project: &Project = project;
+                            to_build.push((key.clone(), project, definitions));
                         }
+                        dependency_keys.push(key);
                     }
-                }
-                
-                let proj = projects.get_mut(name).unwrap();
-                link_libraries.extend(proj.config.link_libraries.iter().cloned());
-                proj.visited = true;
-            }
-            validate_dependencies(&mut projects, &mut link_libraries, &config.name, cxx_options, &config.name);
-
-            async fn build_all<'a>(target: Platform, build_options: &BuildOptions, dependencies: impl IntoIterator<Item=&'a mut Project>, root_project: &mut Project, link_libraries: &[String]) -> (PathBuf, ToolchainPaths) {
-                async fn build(target: Platform, build_options: &BuildOptions, config: &ProjectConfig, config_path: &Path) -> (Option<PathBuf>, ToolchainPaths) {
-                    let mode = match build_options.compile_mode {
-                        CompileMode::Debug => "debug",
-                        CompileMode::Release => "release",
-                    };
-                    println!("Building \"{}\" for target {:?} in {} mode", config.name, target, mode);
-    
-                    let toolchain_paths = ToolchainPaths::find(target).unwrap();            
-                    // Create abs/debug or abs/release, if it doesn't exist already
-                    let mut artifact_path: PathBuf = ["abs", mode, &config.name].iter().collect();
-                    artifact_path.push(format!("{:?}", target));
-        
-                    let mut env = BuildEnvironment::new(
-                        config,
-                        config_path,
-                        build_options,
-                        &toolchain_paths,
-                        // TODO: make these configurable
-                        &[("_WINDOWS", ""), ("WIN32", ""), ("UNICODE", ""), ("_USE_MATH_DEFINES", "")],
-                        &artifact_path,
-                    ).unwrap();
-        
-                    match env.build().await {
-                        Ok(produced_artifact) => {
-                            let artifact_path = if produced_artifact {
-                                Some(artifact_path)
-                            } else {
-                                None
+                    // Dependencies only need each other's sources (for their headers), not each other's products, so
+                    // they're all built at once. E.g., their precompiled headers are generated concurrently.
+                    let results = build_concurrently(to_build, |(key, project, definitions)| async move {
+                        let artifact_path = artifact_path(target, build_options, &project.config);
+                        let unchanged_product_path = artifact_path.join(project.config.product_name(build_options.compile_mode.name(), target));
+                        // Setting up a build for a dependency is expensive, even when there is nothing to
+                        // rebuild. So, skip it if nothing that goes into the dependency has changed since it
+                        // was last built.
+                        let fingerprint_path = artifact_path.join("inputs_fingerprint");
+                        let fingerprint = build::inputs_fingerprint(&project.config, &project.config_path, &definitions, build_options)
+                            .ok()
+                            .map(|fingerprint| format!("{:016x}", fingerprint));
+                        let unchanged = !matches!(action, Action::GenerateNinja | Action::Prime) &&
+                            is_up_to_date(&fingerprint_path, fingerprint.as_deref(), &unchanged_product_path);
+                        let result = if unchanged {
+                            println!("\"{}\" for target {:?} is up to date", project.config.name, target);
+                            Ok(Some(unchanged_product_path))
+                        } else {
+                            let dependency_action = match action {
+                                Action::GenerateNinja => Action::GenerateNinja,
+                                // Dependencies' caches are worth warming too, and don't depend on the products
+                                // of their own dependencies
+                                Action::Prime => Action::Prime,
+                                // Dependencies are only built, so that their headers are available to the root project
+                                Action::Build | Action::Package(_) | Action::IncludeWhatYouUse => Action::Build,
                             };
-                            (artifact_path, toolchain_paths)
+                            build(target, build_options, &project.config, &project.config_path, &definitions, &[], dependency_action).await
+                                .map(|(product_path, _)| product_path)
+                        };
+                        if let Err(error) = &result {
+                            println!("Build of \"{}\" for target {:?} failed: {}.", project.config.name, target, error);
                         }
-                        Err(error) => env.fail(error)
-                    }
-    
-                }
-                let mut link_libraries = Vec::from(link_libraries);
-                for project in dependencies {
-                    project.config.adapt_to_workspace(&root_project.config);
-                    let (artifact_path, _) = build(target, build_options, &project.config, &project.config_path).await;
-                    if let Some(mut artifact_path) = artifact_path {
-                        artifact_path.push(format!("{}.lib", project.config.name));
-                        link_libraries.push(artifact_path.as_os_str().to_string_lossy().into());
-                    }
-                    // Add spacing between projects
-                    println!();
-                }
-                root_project.config.link_libraries = link_libraries;
-                let (artifact_path, toolchain_paths) = build(target, build_options, &root_project.config, &root_project.config_path).await;
-                (artifact_path.unwrap(), toolchain_paths)
-            }
-            let mut root_project = projects.remove(&config.name).unwrap();
-            let mut dependencies: Vec<Project> = projects.into_iter().map(|(_, val)| val).collect();
-            let link_libraries: Vec<String> = link_libraries.into_iter().collect();
-
-            let host = Platform::host();
-            let specified_target: Target = build_options.target.into();
-            match specified_target {
-                Target::All => {
-                    if matches!(options.sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
-                        let sub_command_name = match options.sub_command {
-                            Subcommand::Run(_) => "run",
-                            Subcommand::Debug(_) => "debug",
-                            _ => unreachable!(),
+                        // Add spacing between projects
+                        println!();
+                        let fingerprint = if unchanged || matches!(action, Action::GenerateNinja | Action::Prime) {
+                            None
+                        } else {
+                            fingerprint.map(|fingerprint| (fingerprint_path, fingerprint))
                         };
-                        fail_immediate!("Target `all` is not valid for `{}` subcommand. Please use the `build` subcommand instead.", sub_command_name);
-                    } else {
-                        for &supported_target in &config.supported_targets {
-                            build_all(supported_target, build_options, &mut dependencies, &mut root_project, &link_libraries).await;
+                        (key, fingerprint, result)
+                    }).await;
+                    for (key, fingerprint, result) in results {
+                        match result {
+                            Ok(product_path) => {
+                                if let (Action::GenerateNinja, Some(product_path)) = (action, &product_path) {
+                                    state.ninja_files.push(product_path.with_file_name("build.ninja"));
+                                }
+                                if let Some((fingerprint_path, fingerprint)) = fingerprint {
+                                    // Failing to record the fingerprint only means that the next build can't be
+                                    // skipped
+                                    let _ = fs::write(&fingerprint_path, fingerprint);
+                                }
+                                state.built_projects.insert(key, product_path);
+                            },
+                            Err(_) => {
+                                if !build_options.keep_going {
+                                    state.outcomes.push((root_project.config.name.clone(), target, build_options.compile_mode, Some(format!("dependency \"{}\" failed to build", key.0))));
+                                    state.print_summary();
+                                    _task_failed!();
+                                }
+                                state.fail(key);
+                                dependency_failed = true;
+                            }
                         }
-                        return;
                     }
-                },
-                Target::Host => {
-                    let mut target = host;
-                    let mut can_run_on_host = true;
-                    // If the host isn't a supported target, then pick target with which the host is
-                    // backwards compatible.
-                    if !config.supported_targets.contains(&target) {
-                        can_run_on_host = false;
-                        let compatible = config.supported_targets.iter().cloned()
-                            .find(|&supported_target| host.is_backwards_compatible_with(supported_target));
-                        if let Some(compatible) = compatible {
-                            target = compatible;
-                            can_run_on_host = true;
+                    let dependency_libraries: Vec<PathBuf> = dependency_keys.iter()
+                        .filter_map(|key| state.built_projects.get(key).cloned().flatten())
+                        .collect();
+                    let key = (root_project.config.name.clone(), target, build_options.compile_mode);
+                    if dependency_failed {
+                        println!("Skipping \"{}\" for target {:?} because one or more of its dependencies failed to build.\n", root_project.config.name, target);
+                        state.outcomes.push((root_project.config.name.clone(), target, build_options.compile_mode, Some("one or more dependencies failed to build".to_string())));
+                        state.fail(key);
+                        return None;
+                    }
+                    root_project.config.link_libraries = link_libraries.to_vec();
+                    if let Err(error) = state.build_for(&key, &root_project.config.name, workspace_settings(&root_project.config, &[])) {
+                        fail_immediate!("{}", error);
+                    }
+                    match build(target, build_options, &root_project.config, &root_project.config_path, &[], &dependency_libraries, action).await {
+                        Ok((product_path, toolchain_paths)) => {
+                            if let (Action::GenerateNinja, Some(product_path)) = (action, &product_path) {
+                                state.ninja_files.push(product_path.with_file_name("build.ninja"));
+                            }
+                            state.built_projects.insert(key, product_path.clone());
+                            state.outcomes.push((root_project.config.name.clone(), target, build_options.compile_mode, None));
+                            Some((product_path.unwrap(), toolchain_paths))
+                        },
+                        Err(error) => {
+                            println!("Build of \"{}\" for target {:?} failed: {}.", root_project.config.name, target, error);
+                            state.outcomes.push((root_project.config.name.clone(), target, build_options.compile_mode, Some(error.to_string())));
+                            if !build_options.keep_going {
+                                state.print_summary();
+                                _task_failed!();
+                            }
+                            state.fail(key);
+                            None
                         }
                     }
+                }
+                let mut root_project = projects.remove(&config.name).unwrap();
+                root_project.config.target_link_libraries = target_link_libraries;
+                root_project.config.library_paths = library_paths;
+                root_project.config.runtime_libraries = runtime_libraries;
+                let mut dependencies: Vec<Project> = projects.into_values().collect();
+                let link_libraries: Vec<PathBuf> = link_libraries.into_iter().collect();
 
-                    if !can_run_on_host {
-                        if matches!(options.sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
-                            let sub_command_name = match options.sub_command {
+                let host = Platform::host();
+                match build_options.target(config.default_target) {
+                    Target::All => {
+                        if matches!(sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
+                            let sub_command_name = match sub_command {
                                 Subcommand::Run(_) => "run",
                                 Subcommand::Debug(_) => "debug",
                                 _ => unreachable!(),
                             };
-                            fail_immediate!("`{}` subcommand cannot proceed because your host platform, {:?}, is not compatible with any of the supported targets in this project's abs.json.\nThe supported platforms listed are: {:?}", sub_command_name, host, config.supported_targets);
+                            fail_immediate!("Target `all` is not valid for `{}` subcommand. Please use the `build` subcommand instead.", sub_command_name);
                         } else {
-                            // Don't need to run, so if there is only one target supported, choose it regardless
-                            // of compatibility.
-                            if config.supported_targets.len() == 1 {
-                                target = config.supported_targets[0];
+                            for &supported_target in &config.supported_targets {
+                                build_all(supported_target, build_options, action, &mut dependencies, &mut root_project, &link_libraries, state).await;
+                            }
+                            None
+                        }
+                    },
+                    Target::Host => {
+                        let mut target = host;
+                        let mut can_run_on_host = true;
+                        // If the host isn't a supported target, then pick target with which the host is
+                        // backwards compatible.
+                        if !config.supported_targets.contains(&target) {
+                            can_run_on_host = false;
+                            let compatible = config.supported_targets.iter().cloned()
+                                .find(|&supported_target| host.is_backwards_compatible_with(supported_target));
+                            if let Some(compatible) = compatible {
+                                target = compatible;
+                                can_run_on_host = true;
+                            }
+                        }
+
+                        if !can_run_on_host {
+                            if matches!(sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
+                                let sub_command_name = match sub_command {
+                                    Subcommand::Run(_) => "run",
+                                    Subcommand::Debug(_) => "debug",
+                                    _ => unreachable!(),
+                                };
+                                fail_immediate!("`{}` subcommand cannot proceed because your host platform, {:?}, is not compatible with any of the supported targets in this project's abs.json.\nThe supported platforms listed are: {:?}", sub_command_name, host, config.supported_targets);
                             } else {
-                                fail_immediate!("Unable to choose a target platform, because there is more than one supported target in this project's abs.json, and none of them are compatible with your host. Please consider specifying a target on the command line (not yet supported).\nThe supported platforms listed are: {:?}", config.supported_targets);
+                                // Don't need to run, so if there is only one target supported, choose it regardless
+                                // of compatibility.
+                                if config.supported_targets.len() == 1 {
+                                    target = config.supported_targets[0];
+                                } else {
+                                    fail_immediate!("Unable to choose a target platform, because there is more than one supported target in this project's abs.json, and none of them are compatible with your host. Please consider specifying a target on the command line (not yet supported).\nThe supported platforms listed are: {:?}", config.supported_targets);
+                                }
                             }
                         }
-                    }
-                    let (artifact_path, toolchain_paths) = build_all(target, build_options, &mut dependencies, &mut root_project, &link_libraries).await;
-                    (config, artifact_path, toolchain_paths)
-                },
-                Target::Platform(target) => {
-                    if !config.supported_targets.contains(&target) {
-                        fail_immediate!("Cannot build for target {:?} because it is not listed as a supported platform in this project's abs.json.\nThe supported platforms listed are: {:?}", target, config.supported_targets);
-                    }
+                        build_all(target, build_options, action, &mut dependencies, &mut root_project, &link_libraries, state).await
+                            .map(|(product_path, toolchain_paths)| (config, product_path, toolchain_paths))
+                    },
+                    Target::Platform(target) => {
+                        if !config.supported_targets.contains(&target) {
+                            fail_immediate!("Cannot build for target {:?} because it is not listed as a supported platform in this project's abs.json.\nThe supported platforms listed are: {:?}", target, config.supported_targets);
+                        }
 
-                    if !host.is_backwards_compatible_with(target) && matches!(options.sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
-                        let sub_command_name = match options.sub_command {
-                            Subcommand::Run(_) => "run",
-                            Subcommand::Debug(_) => "debug",
-                            _ => unreachable!(),
-                        };
-                        fail_immediate!("`{}` subcommand cannot proceed because your host platform, {:?}, is not compatible with the supplied target {:?}. Please use the `build` subcommand instead.", sub_command_name, host, target);
+                        if !host.is_backwards_compatible_with(target) && matches!(sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
+                            let sub_command_name = match sub_command {
+                                Subcommand::Run(_) => "run",
+                                Subcommand::Debug(_) => "debug",
+                                _ => unreachable!(),
+                            };
+                            fail_immediate!("`{}` subcommand cannot proceed because your host platform, {:?}, is not compatible with the supplied target {:?}. Please use the `build` subcommand instead.", sub_command_name, host, target);
+                        }
+
+                        build_all(target, build_options, action, &mut dependencies, &mut root_project, &link_libraries, state).await
+                            .map(|(product_path, toolchain_paths)| (config, product_path, toolchain_paths))
                     }
+                }
+            }
 
-                    let (artifact_path, toolchain_paths) = build_all(target, build_options, &mut dependencies, &mut root_project, &link_libraries).await;
-                    (config, artifact_path, toolchain_paths)
+            let mut state = BuildState::default();
+            macro_rules! fail_if_any_project_failed {
+                () => {
+                    state.print_summary();
+                    if let Some(report) = state.failure_report() {
+                        println!("{}", report);
+                        _task_failed!();
+                    }
+                }
+            }
+            macro_rules! write_root_ninja_file_if_generating {
+                () => {
+                    if let Subcommand::Generate(_) = options.sub_command {
+                        match ninja::write_root_ninja_file(&state.ninja_files) {
+                            Ok(path) => println!("Generated {}. Run `ninja` in this directory to build.", path.as_os_str().to_string_lossy()),
+                            Err(error) => fail_immediate!("Unable to write Ninja build file: {}", error),
+                        }
+                        return;
+                    }
                 }
             }
+            if build_options.both && matches!(options.sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
+                let sub_command_name = match options.sub_command {
+                    Subcommand::Run(_) => "run",
+                    Subcommand::Debug(_) => "debug",
+                    _ => unreachable!(),
+                };
+                fail_immediate!("`--both` is not valid for `{}` subcommand. Please use the `build` subcommand instead.", sub_command_name);
+            }
+            let mode_build_options = build_options.per_mode();
+            if let Some(workspace) = load_workspace() {
+                if matches!(options.sub_command, Subcommand::Run(_) | Subcommand::Debug(_)) {
+                    let sub_command_name = match options.sub_command {
+                        Subcommand::Run(_) => "run",
+                        Subcommand::Debug(_) => "debug",
+                        _ => unreachable!(),
+                    };
+                    fail_immediate!("`{}` subcommand not supported for workspaces. Please run it from the directory of the member project you want to {}.", sub_command_name, sub_command_name);
+                }
+                for build_options in &mode_build_options {
+                    for member in &workspace.members {
+                        build_root(member, true, &options.sub_command, build_options, &mut state).await;
+                    }
+                }
+                fail_if_any_project_failed!();
+                write_root_ninja_file_if_generating!();
+                return;
+            }
+            if build_options.both {
+                for build_options in &mode_build_options {
+                    build_root(Path::new("."), false, &options.sub_command, build_options, &mut state).await;
+                }
+                fail_if_any_project_failed!();
+                write_root_ninja_file_if_generating!();
+                return;
+            }
+            let result = build_root(Path::new("."), false, &options.sub_command, build_options, &mut state).await;
+            fail_if_any_project_failed!();
+            write_root_ninja_file_if_generating!();
+            match result {
+                Some(result) => result,
+                None => return,
+            }
         },
-        Subcommand::Clean => {
-            for &mode in ["debug", "release"].iter() {
-                if let Err(error) = fs::remove_dir_all(Path::new("abs/").join(mode)) {
+        Subcommand::Clean { objects, warnings, src_deps, library_index } => {
+            let caches: Vec<gc::Cache> = [(objects, gc::Cache::Objects), (warnings, gc::Cache::Warnings), (src_deps, gc::Cache::SrcDeps), (library_index, gc::Cache::LibraryIndex)]
+                .iter()
+                .filter_map(|&(selected, cache)| selected.then_some(cache))
+                .collect();
+            if !caches.is_empty() {
+                for cache in caches {
+                    match gc::remove_cache(Path::new("."), cache) {
+                        Ok(reclaimed) if reclaimed.files > 0 => println!("Removed {}: {} files, {}.", cache.description(), format_count(reclaimed.files), format_size(reclaimed.bytes)),
+                        Ok(_) => println!("No {} to remove.", cache.description()),
+                        Err(error) => fail_immediate!("Failed to clean: {:?}.", error.kind()),
+                    }
+                }
+                return;
+            }
+            let mut found_artifacts = false;
+            let mut removed_files = 0;
+            let mut removed_bytes = 0;
+            for mode in CompileMode::ALL {
+                let mode_path = Path::new("abs/").join(mode.name());
+                // Intermediate files built under `--scratch-dir` live outside of the artifact directories
+                // (abs/{mode}/{project}/{target}), which record where
+                let scratch_paths: Vec<PathBuf> = fs::read_dir(&mode_path).into_iter().flatten().flatten()
+                    .flat_map(|project| fs::read_dir(project.path()).into_iter().flatten().flatten())
+                    .map(|target| target.path())
+                    .filter_map(|artifact_path| {
+                        let intermediates_path = build::intermediates_path(&artifact_path);
+                        (intermediates_path != artifact_path).then_some(intermediates_path)
+                    })
+                    .collect();
+                for scratch_path in &scratch_paths {
+                    if let Ok((files, bytes)) = dir_usage(scratch_path) {
+                        found_artifacts = true;
+                        removed_files += files;
+                        removed_bytes += bytes;
+                    }
+                    if let Err(error) = fs::remove_dir_all(scratch_path) {
+                        match error.kind() {
+                            IoErrorKind::NotFound => {},
+                            error => fail_immediate!("Failed to clean: {:?}.", error),
+                        }
+                    }
+                }
+                match dir_usage(&mode_path) {
+                    Ok((files, bytes)) => {
+                        found_artifacts = true;
+                        removed_files += files;
+                        removed_bytes += bytes;
+                    },
+                    Err(error) => match error.kind() {
+                        IoErrorKind::NotFound => continue,
+                        error => fail_immediate!("Failed to clean: {:?}.", error),
+                    }
+                }
+                if let Err(error) = fs::remove_dir_all(&mode_path) {
                     match error.kind() {
                         IoErrorKind::NotFound => {},
                         error => fail_immediate!("Failed to clean: {:?}.", error),
                     }
                 }
             }
-            println!("Cleaned successfully.");
+            if found_artifacts {
+                println!("Cleaned successfully. Removed {} files, {}.", format_count(removed_files), format_size(removed_bytes));
+            } else {
+                println!("Nothing to clean.");
+            }
+            return;
+        },
+        Subcommand::Gc => {
+            let projects = load_all_projects();
+            let reclaimed = gc::collect_garbage(Path::new("."), projects.values().map(|project| (&project.config, project.config_path.as_path())))
+                .unwrap_or_else(|error| fail_immediate!("Failed to collect garbage: {}.", error));
+            if reclaimed.files > 0 {
+                println!("Removed {} unused files, {}.", format_count(reclaimed.files), format_size(reclaimed.bytes));
+            } else {
+                println!("Nothing to remove.");
+            }
             return;
         },
         Subcommand::Kill => {
@@ -500,29 +1430,719 @@ void print_hello_world() {{
             println!("Successfully killed debugger.");
             return;
         },
+        Subcommand::Info => {
+            let (_, config) = load_config(Path::new("."));
+            println!("Name: {}", config.name);
+            if let Some(version) = &config.version {
+                println!("Version: {}", version);
+            }
+            if let Some(description) = &config.description {
+                println!("Description: {}", description);
+            }
+            if !config.authors.is_empty() {
+                println!("Authors: {}", config.authors.join(", "));
+            }
+            println!("Output type: {:?}", config.output_type);
+            println!("Supported targets: {:?}", config.supported_targets);
+            if !config.dependencies.is_empty() {
+                let dependencies: Vec<_> = config.dependencies.iter()
+                    .map(|dep| dep.as_os_str().to_string_lossy())
+                    .collect();
+                println!("Dependencies: {}", dependencies.join(", "));
+            }
+            return;
+        },
+        Subcommand::Fmt { check } => {
+            let (_, config) = load_config(Path::new("."));
+            let src_paths = SrcPaths::from_root("src")
+                .unwrap_or_else(|error| fail_immediate!("Unable to read src directory: {}.", error));
+            let mut paths: Vec<&Path> = src_paths.all_src_paths();
+            paths.extend(src_paths.all_header_paths());
+            paths.extend(config.extra_sources.iter().map(PathBuf::as_path));
+            paths.sort();
+
+            // Prefer the clang-format that ships with the toolchain, then fall back to the one in PATH
+            let clang_format = ToolchainPaths::find(Platform::host()).ok()
+                .and_then(|toolchain_paths| toolchain_paths.clang_format_path)
+                .unwrap_or_else(|| PathBuf::from("clang-format"));
+            let mut unformatted_paths = Vec::new();
+            for path in paths {
+                let mut command = Command::new(&clang_format);
+                // Uses the nearest .clang-format file, if there is one
+                command.arg("-style=file");
+                if *check {
+                    command.args(["--dry-run", "-Werror"])
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null());
+                } else {
+                    command.arg("-i");
+                }
+                let status = command.arg(path).status()
+                    .unwrap_or_else(|error| fail_immediate!("Unable to run clang-format: {}. Please make sure that it is installed and in your PATH.", error));
+                if !status.success() {
+                    unformatted_paths.push(path);
+                }
+            }
+            if unformatted_paths.is_empty() {
+                if *check {
+                    println!("All files are formatted correctly.");
+                }
+            } else {
+                if *check {
+                    println!("The following files need to be formatted:");
+                } else {
+                    println!("clang-format failed on the following files:");
+                }
+                for path in unformatted_paths {
+                    println!("    {}", path.as_os_str().to_string_lossy());
+                }
+                _task_failed!();
+            }
+            return;
+        },
+        Subcommand::Targets { json } => {
+            let (_, config) = load_config(Path::new("."));
+            let host = Platform::host();
+            let is_executable = matches!(config.output_type, OutputType::GuiApp | OutputType::ConsoleApp);
+            // Any supported target can be built, but only those the host is compatible with can be run or debugged
+            let targets = config.supported_targets.iter()
+                .map(|&target| {
+                    let host_compatible = host.is_backwards_compatible_with(target);
+                    (target, host_compatible, host_compatible && is_executable)
+                });
+            if *json {
+                let targets: Vec<_> = targets
+                    .map(|(target, host_compatible, can_run)| serde_json::json!({
+                        "target": target,
+                        "host_compatible": host_compatible,
+                        "can_run": can_run,
+                    }))
+                    .collect();
+                let output = serde_json::json!({
+                    "host": host,
+                    "targets": targets,
+                });
+                println!("{}", serde_json::to_string_pretty(&output).unwrap());
+            } else {
+                println!("Host: {:?}", host);
+                println!("Supported targets:");
+                for (target, _, can_run) in targets {
+                    println!("    {:?}: {}", target, if can_run { "build, run, debug" } else { "build only" });
+                }
+            }
+            return;
+        },
+        Subcommand::Validate { verbose } => {
+            let roots = match load_workspace() {
+                Some(workspace) => workspace.members,
+                None => vec![PathBuf::from(".")],
+            };
+            let mut problems = Vec::new();
+            // The targets each project supports, and those that some root project actually builds it for
+            let mut targets: HashMap<String, (Vec<Platform>, HashSet<Platform>)> = HashMap::new();
+            for root in &roots {
+                let (config_path, config) = match read_config(root, &mut problems) {
+                    Some(result) => result,
+                    None => continue,
+                };
+                let config_path = match canonicalize(&config_path) {
+                    Ok(canon) => canon,
+                    Err(_) => {
+                        problems.push("Failed to get canonical path for project config file".to_string());
+                        continue;
+                    },
+                };
+                let mut projects = load_project_graph(config_path, &config, &mut problems);
+                validate_dependencies(&mut projects, &mut HashSet::new(), &mut HashMap::new(), &mut Vec::new(), &mut Vec::new(), &config.name, &mut problems);
+                validate_cxx_options(&projects, &config, &CompileMode::ALL, &mut problems);
+                validate_dependency_defines(&projects, &config, &mut problems);
+                for project in projects.values() {
+                    let (_, needed_targets) = targets.entry(project.config.name.clone())
+                        .or_insert_with(|| (project.config.supported_targets.clone(), HashSet::new()));
+                    needed_targets.extend(config.supported_targets.iter().copied());
+                }
+                let mut config_paths: Vec<&PathBuf> = projects.values().map(|project| &project.config_path).collect();
+                config_paths.sort();
+                for config_path in config_paths {
+                    problems.extend(unknown_fields(config_path));
+                }
+            }
+            if *verbose {
+                let mut names: Vec<&String> = targets.keys().collect();
+                names.sort();
+                for name in names {
+                    let (supported_targets, needed_targets) = &targets[name];
+                    let unneeded_targets: Vec<&Platform> = supported_targets.iter()
+                        .filter(|target| !needed_targets.contains(target))
+                        .collect();
+                    if !unneeded_targets.is_empty() {
+                        println!("Note: \"{}\" supports targets {:?} that no project depending on it is built for. Consider removing them from its supported targets.", name, unneeded_targets);
+                    }
+                }
+            }
+            dedup_problems(&mut problems);
+            if problems.is_empty() {
+                println!("No problems found.");
+            } else {
+                println!("Found {} {}:", problems.len(), if problems.len() == 1 { "problem" } else { "problems" });
+                fail_on_problems(&mut problems);
+            }
+            return;
+        },
+        Subcommand::Toolchain { target } => {
+            let target = match Target::from(*target) {
+                Target::Platform(target) => target,
+                Target::Host => Platform::host(),
+                Target::All => fail_immediate!("Target `all` is not valid for `toolchain` subcommand. Please specify a single target."),
+            };
+            let toolchain_paths = ToolchainPaths::find(target)
+                .unwrap_or_else(|error| fail_immediate!("Unable to find the toolchain for target {:?}: {}.", target, error));
+            print!("{}", describe_toolchain(target, &toolchain_paths));
+            if let Some(tool) = toolchain_paths.missing_tool() {
+                fail_immediate!("{} was not found in any of the binary paths. Your toolchain installation may be incomplete.", tool);
+            }
+            return;
+        },
+        Subcommand::Describe { compile_mode, format } => {
+            let projects = load_all_projects();
+
+            let mut projects: Vec<&Project> = projects.values().collect();
+            projects.sort_by(|a, b| a.config.name.cmp(&b.config.name));
+            let descriptions: Vec<_> = projects.into_iter()
+                .map(|project| {
+                    describe::describe_project(&project.config, &project.config_path, project.dep_names.clone(), *compile_mode)
+                        .unwrap_or_else(|error| fail_immediate!("Unable to read the sources of \"{}\": {}.", project.config.name, error))
+                })
+                .collect();
+            match format {
+                DescribeFormat::Json => {
+                    let output = serde_json::json!({
+                        "mode": compile_mode.name(),
+                        "projects": descriptions,
+                    });
+                    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+                },
+                DescribeFormat::Text => for description in &descriptions {
+                    println!("{} ({:?}): {}", description.name, description.output_type, description.config_path.as_os_str().to_string_lossy());
+                    if !description.dependencies.is_empty() {
+                        println!("    Dependencies: {}", description.dependencies.join(", "));
+                    }
+                    println!("    Sources: {}", description.sources.len());
+                    println!("    Headers: {}", description.headers.len());
+                    for target in &description.targets {
+                        println!("    {:?}: {}", target.target, target.product_path.as_os_str().to_string_lossy());
+                    }
+                },
+            }
+            return;
+        },
+        Subcommand::Includes { file, compile_mode } => {
+            let src_path = canonicalize(file)
+                .unwrap_or_else(|error| fail_immediate!("Unable to find \"{}\": {}.", file.as_os_str().to_string_lossy(), error));
+            let project_path = src_path.ancestors()
+                .skip(1)
+                .find(|dir| dir.join("abs.json").is_file())
+                .unwrap_or_else(|| fail_immediate!("\"{}\" is not part of an ABS project.", file.as_os_str().to_string_lossy()));
+            let (_, config) = load_config(project_path);
+            // Artifacts are written under the project (or workspace) being built, which may depend on the project
+            // that the file belongs to
+            let root_path = find_project_root().ok().flatten().unwrap_or_else(|| project_path.to_owned());
+
+            // Use the target that the file was most recently compiled for
+            let src_deps_json_path = config.supported_targets.iter()
+                .filter_map(|&target| {
+                    let src_deps_path = build::intermediates_path(&root_path.join(config.artifact_path(compile_mode.name(), target))).join("src_deps");
+                    let json_path = build::src_artifact_path(&config, project_path, &src_path, &src_deps_path, "json");
+                    let modified = fs::metadata(&json_path).and_then(|metadata| metadata.modified()).ok()?;
+                    Some((modified, json_path))
+                })
+                .max_by_key(|&(modified, _)| modified)
+                .map(|(_, json_path)| json_path)
+                .unwrap_or_else(|| fail_immediate!("No include information was found for \"{}\". Please build it in {} mode and try again.", file.as_os_str().to_string_lossy(), compile_mode.name()));
+            let includes = build::read_src_deps(&src_deps_json_path)
+                .unwrap_or_else(|error| fail_immediate!("Unable to read include information for \"{}\": {}.", file.as_os_str().to_string_lossy(), error));
+
+            fn print_node(node: &includes::IncludeNode, depth: usize) {
+                if node.children.is_empty() {
+                    println!("{}{} ({})", "  ".repeat(depth), node.path.as_os_str().to_string_lossy(), format_size(node.size));
+                } else {
+                    println!("{}{} ({}, {} with includes)", "  ".repeat(depth), node.path.as_os_str().to_string_lossy(), format_size(node.size), format_size(node.total_size));
+                }
+                for child in &node.children {
+                    print_node(child, depth + 1);
+                }
+            }
+            let (tree, unplaced) = includes::include_tree(&src_path, &includes);
+            print_node(&tree, 0);
+            if !unplaced.is_empty() {
+                println!("\nAlso included, from an unknown location:");
+                for path in &unplaced {
+                    let size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+                    println!("  {} ({})", path.as_os_str().to_string_lossy(), format_size(size));
+                }
+            }
+
+            let mut heaviest = tree.descendants();
+            if !heaviest.is_empty() {
+                heaviest.sort_by_key(|node| std::cmp::Reverse(node.total_size));
+                println!("\nHeaviest includes:");
+                for node in heaviest.iter().take(5) {
+                    println!("  {:>10}  {}", format_size(node.total_size), node.path.as_os_str().to_string_lossy());
+                }
+            }
+            println!("\n{} headers included, {} in total", format_count(includes.len() as u64), format_size(tree.total_size));
+            return;
+        },
+        Subcommand::Explain { code } => {
+            if let Some(explanation) = explain::explain(code) {
+                println!("{}: {}", code.trim().to_uppercase(), explanation);
+            } else {
+                let known_codes: Vec<_> = explain::known_codes().collect();
+                fail_immediate!(
+                    "No explanation is available for \"{}\". Known codes are: {}.",
+                    code,
+                    known_codes.join(", "),
+                );
+            }
+            return;
+        },
     };
 
-    let mut run_path = artifact_path.join(&config.name);
-    run_path.set_extension("exe");
     match options.sub_command {
-        Subcommand::Run(_) => {
+        Subcommand::Run(RunOptions { build_options, pause, wait, env }) => {
+            // A GUI app is intentionally left running after ABS exits, unless `--wait` is passed
+            #[allow(clippy::zombie_processes)]
             let mut child = Command::new(run_path)
+                .envs(&config.run_env)
+                .envs(env.iter().map(|var| (&var.name, &var.value)))
                 .spawn()
                 .unwrap();
-            match config.output_type {
-                OutputType::ConsoleApp => {
-                    // Only wait for the process to complete if this is a console app
-                    child.wait().unwrap();
-                },
-                OutputType::GuiApp | OutputType::DynamicLibrary | OutputType::StaticLibrary => {}
+            let is_console = matches!(config.output_type.subsystem(build_options.subsystem), Some(Subsystem::Console));
+            // Unless asked to, only wait for the process to complete if this is a console app
+            if is_console || wait {
+                let status = child.wait().unwrap();
+                if pause && is_console {
+                    let _ = pause_after_exit(status.code(), &mut io::stdin().lock(), &mut io::stdout());
+                }
+                if wait && !status.success() {
+                    std::process::exit(status.code().unwrap_or(1));
+                }
             }
         },
         Subcommand::Debug(_) => {
+            // The debugger is intentionally left running after ABS exits.
+            #[allow(clippy::zombie_processes)]
+            // The debugger passes its environment on to the program
             Command::new(&toolchain_paths.debugger_path)
-                .args(&[OsStr::new("/debugexe"), run_path.as_os_str()])
+                .args([OsStr::new("/debugexe"), run_path.as_os_str()])
+                .envs(&config.run_env)
                 .spawn()
                 .unwrap();
         },
         _ => {},
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_options, config, toolchain, TempDir};
+    use crate::proj_config::CxxStandard;
+
+    #[test]
+    fn counts_have_thousands_separators() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1000), "1,000");
+        assert_eq!(format_count(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn sizes_use_the_largest_whole_unit() {
+        assert_eq!(format_size(1023), "1023 bytes");
+        assert_eq!(format_size(1024), "1.0 KB");
+        assert_eq!(format_size(1536 * 1024), "1.5 MB");
+        assert_eq!(format_size(2469606195), "2.3 GB");
+        assert_eq!(format_size(u64::MAX), "16777216.0 TB");
+    }
+
+    #[test]
+    fn workspace_settings_depend_on_the_root() {
+        fn settings_for(root: &ProjectConfig, definitions: &[(&str, &str)]) -> String {
+            let mut dependency = config("dependency");
+            dependency.adapt_to_workspace(root);
+            workspace_settings(&dependency, definitions)
+        }
+        let first_root = config("first");
+        let mut second_root = config("second");
+        assert_eq!(settings_for(&first_root, &[]), settings_for(&second_root, &[]));
+
+        second_root.hardening.control_flow_guard = true;
+        assert_ne!(settings_for(&first_root, &[]), settings_for(&second_root, &[]));
+        assert_ne!(settings_for(&first_root, &[]), settings_for(&first_root, &[("FOO", "1")]));
+    }
+
+    #[test]
+    fn failed_projects_are_reported_in_order() {
+        let mut state = BuildState::default();
+        assert_eq!(state.failure_report(), None);
+
+        let dependency = ("dependency".to_string(), Platform::Win64, CompileMode::Debug);
+        let root = ("app".to_string(), Platform::Win64, CompileMode::Debug);
+        state.fail(dependency.clone());
+        // The dependency failing only affects the target and mode it failed in
+        assert!(state.has_failed(&dependency));
+        assert!(!state.has_failed(&("dependency".to_string(), Platform::Win64, CompileMode::Release)));
+        state.fail(root);
+        // e.g., a dependency shared between workspace members is only reported once
+        state.fail(dependency);
+        assert_eq!(
+            state.failure_report().unwrap(),
+            "The following projects failed to build:\n    \"dependency\" for target Win64 in debug mode\n    \"app\" for target Win64 in debug mode",
+        );
+    }
+
+    #[test]
+    fn one_failed_target_is_summarized_and_fails_the_build() {
+        let mut state = BuildState::default();
+        state.outcomes.push(("app".to_string(), Platform::Win32, CompileMode::Debug, None));
+        // A single outcome speaks for itself
+        assert_eq!(state.summary(), Vec::<String>::new());
+
+        state.outcomes.push(("app".to_string(), Platform::Win64, CompileMode::Debug, Some("linking failed".to_string())));
+        state.fail(("app".to_string(), Platform::Win64, CompileMode::Debug));
+        state.outcomes.push(("app".to_string(), Platform::Win64, CompileMode::Release, None));
+        assert_eq!(state.summary(), [
+            "Summary:",
+            "    \"app\" for target Win32 in debug mode: ok",
+            "    \"app\" for target Win64 in debug mode: FAILED (linking failed)",
+            "    \"app\" for target Win64 in release mode: ok",
+        ]);
+        // Any failure report makes the build exit with a nonzero code once every combination has been attempted
+        assert_eq!(state.failure_report().unwrap(), "The following projects failed to build:\n    \"app\" for target Win64 in debug mode");
+    }
+
+    #[test]
+    fn own_definitions_dont_leak_between_projects() {
+        let mut dependency = config("dependency");
+        dependency.definitions = vec!["FOO_INTERNAL".to_string()];
+        let mut app = config("app");
+        app.definitions = vec!["APP_NAME=\"app\"".to_string()];
+        app.dependency_defines = HashMap::from([("dependency".to_string(), vec!["FOO_LEVEL=2".to_string()])]);
+
+        let app_definitions = project_definitions(&app, &[]);
+        assert!(app_definitions.contains(&("APP_NAME", "\"app\"")));
+        assert!(!app_definitions.iter().any(|&(name, _)| name.starts_with("FOO_")), "{:?}", app_definitions);
+
+        let passed_definitions = app.dependency_definitions("dependency");
+        let dependency_definitions = project_definitions(&dependency, &passed_definitions);
+        assert!(dependency_definitions.contains(&("FOO_INTERNAL", "1")));
+        assert!(dependency_definitions.contains(&("FOO_LEVEL", "2")));
+        assert!(!dependency_definitions.iter().any(|&(name, _)| name == "APP_NAME"), "{:?}", dependency_definitions);
+    }
+
+    #[test]
+    fn library_kinds() {
+        for (lib, kind) in [
+            ("user32.lib", LibraryKind::Windows),
+            ("USER32.LIB", LibraryKind::Windows),
+            ("libs/Prebuilt.Lib", LibraryKind::Windows),
+            ("-lpthread", LibraryKind::Unix),
+            ("-lfoo.lib", LibraryKind::Unix),
+            ("libfoo.a", LibraryKind::Any),
+            ("lib", LibraryKind::Any),
+        ] {
+            assert_eq!(library_kind(Path::new(lib)), kind, "{}", lib);
+        }
+    }
+
+    #[test]
+    fn project_root_is_the_nearest_ancestor_with_a_project_file() {
+        let dir = TempDir::new();
+        dir.write("workspace/abs-workspace.json", "{}");
+        dir.write("workspace/app/abs.json", "{}");
+        dir.write("workspace/app/src/nested/main.cpp", "");
+        dir.write("workspace/docs/readme.txt", "");
+        let workspace = dir.path().join("workspace");
+        assert_eq!(find_project_root_from(&workspace.join("app/src/nested")), Some(workspace.join("app")));
+        assert_eq!(find_project_root_from(&workspace.join("app")), Some(workspace.join("app")));
+        assert_eq!(find_project_root_from(&workspace.join("docs")), Some(workspace.clone()));
+        // A directory named abs.json doesn't count
+        fs::create_dir_all(dir.path().join("elsewhere/abs.json")).unwrap();
+        assert_eq!(find_project_root_from(&dir.path().join("elsewhere")), None);
+    }
+
+    /// Writes `config` as the project file of a project in `relative_path` under `dir`, returning the project's path
+    fn write_config(dir: &TempDir, relative_path: impl AsRef<Path>, config: &ProjectConfig) -> PathBuf {
+        let relative_path = relative_path.as_ref();
+        dir.write(relative_path.join("abs.json"), serde_json::to_string(config).unwrap());
+        dir.path().join(relative_path)
+    }
+
+    /// The problems found by `read_config()` in the project file of `config`
+    fn config_problems(config: &ProjectConfig) -> Vec<String> {
+        let dir = TempDir::new();
+        let mut problems = Vec::new();
+        read_config(&write_config(&dir, "app", config), &mut problems).unwrap();
+        problems
+    }
+
+    #[test]
+    fn windows_libraries_are_rejected_for_non_windows_targets() {
+        let mut config = config("app");
+        config.supported_targets = vec![Platform::Linux64];
+        config.link_libraries = vec![PathBuf::from("USER32.LIB"), PathBuf::from("-lpthread")];
+        let problems = config_problems(&config);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].contains("Windows-only libraries [\"USER32.LIB\"]"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn unix_library_names_are_rejected_for_windows_targets() {
+        let mut config = config("app");
+        config.supported_targets = vec![Platform::Win64];
+        config.link_libraries = vec![PathBuf::from("user32.lib"), PathBuf::from("-lpthread")];
+        let problems = config_problems(&config);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].contains("libraries [\"-lpthread\"] by `-l` name"), "{}", problems[0]);
+
+        config.link_libraries = vec![PathBuf::from("user32.lib")];
+        assert_eq!(config_problems(&config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn runtime_libraries_of_dependencies_are_collected() {
+        let dir = TempDir::new();
+        let mut dependency = config("dependency");
+        dependency.output_type = OutputType::StaticLibrary;
+        dependency.runtime_libraries = vec![PathBuf::from("vendor/foo.dll")];
+        let dll = dir.write("dependency/vendor/foo.dll", "");
+        write_config(&dir, "dependency", &dependency);
+        let mut app = config("app");
+        app.dependencies = vec![PathBuf::from("../dependency")];
+        let app_path = write_config(&dir, "app", &app);
+
+        let mut problems = Vec::new();
+        let mut projects = load_project_graph(app_path.join("abs.json"), &app, &mut problems);
+        let mut runtime_libraries = Vec::new();
+        validate_dependencies(&mut projects, &mut HashSet::new(), &mut HashMap::new(), &mut Vec::new(), &mut runtime_libraries, "app", &mut problems);
+        assert_eq!(problems, Vec::<String>::new());
+        assert_eq!(runtime_libraries, [dll]);
+    }
+
+    #[test]
+    fn prebuilt_dependency_libraries_are_linked_by_path() {
+        let dir = TempDir::new();
+        // Paths that aren't valid UTF-8 must survive intact
+        #[cfg(unix)]
+        let parent = {
+            use std::os::unix::ffi::OsStrExt;
+            PathBuf::from(OsStr::from_bytes(b"non-utf8-\xff"))
+        };
+        #[cfg(not(unix))]
+        let parent = PathBuf::from("parent");
+        let mut dependency = config("dependency");
+        dependency.output_type = OutputType::StaticLibrary;
+        dependency.link_libraries = vec![PathBuf::from("vendor/foo.lib"), PathBuf::from("user32.lib")];
+        let prebuilt = dir.write(parent.join("dependency/vendor/foo.lib"), "");
+        write_config(&dir, parent.join("dependency"), &dependency);
+        let mut app = config("app");
+        app.dependencies = vec![PathBuf::from("../dependency")];
+        let app_path = write_config(&dir, parent.join("app"), &app);
+
+        let mut problems = Vec::new();
+        let mut projects = load_project_graph(app_path.join("abs.json"), &app, &mut problems);
+        let mut link_libraries = HashSet::new();
+        validate_dependencies(&mut projects, &mut link_libraries, &mut HashMap::new(), &mut Vec::new(), &mut Vec::new(), "app", &mut problems);
+        assert_eq!(problems, Vec::<String>::new());
+        assert_eq!(link_libraries, HashSet::from([prebuilt, PathBuf::from("user32.lib")]));
+    }
+
+    #[test]
+    fn dir_usage_counts_nested_files() {
+        let dir = TempDir::new();
+        dir.write("a.obj", "0123");
+        dir.write("nested/deeper/b.obj", "012345");
+        assert_eq!(dir_usage(dir.path()).unwrap(), (2, 10));
+    }
+
+    #[test]
+    fn unchanged_dependencies_are_skipped() {
+        let dir = TempDir::new();
+        let mut config = config("dependency");
+        config.include_paths = vec!["{src}".into(), "vendor".into()];
+        let config_path = dir.write("abs.json", "{}");
+        dir.write("src/lib.cpp", "");
+        let header = dir.write("vendor/vendor.h", "");
+        let build_options = build_options(&[]);
+        let fingerprint_path = dir.path().join("inputs_fingerprint");
+        let product_path = dir.path().join("dependency.lib");
+        let fingerprint = || {
+            let fingerprint = build::inputs_fingerprint(&config, &config_path, &[], &build_options).unwrap();
+            format!("{:016x}", fingerprint)
+        };
+
+        // Never built
+        assert!(!is_up_to_date(&fingerprint_path, Some(&fingerprint()), &product_path));
+        fs::write(&fingerprint_path, fingerprint()).unwrap();
+        fs::write(&product_path, "").unwrap();
+        assert!(is_up_to_date(&fingerprint_path, Some(&fingerprint()), &product_path));
+        assert!(!is_up_to_date(&fingerprint_path, None, &product_path));
+
+        // Headers in include directories are inputs, just like sources
+        fs::write(&header, "#pragma once").unwrap();
+        assert!(!is_up_to_date(&fingerprint_path, Some(&fingerprint()), &product_path));
+        fs::write(&fingerprint_path, fingerprint()).unwrap();
+        dir.write("src/lib.h", "");
+        assert!(!is_up_to_date(&fingerprint_path, Some(&fingerprint()), &product_path));
+        fs::write(&fingerprint_path, fingerprint()).unwrap();
+
+        fs::remove_file(&product_path).unwrap();
+        assert!(!is_up_to_date(&fingerprint_path, Some(&fingerprint()), &product_path));
+    }
+
+    #[test]
+    fn toolchain_description_marks_missing_paths() {
+        let dir = TempDir::new();
+        let mut toolchain_paths = toolchain();
+        toolchain_paths.details = vec![("MSVC version", "14.29.30133".to_string())];
+        toolchain_paths.include_paths = vec![dir.path().to_owned()];
+        toolchain_paths.lib_paths = vec![dir.path().join("missing")];
+        let description = describe_toolchain(Platform::Win64, &toolchain_paths);
+        assert_eq!(description, format!(
+            "Target: Win64\nMSVC version: 14.29.30133\nclang-format:\n    (not found)\nsigntool:\n    (not found)\n\
+             Include paths:\n    {}\nLibrary paths:\n    {} (missing)\nBinary paths:\n",
+            dir.path().to_string_lossy(),
+            dir.path().join("missing").to_string_lossy(),
+        ));
+    }
+
+    #[test]
+    fn pausing_reports_the_exit_code_and_waits_for_a_line() {
+        let mut input = Cursor::new("\nnext line\n");
+        let mut output = Vec::new();
+        pause_after_exit(Some(3), &mut input, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\nProcess exited with code 3. Press Enter to continue...");
+        assert_eq!(input.position(), 1);
+
+        let mut output = Vec::new();
+        pause_after_exit(None, &mut Cursor::new(""), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\nProcess was terminated. Press Enter to continue...");
+    }
+
+    #[test]
+    fn default_target_must_be_supported() {
+        let mut config = config("app");
+        config.default_target = Some(Platform::Win64);
+        assert_eq!(config_problems(&config), Vec::<String>::new());
+        config.default_target = Some(Platform::Win32);
+        let problems = config_problems(&config);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].contains("default target of Win32, which is not one of its supported targets"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn templates_are_copied_with_the_project_name() {
+        let dir = TempDir::new();
+        dir.write("template/src/{name}.cpp", "// {name}");
+        dir.write("template/src/main.cpp", "int main() {} // {name}");
+        dir.write("template/icon.ico", [0xff, 0xfe, 0x00]);
+        let template = dir.path().join("template");
+        copy_template(&template, &dir.path().join("app"), "app").unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("app/src/main.cpp")).unwrap(), "int main() {} // app");
+        // Only file contents are substituted
+        assert_eq!(fs::read_to_string(dir.path().join("app/src/{name}.cpp")).unwrap(), "// app");
+        assert_eq!(fs::read(dir.path().join("app/icon.ico")).unwrap(), [0xff, 0xfe, 0x00]);
+    }
+
+    #[test]
+    fn templates_may_not_overlap_the_new_project() {
+        let dir = TempDir::new();
+        dir.write("project/template/src/main.cpp", "");
+        let project = dir.path().join("project");
+        let error = copy_template(&project.join("template"), &project, "project").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        // Otherwise, the copy would recurse into itself forever
+        let error = copy_template(&project, &project.join("template/nested"), "nested").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!project.join("src").exists());
+        assert!(!project.join("template/nested/template").exists());
+    }
+
+    #[test]
+    fn unreadable_dependencies_are_reported() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path().join("no_config")).unwrap();
+        dir.write("bad_config/abs.json", "{ \"name\": ");
+        let mut app = config("app");
+        app.dependencies = vec![PathBuf::from("../missing"), PathBuf::from("../no_config"), PathBuf::from("../bad_config")];
+        let app_path = write_config(&dir, "app", &app);
+
+        let mut problems = Vec::new();
+        let projects = load_project_graph(app_path.join("abs.json"), &app, &mut problems);
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects["app"].dep_names, Vec::<String>::new());
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with(&format!("Failed to get canonical path for dependency \"{}\": ", app_path.join("../missing").to_string_lossy())), "{}", problems[0]);
+        assert!(problems[1].starts_with(&format!("Unable to read project file in directory \"{}\": ", dir.path().join("no_config").to_string_lossy())), "{}", problems[1]);
+        assert!(problems[2].starts_with(&format!("Failed to parse project file {}: ", dir.path().join("bad_config/abs.json").to_string_lossy())), "{}", problems[2]);
+    }
+
+    #[test]
+    fn incompatible_dependencies_are_reported_with_their_modes() {
+        let dir = TempDir::new();
+        let mut dependency = config("dependency");
+        dependency.output_type = OutputType::StaticLibrary;
+        dependency.cxx_options.rtti = true;
+        dependency.cxx_options.standard = CxxStandard::Cxx17;
+        dependency.cxx_options.mode_standards.release = Some(CxxStandard::Cxx20);
+        let dependency_path = write_config(&dir, "dependency", &dependency);
+        let mut app = config("app");
+        app.cxx_options.standard = CxxStandard::Cxx17;
+        app.dependencies = vec![PathBuf::from("../dependency")];
+        let app_path = write_config(&dir, "app", &app);
+
+        let mut problems = Vec::new();
+        let projects = load_project_graph(app_path.join("abs.json"), &app, &mut problems);
+        validate_cxx_options(&projects, &app, &[CompileMode::Debug, CompileMode::Release], &mut problems);
+        let display_name = format!("\"dependency\" ({})", dependency_path.join("abs.json").to_string_lossy());
+        assert_eq!(problems, [
+            format!("The C++ options of {} are incompatible with those of the root project \"app\" (rtti: dependency=true, root=false).", display_name),
+        ]);
+
+        // Once rtti matches, the standard is only a problem in the mode that overrides it
+        let mut projects = projects;
+        projects.get_mut("dependency").unwrap().config.cxx_options.rtti = false;
+        problems.clear();
+        validate_cxx_options(&projects, &app, &[CompileMode::Debug, CompileMode::Release], &mut problems);
+        assert_eq!(problems, [
+            format!("The C++ options of {} are incompatible with those of the root project \"app\" in release mode (standard: dependency=c++20, root=c++17).", display_name),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn dependencies_generate_pchs_concurrently_and_compile_after_their_own() {
+        // Neither project's precompiled header is done until the other's has started, so building the projects one at
+        // a time would never finish
+        let pchs_started = tokio::sync::Barrier::new(2);
+        let events = std::sync::Mutex::new(Vec::new());
+        let build = |name: &'static str| {
+            let (pchs_started, events) = (&pchs_started, &events);
+            async move {
+                events.lock().unwrap().push((name, "generate PCH"));
+                pchs_started.wait().await;
+                events.lock().unwrap().push((name, "compile sources"));
+                name
+            }
+        };
+        let built = tokio::time::timeout(std::time::Duration::from_secs(10), build_concurrently(["a", "b"], build)).await
+            .expect("the projects weren't built concurrently");
+        assert_eq!(built, ["a", "b"]);
+
+        let events = events.into_inner().unwrap();
+        let position = |event| events.iter().position(|&e| e == event).unwrap();
+        for name in ["a", "b"] {
+            assert!(position((name, "generate PCH")) < position((name, "compile sources")));
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// Generation of Ninja (https://ninja-build.org) build files, which compile and link a project with the same flags
+// that ABS would use to build it directly.
+
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::build::{BuildEnvironment, BuildError, PchOption, retry_output_io};
+use crate::task::{CxxTask, AsmTask};
+
+/// Each project's Ninja file defines the same rules, which is only allowed in separate `subninja` scopes as of 1.6
+const REQUIRED_NINJA_VERSION: &str = "1.6";
+
+/// Escapes a path for use in a build statement
+fn escape_path(path: impl AsRef<Path>) -> String {
+    let mut escaped = String::new();
+    for c in path.as_ref().as_os_str().to_string_lossy().chars() {
+        if matches!(c, '$' | ' ' | ':') {
+            escaped.push('$');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quotes an argument for a Windows command line if necessary, and escapes it for use in a variable
+fn escape_arg(arg: impl AsRef<OsStr>) -> String {
+    // Line breaks aren't allowed in variables, and aren't meaningful in any arguments ABS passes
+    let arg = arg.as_ref().to_string_lossy().replace('\n', " ");
+    let arg = if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        arg
+    } else {
+        // Backslashes are only special when they precede a quote
+        let mut quoted = String::from("\"");
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            if c == '\\' {
+                backslashes += 1;
+                continue;
+            }
+            let escaped_backslashes = if c == '"' { backslashes * 2 + 1 } else { backslashes };
+            quoted.push_str(&"\\".repeat(escaped_backslashes));
+            quoted.push(c);
+            backslashes = 0;
+        }
+        quoted.push_str(&"\\".repeat(backslashes * 2));
+        quoted.push('"');
+        quoted
+    };
+    arg.replace('$', "$$")
+}
+
+fn escape_args(args: impl IntoIterator<Item=impl AsRef<OsStr>>) -> String {
+    args.into_iter()
+        .map(escape_arg)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn write_build_statement(
+    ninja: &mut String,
+    outputs: &[&Path],
+    rule: &str,
+    inputs: &[&Path],
+    implicit_inputs: &[&Path],
+    variables: &[(&str, String)],
+) {
+    write!(ninja, "build").unwrap();
+    for output in outputs {
+        write!(ninja, " {}", escape_path(output)).unwrap();
+    }
+    write!(ninja, ": {}", rule).unwrap();
+    for input in inputs {
+        write!(ninja, " {}", escape_path(input)).unwrap();
+    }
+    if !implicit_inputs.is_empty() {
+        write!(ninja, " |").unwrap();
+        for input in implicit_inputs {
+            write!(ninja, " {}", escape_path(input)).unwrap();
+        }
+    }
+    writeln!(ninja).unwrap();
+    for (name, value) in variables {
+        writeln!(ninja, "  {} = {}", name, value).unwrap();
+    }
+}
+
+impl<'a> BuildEnvironment<'a> {
+    /// Ninja doesn't get the PATH modifications that ABS makes when running tools itself, so tools are referred to
+    /// by their full paths when possible
+    fn tool_path(&self, name: &str) -> PathBuf {
+        self.toolchain_paths.bin_paths.iter()
+            .map(|bin_path| bin_path.join(name))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| PathBuf::from(name))
+    }
+
+    /// Writes a Ninja build file for the project to its artifact directory, and returns its path. Paths in the file
+    /// are relative to the current directory, so Ninja must be run from there.
+    ///
+    /// Dependencies' headers are copied during generation, just as they would be at the start of a build, so the
+    /// file must be regenerated after they change.
+    pub fn generate_ninja(&self) -> Result<PathBuf, BuildError> {
+        self.check_dependency_library_modes()?;
+        let paths = self.discover_sources()?;
+        self.copy_dependency_headers()?;
+
+        let mut ninja = String::new();
+        writeln!(ninja, "# Generated by `abs generate ninja` for \"{}\". Do not edit.", self.config.name).unwrap();
+        writeln!(ninja, "ninja_required_version = {}", REQUIRED_NINJA_VERSION).unwrap();
+        writeln!(ninja).unwrap();
+        writeln!(ninja, "compiler = {}", escape_arg(self.tool_path("cl.exe"))).unwrap();
+        writeln!(ninja).unwrap();
+        writeln!(ninja, "rule cxx").unwrap();
+        writeln!(ninja, "  command = $compiler $flags /showIncludes").unwrap();
+        writeln!(ninja, "  deps = msvc").unwrap();
+        writeln!(ninja, "  description = Compiling $in").unwrap();
+        writeln!(ninja).unwrap();
+        writeln!(ninja, "rule asm").unwrap();
+        writeln!(ninja, "  command = $assembler $flags").unwrap();
+        writeln!(ninja, "  description = Assembling $in").unwrap();
+        writeln!(ninja).unwrap();
+        writeln!(ninja, "rule link").unwrap();
+        writeln!(ninja, "  command = $linker @$out.rsp").unwrap();
+        writeln!(ninja, "  rspfile = $out.rsp").unwrap();
+        writeln!(ninja, "  rspfile_content = $flags").unwrap();
+        writeln!(ninja, "  description = Linking $out").unwrap();
+        writeln!(ninja).unwrap();
+
+        let pch_src_path = self.src_dir_path.join("pch.cpp");
+        let pch = paths.src_paths.contains(&pch_src_path);
+        let pch_path = self.get_artifact_path(self.src_dir_path.join("pch.h"), &self.objs_path, "pch");
+        let mut obj_paths = Vec::new();
+        for path in paths.all_src_paths() {
+            let pch_option = if !pch {
+                PchOption::NoPch
+            } else if path == pch_src_path {
+                PchOption::GeneratePch
+            } else {
+                PchOption::UsePch
+            };
+            let (flags, obj_path) = CxxTask::compile_flags(self, path, pch_option)?;
+            let mut outputs = vec![obj_path.as_path()];
+            // Headers discovered by a previous build. Ninja discovers them itself after its first build.
+            let dependencies = self.discover_src_deps(path)?.unwrap_or_default();
+            let mut implicit_inputs: Vec<&Path> = dependencies.iter().map(PathBuf::as_path).collect();
+            match pch_option {
+                PchOption::GeneratePch => outputs.push(&pch_path),
+                PchOption::UsePch => implicit_inputs.push(&pch_path),
+                PchOption::NoPch => {},
+            }
+            write_build_statement(&mut ninja, &outputs, "cxx", &[path], &implicit_inputs, &[("flags", escape_args(flags.build()))]);
+            obj_paths.push(obj_path);
+        }
+        for path in paths.all_asm_paths() {
+            let (assembler, args) = AsmTask::assemble_command(self, path)?;
+            let obj_path = AsmTask::obj_path(self, path);
+            let variables = [
+                ("assembler", escape_arg(self.tool_path(assembler))),
+                ("flags", escape_args(args)),
+            ];
+            write_build_statement(&mut ninja, &[&obj_path], "asm", &[path], &[], &variables);
+            obj_paths.push(obj_path);
+        }
+
+        let product_path = self.artifact_path.join(self.product_name());
+        let (linker, args) = self.link_command(&product_path, &obj_paths);
+        let inputs: Vec<&Path> = obj_paths.iter().map(PathBuf::as_path).collect();
+        let implicit_inputs: Vec<&Path> = self.linker_lib_dependencies.iter()
+            .chain(&self.dependency_libraries)
+            .chain(&self.manifest_paths)
+            .map(PathBuf::as_path)
+            .collect();
+        let variables = [
+            ("linker", escape_arg(self.tool_path(linker))),
+            ("flags", escape_args(args)),
+        ];
+        write_build_statement(&mut ninja, &[&product_path], "link", &inputs, &implicit_inputs, &variables);
+
+        let ninja_path = self.artifact_path.join("build.ninja");
+        retry_output_io(&ninja_path, || fs::write(&ninja_path, &ninja))?;
+        Ok(ninja_path)
+    }
+}
+
+/// Writes `build.ninja` to the current directory, which includes the Ninja files generated for each project, and
+/// returns its path
+pub fn write_root_ninja_file(ninja_files: &[PathBuf]) -> io::Result<PathBuf> {
+    let mut ninja = String::new();
+    writeln!(ninja, "# Generated by `abs generate ninja`. Do not edit.").unwrap();
+    writeln!(ninja, "ninja_required_version = {}", REQUIRED_NINJA_VERSION).unwrap();
+    writeln!(ninja).unwrap();
+    for ninja_file in ninja_files {
+        writeln!(ninja, "subninja {}", escape_path(ninja_file)).unwrap();
+    }
+    let ninja_path = PathBuf::from("build.ninja");
+    fs::write(&ninja_path, ninja)?;
+    Ok(ninja_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_escape_ninja_syntax() {
+        assert_eq!(escape_path(r"C:\My Project\src\a$b.cpp"), r"C$:\My$ Project\src\a$$b.cpp");
+    }
+
+    #[test]
+    fn args_are_quoted_for_the_command_line_and_escaped_for_ninja() {
+        assert_eq!(escape_arg("/nologo"), "/nologo");
+        assert_eq!(escape_arg(""), r#""""#);
+        assert_eq!(escape_arg(r"C:\My Project\"), r#""C:\My Project\\""#);
+        assert_eq!(escape_arg(r#"/DNAME="a b""#), r#""/DNAME=\"a b\"""#);
+        assert_eq!(escape_arg("/Fo$out"), "/Fo$$out");
+    }
+
+    #[test]
+    fn build_statements_list_implicit_inputs_after_a_pipe() {
+        let mut ninja = String::new();
+        write_build_statement(
+            &mut ninja,
+            &[Path::new("obj/main.obj")],
+            "cxx",
+            &[Path::new("src/main.cpp")],
+            &[Path::new("src/main.h")],
+            &[("flags", escape_args(["/c", "/Od"]))],
+        );
+        assert_eq!(ninja, "build obj/main.obj: cxx src/main.cpp | src/main.h\n  flags = /c /Od\n");
+    }
+}
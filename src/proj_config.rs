@@ -1,33 +1,386 @@
 use serde::{Serialize, Deserialize};
 use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::collections::HashMap;
 use std::cmp::{PartialOrd, Ord, Ordering};
+use std::fmt;
+
+use crate::cmd_options::CompileMode;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ProjectConfig {
     pub name: String,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub version: Option<String>,
     pub cxx_options: CxxOptions,
+    /// When this project is a dependency, compile it with its own `cxx_options` rather than the root project's. They
+    /// must still be compatible with the root project's.
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub pin_cxx_options: bool,
     pub output_type: OutputType,
-    pub link_libraries: Vec<String>,
+    /// System libraries to link by name (e.g., user32.lib), or paths to other libraries. Stored as paths so that
+    /// non-UTF-8 paths survive intact.
+    pub link_libraries: Vec<PathBuf>,
+    /// Libraries to link in addition to `link_libraries` when building for a particular target
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub target_link_libraries: HashMap<Platform, Vec<PathBuf>>,
     pub supported_targets: Vec<Platform>,
+    /// The target to build for when none is specified on the command line, instead of the host
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub default_target: Option<Platform>,
     pub dependencies: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub windows_target_version: Option<WindowsVersion>,
+    /// File name (without extension) of the build product. May contain the placeholders `{name}`,
+    /// `{version}`, `{mode}` and `{target}`. Defaults to `{name}`.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub output_name: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub uac_execution_level: Option<UacExecutionLevel>,
+    /// Place intermediate build artifacts (objects, etc.) directly in their output directories,
+    /// rather than mirroring the structure of the src directory.
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub flatten_artifacts: bool,
+    /// Source files outside of the src directory to compile and link, relative to the project root
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub extra_sources: Vec<PathBuf>,
+    /// The directory, relative to the project root, whose contents are packaged alongside the build
+    /// product. Defaults to `assets`.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub assets_dir: Option<PathBuf>,
+    /// Preprocessor definitions to pass when compiling this project, but not its dependencies or the projects that
+    /// depend on it. Each definition is either `NAME=VALUE` or just `NAME`, which defines it as 1.
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub definitions: Vec<String>,
+    /// Preprocessor definitions to pass when compiling dependencies, keyed by dependency project name. Each
+    /// definition is either `NAME=VALUE` or just `NAME`, which defines it as 1.
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub dependency_defines: HashMap<String, Vec<String>>,
+    /// Produce bit-identical outputs across builds and machines, by omitting timestamps and normalizing
+    /// embedded paths.
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub reproducible: bool,
+    /// Embed only the file name of the PDB in the binary, rather than its absolute path, so that the build
+    /// machine's directory layout isn't revealed
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub strip_pdb_path: bool,
+    /// Whether the project's own headers or its dependencies' headers are found first when both contain a header
+    /// with the same name. Defaults to `dependencies`.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub include_precedence: Option<IncludePrecedence>,
+    /// Additional include directories, relative to the project root, searched after the project's and its
+    /// dependencies' headers. The placeholders `{src}` and `{dependencies}` may be used to position those
+    /// explicitly, in which case `include_precedence` is ignored.
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub include_paths: Vec<PathBuf>,
+    /// Additional directories, relative to the project root, in which to search for `link_libraries`. These are
+    /// searched before the toolchain's library directories.
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub library_paths: Vec<PathBuf>,
+    /// Prebuilt DLLs, relative to the project root, to copy next to the build product of the project (or of any
+    /// project that depends on it), so that they can be found at run time
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub runtime_libraries: Vec<PathBuf>,
+    /// Additional manifest files, relative to the project root, to merge with windows_manifest.xml (if it exists)
+    /// and embed in the binary
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub manifests: Vec<PathBuf>,
+    /// Security hardening features to enable in the compiled code and the binary
+    #[serde(default, skip_serializing_if="HardeningOptions::is_empty")]
+    pub hardening: HardeningOptions,
+    /// Build a static library as a thin archive, which refers to its object files by path rather than containing
+    /// copies of them. Only supported when building on Linux, where static libraries are archived with llvm-ar.
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub thin_archive: bool,
+    /// Authenticode-sign the build product after linking it. Only applies to Windows targets, and is ignored for static
+    /// libraries.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub code_signing: Option<CodeSigningOptions>,
+    /// The include-what-you-use executable for `abs iwyu` to run, relative to the project root. If not specified, it
+    /// is searched for in PATH.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub iwyu_path: Option<PathBuf>,
+    /// Environment variables to set for the program when it's launched by `abs run` or `abs debug`, in addition to
+    /// those ABS was run with
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub run_env: HashMap<String, String>,
 }
 
 impl ProjectConfig {
+    /// The names of all fields that may appear in a project file
+    pub const FIELD_NAMES: &'static [&'static str] = &[
+        "name",
+        "description",
+        "authors",
+        "version",
+        "cxx_options",
+        "pin_cxx_options",
+        "output_type",
+        "link_libraries",
+        "target_link_libraries",
+        "supported_targets",
+        "default_target",
+        "dependencies",
+        "windows_target_version",
+        "output_name",
+        "uac_execution_level",
+        "flatten_artifacts",
+        "extra_sources",
+        "assets_dir",
+        "definitions",
+        "dependency_defines",
+        "reproducible",
+        "strip_pdb_path",
+        "include_precedence",
+        "include_paths",
+        "library_paths",
+        "runtime_libraries",
+        "manifests",
+        "hardening",
+        "code_signing",
+        "thin_archive",
+        "iwyu_path",
+        "run_env",
+    ];
+
+    pub fn output_name(&self, mode: &str, target: Platform) -> String {
+        match &self.output_name {
+            Some(template) => template
+                .replace("{name}", &self.name)
+                .replace("{version}", self.version.as_deref().unwrap_or(""))
+                .replace("{mode}", mode)
+                .replace("{target}", &format!("{:?}", target).to_lowercase()),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The directory to which the project is built for `target` in `mode`, relative to the directory of the root
+    /// project (e.g., abs/debug/my_app/Win64)
+    pub fn artifact_path(&self, mode: &str, target: Platform) -> PathBuf {
+        let mut artifact_path: PathBuf = ["abs", mode, &self.name].iter().collect();
+        artifact_path.push(format!("{:?}", target));
+        artifact_path
+    }
+
+    /// The file name of the build product, including its extension
+    pub fn product_name(&self, mode: &str, target: Platform) -> String {
+        let extension = match self.output_type {
+            OutputType::ConsoleApp | OutputType::GuiApp => "exe",
+            OutputType::DynamicLibrary => "dll",
+            OutputType::StaticLibrary => match target.os() {
+                Os::Windows => "lib",
+                Os::Linux => "a",
+            },
+        };
+        format!("{}.{}", self.output_name(mode, target), extension)
+    }
+
+    /// The definitions the project is compiled with itself, as (name, value) pairs
+    pub fn own_definitions(&self) -> Vec<(&str, &str)> {
+        self.definitions.iter()
+            .map(|definition| split_definition(definition))
+            .collect()
+    }
+
+    /// The definitions the project passes to its dependency `dependency_name`, as (name, value) pairs
+    pub fn dependency_definitions(&self, dependency_name: &str) -> Vec<(&str, &str)> {
+        self.dependency_defines.get(dependency_name)
+            .into_iter()
+            .flatten()
+            .map(|definition| split_definition(definition))
+            .collect()
+    }
+
     pub fn adapt_to_workspace(&mut self, root_config: &ProjectConfig) {
-        self.cxx_options = root_config.cxx_options;
+        // Includes the per-mode standards, so that every project is compiled at the same standard in each mode
+        if !self.pin_cxx_options {
+            self.cxx_options = root_config.cxx_options;
+        }
+        // e.g., Control Flow Guard only protects code that was compiled with it
+        self.hardening = root_config.hardening;
     }
 }
 
+/// Splits a `NAME=VALUE` definition into its name and value. A definition without a value defines the name as 1.
+fn split_definition(definition: &str) -> (&str, &str) {
+    definition.split_once('=').unwrap_or((definition, "1"))
+}
+
+/// The contents of an abs-workspace.json file, which lists independent projects to be built together
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorkspaceConfig {
+    pub members: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all="snake_case")]
+pub enum IncludePrecedence {
+    Dependencies,
+    Project,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+pub struct HardeningOptions {
+    /// Control Flow Guard. Checks the targets of indirect calls at run time (`/guard:cf` when compiling and
+    /// `/GUARD:CF` when linking).
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub control_flow_guard: bool,
+    /// Marks the binary as compatible with CET shadow stacks, which protect return addresses (`/CETCOMPAT`). Only
+    /// applies to 64-bit targets.
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub cet_compat: bool,
+    /// Enables 64-bit address space layout randomization (`/DYNAMICBASE` and `/HIGHENTROPYVA`). Only applies to
+    /// 64-bit targets.
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub high_entropy_va: bool,
+}
+
+impl HardeningOptions {
+    /// The names of all fields that may appear in the `hardening` object of a project file
+    pub const FIELD_NAMES: &'static [&'static str] = &["control_flow_guard", "cet_compat", "high_entropy_va"];
+
+    pub fn is_empty(&self) -> bool {
+        !self.control_flow_guard && !self.cet_compat && !self.high_entropy_va
+    }
+
+    /// The linker flags that enable these features in an executable or DLL for `arch`
+    pub fn link_flags(self, arch: Arch) -> Vec<&'static str> {
+        let is_64_bit = matches!(arch, Arch::X64);
+        let mut flags = Vec::new();
+        if self.control_flow_guard {
+            flags.push("/GUARD:CF");
+        }
+        if self.cet_compat && is_64_bit {
+            flags.push("/CETCOMPAT");
+        }
+        if self.high_entropy_va && is_64_bit {
+            flags.extend(["/DYNAMICBASE", "/HIGHENTROPYVA"]);
+        }
+        flags
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct CodeSigningOptions {
+    /// The SHA-1 thumbprint of a certificate in the Windows certificate store to sign with (signtool's `/sha1`)
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub certificate_thumbprint: Option<String>,
+    /// A .pfx file, relative to the project root, containing the certificate to sign with (signtool's `/f`). Its
+    /// password, if any, is read from the `ABS_CODE_SIGNING_PASSWORD` environment variable, so that it needn't be
+    /// committed.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub certificate_file: Option<PathBuf>,
+    /// The URL of an RFC 3161 timestamp server (signtool's `/tr`). Timestamped signatures remain valid after the
+    /// certificate expires.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub timestamp_url: Option<String>,
+}
+
+impl CodeSigningOptions {
+    /// The names of all fields that may appear in the `code_signing` object of a project file
+    pub const FIELD_NAMES: &'static [&'static str] = &["certificate_thumbprint", "certificate_file", "timestamp_url"];
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub struct CxxOptions {
     pub rtti: bool,
     pub async_await: bool,
     pub standard: CxxStandard,
+    /// Overrides `standard` for particular compile modes
+    #[serde(default, skip_serializing_if="ModeStandards::is_empty")]
+    pub mode_standards: ModeStandards,
+    #[serde(default)]
+    pub debug_info_format: DebugInfoFormat,
+    /// Overrides `debug_info_format` in release and minsizerel mode (e.g., to ship release binaries without debug info)
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub release_debug_info_format: Option<DebugInfoFormat>,
+    /// Raises the limit on the number of sections in an object file (/bigobj), which template-heavy sources can exceed
+    #[serde(default, skip_serializing_if="std::ops::Not::not")]
+    pub big_obj: bool,
 }
 
 impl CxxOptions {
-    pub fn is_compatible_with(&self, other: &CxxOptions) -> bool {
-        self.rtti == other.rtti && self.async_await == other.async_await && self.standard <= other.standard
+    /// The names of all fields that may appear in the `cxx_options` object of a project file
+    pub const FIELD_NAMES: &'static [&'static str] = &["rtti", "async_await", "standard", "mode_standards", "debug_info_format", "release_debug_info_format", "big_obj"];
+
+    /// The C++ standard to compile with in `mode`
+    pub fn standard_for(&self, mode: CompileMode) -> CxxStandard {
+        let mode_standard = match mode {
+            CompileMode::Debug => self.mode_standards.debug,
+            CompileMode::Release => self.mode_standards.release,
+            CompileMode::RelWithDebInfo => self.mode_standards.relwithdebinfo,
+            CompileMode::MinSizeRel => self.mode_standards.minsizerel,
+        };
+        mode_standard.unwrap_or(self.standard)
+    }
+
+    /// The format of the debug info to generate in `mode`
+    pub fn debug_info_format_for(&self, mode: CompileMode) -> DebugInfoFormat {
+        match mode {
+            CompileMode::Release | CompileMode::MinSizeRel => self.release_debug_info_format.unwrap_or(self.debug_info_format),
+            CompileMode::Debug | CompileMode::RelWithDebInfo => self.debug_info_format,
+        }
+    }
+
+    /// Checks whether a dependency with these options can be linked into a root project with the `root` options,
+    /// when built in `mode`. If not, returns the first option that differs.
+    pub fn check_compatible_with(&self, root: &CxxOptions, mode: CompileMode) -> Result<(), CxxIncompatibility> {
+        if self.rtti != root.rtti {
+            return Err(CxxIncompatibility::Rtti { dependency: self.rtti, root: root.rtti });
+        }
+        if self.async_await != root.async_await {
+            return Err(CxxIncompatibility::AsyncAwait { dependency: self.async_await, root: root.async_await });
+        }
+        let (dependency_standard, root_standard) = (self.standard_for(mode), root.standard_for(mode));
+        if dependency_standard > root_standard {
+            return Err(CxxIncompatibility::Standard { dependency: dependency_standard, root: root_standard });
+        }
+        Ok(())
+    }
+}
+
+/// A C++ option whose value in a dependency is incompatible with its value in the root project
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CxxIncompatibility {
+    Rtti { dependency: bool, root: bool },
+    AsyncAwait { dependency: bool, root: bool },
+    /// The dependency requires a newer standard than the root project is compiled with
+    Standard { dependency: CxxStandard, root: CxxStandard },
+}
+
+impl fmt::Display for CxxIncompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CxxIncompatibility::Rtti { dependency, root } => write!(f, "rtti: dependency={}, root={}", dependency, root),
+            CxxIncompatibility::AsyncAwait { dependency, root } => write!(f, "async_await: dependency={}, root={}", dependency, root),
+            CxxIncompatibility::Standard { dependency, root } => write!(f, "standard: dependency={}, root={}", dependency.name(), root.name()),
+        }
+    }
+}
+
+/// Per-compile mode overrides of `CxxOptions::standard`
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+pub struct ModeStandards {
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub debug: Option<CxxStandard>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub release: Option<CxxStandard>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub relwithdebinfo: Option<CxxStandard>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub minsizerel: Option<CxxStandard>,
+}
+
+impl ModeStandards {
+    /// The names of all fields that may appear in the `cxx_options.mode_standards` object of a project file
+    pub const FIELD_NAMES: &'static [&'static str] = &["debug", "release", "relwithdebinfo", "minsizerel"];
+
+    pub fn is_empty(&self) -> bool {
+        self.debug.is_none() && self.release.is_none() && self.relwithdebinfo.is_none() && self.minsizerel.is_none()
     }
 }
 
@@ -37,11 +390,27 @@ impl Default for CxxOptions {
             rtti: false,
             async_await: true,
             standard: CxxStandard::Cxx20,
+            mode_standards: ModeStandards::default(),
+            debug_info_format: DebugInfoFormat::Pdb,
+            release_debug_info_format: None,
+            big_obj: false,
         }
     }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all="snake_case")]
+pub enum DebugInfoFormat {
+    /// Debug info for all objects is written to a single shared PDB (/Zi)
+    #[default]
+    Pdb,
+    /// Debug info is embedded in each object file (/Z7)
+    Embedded,
+    /// No debug info is generated, and the binary is linked without a PDB
+    None,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
 pub enum CxxStandard {
     #[serde(rename="c++11")]
     Cxx11,
@@ -50,16 +419,36 @@ pub enum CxxStandard {
     #[serde(rename="c++17")]
     Cxx17,
     #[serde(rename="c++20")]
+    #[default]
     Cxx20,
+    #[serde(rename="c++23")]
+    Cxx23,
+    /// The latest (possibly unstable) standard supported by the compiler
+    #[serde(rename="c++latest")]
+    Latest,
 }
 
 impl CxxStandard {
+    /// The name of the standard, as written in a project file
+    pub fn name(self) -> &'static str {
+        match self {
+            CxxStandard::Cxx11 => "c++11",
+            CxxStandard::Cxx14 => "c++14",
+            CxxStandard::Cxx17 => "c++17",
+            CxxStandard::Cxx20 => "c++20",
+            CxxStandard::Cxx23 => "c++23",
+            CxxStandard::Latest => "c++latest",
+        }
+    }
+
     fn numeric_value(self) -> u8 {
         match self {
             CxxStandard::Cxx11 => 11,
             CxxStandard::Cxx14 => 14,
             CxxStandard::Cxx17 => 17,
             CxxStandard::Cxx20 => 20,
+            CxxStandard::Cxx23 => 23,
+            CxxStandard::Latest => u8::MAX,
         }
     }
 }
@@ -76,12 +465,40 @@ impl Ord for CxxStandard {
     }
 }
 
-impl Default for CxxStandard {
-    fn default() -> Self {
-        CxxStandard::Cxx20
+/// The oldest version of Windows the project targets. Determines which Win32 APIs are declared by
+/// the Windows headers.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all="snake_case")]
+pub enum WindowsVersion {
+    Win7,
+    Win8,
+    Win81,
+    Win10,
+}
+
+impl WindowsVersion {
+    pub fn definitions(self) -> [(&'static str, &'static str); 3] {
+        let (winnt, ntddi) = match self {
+            WindowsVersion::Win7 => ("0x0601", "0x06010000"),
+            WindowsVersion::Win8 => ("0x0602", "0x06020000"),
+            WindowsVersion::Win81 => ("0x0603", "0x06030000"),
+            WindowsVersion::Win10 => ("0x0A00", "0x0A000000"),
+        };
+        [("_WIN32_WINNT", winnt), ("WINVER", winnt), ("NTDDI_VERSION", ntddi)]
     }
 }
 
+/// The UAC settings embedded in an executable's manifest
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all="snake_case")]
+pub enum UacExecutionLevel {
+    AsInvoker,
+    HighestAvailable,
+    RequireAdministrator,
+    /// Don't embed any UAC information in the manifest
+    Disabled,
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 #[serde(rename_all="snake_case")]
 pub enum OutputType {
@@ -91,6 +508,25 @@ pub enum OutputType {
     StaticLibrary,
 }
 
+impl OutputType {
+    /// The subsystem an executable is linked for, given an optional override of the default for its output type.
+    /// `None` for libraries.
+    pub fn subsystem(self, override_subsystem: Option<Subsystem>) -> Option<Subsystem> {
+        match self {
+            OutputType::GuiApp => Some(override_subsystem.unwrap_or(Subsystem::Windows)),
+            OutputType::ConsoleApp => Some(override_subsystem.unwrap_or(Subsystem::Console)),
+            OutputType::DynamicLibrary | OutputType::StaticLibrary => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all="snake_case")]
+pub enum Subsystem {
+    Console,
+    Windows,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, clap::Parser)]
 #[serde(rename_all="snake_case")]
 pub enum Platform {
@@ -100,13 +536,7 @@ pub enum Platform {
 impl Platform {
     pub fn host() -> Self {
         if cfg!(target_os = "windows") {
-            if cfg!(target_pointer_width = "32") {
-                Self::Win32
-            } else if cfg!(target_pointer_width = "64") {
-                Self::Win64
-            } else {
-                panic!("Unsupported host Windows bit width.");
-            }
+            Self::windows_host(usize::BITS, std::env::var_os("PROCESSOR_ARCHITEW6432").as_deref())
         } else if cfg!(target_os = "linux") {
             if cfg!(target_pointer_width = "32") {
                 Self::Linux32
@@ -120,6 +550,17 @@ impl Platform {
         }
     }
 
+    /// The Windows host that a build of ABS with the given pointer width is running on. A 32-bit build may be running
+    /// on a 64-bit host under WOW64, in which case Windows sets PROCESSOR_ARCHITEW6432 to the native architecture.
+    /// 64-bit Windows can run both Win32 and Win64 targets.
+    fn windows_host(pointer_width: u32, processor_architew6432: Option<&OsStr>) -> Self {
+        match (pointer_width, processor_architew6432) {
+            (32, None) => Self::Win32,
+            (32, Some(_)) | (64, _) => Self::Win64,
+            _ => panic!("Unsupported host Windows bit width."),
+        }
+    }
+
     pub fn os(&self) -> Os {
         match self {
             Platform::Win32 | Platform::Win64 => Os::Windows,
@@ -154,4 +595,191 @@ pub enum Os {
 
 pub enum Arch {
     X86, X64,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::config;
+
+    #[test]
+    fn metadata_fields_are_optional() {
+        let config = config("app");
+        assert_eq!(config.description, None);
+        assert!(config.authors.is_empty());
+        assert_eq!(config.version, None);
+
+        // Unset metadata isn't written back out
+        let value = serde_json::to_value(&config).unwrap();
+        for field in ["description", "authors", "version"] {
+            assert!(value.get(field).is_none(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn metadata_fields_round_trip() {
+        let mut config = config("app");
+        config.description = Some("An app".to_string());
+        config.authors = vec!["A".to_string(), "B".to_string()];
+        config.version = Some("1.2.3".to_string());
+        let config: ProjectConfig = serde_json::from_value(serde_json::to_value(&config).unwrap()).unwrap();
+        assert_eq!(config.description.as_deref(), Some("An app"));
+        assert_eq!(config.authors, ["A", "B"]);
+        assert_eq!(config.version.as_deref(), Some("1.2.3"));
+    }
+
+    #[test]
+    fn host_compatibility_of_targets() {
+        use Platform::*;
+        for (host, compatible) in [
+            (Win32, [Win32].as_slice()),
+            (Win64, &[Win32, Win64]),
+            (Linux32, &[Linux32]),
+            (Linux64, &[Linux32, Linux64]),
+        ] {
+            for target in [Win32, Win64, Linux32, Linux64] {
+                assert_eq!(host.is_backwards_compatible_with(target), compatible.contains(&target), "{:?} running {:?}", host, target);
+            }
+        }
+    }
+
+    #[test]
+    fn windows_hosts_are_detected_under_wow64() {
+        assert_eq!(Platform::windows_host(32, None), Platform::Win32);
+        // A 32-bit build of ABS on 64-bit Windows
+        assert_eq!(Platform::windows_host(32, Some(OsStr::new("AMD64"))), Platform::Win64);
+        assert_eq!(Platform::windows_host(64, None), Platform::Win64);
+    }
+
+    #[test]
+    fn targets_are_named_as_in_project_files() {
+        assert_eq!(serde_json::to_value(Platform::Win64).unwrap(), "win64");
+        assert_eq!(serde_json::from_value::<Platform>(serde_json::json!("linux32")).unwrap(), Platform::Linux32);
+    }
+
+    #[test]
+    fn subsystem_overrides_only_apply_to_apps() {
+        assert_eq!(OutputType::GuiApp.subsystem(None), Some(Subsystem::Windows));
+        assert_eq!(OutputType::GuiApp.subsystem(Some(Subsystem::Console)), Some(Subsystem::Console));
+        assert_eq!(OutputType::ConsoleApp.subsystem(None), Some(Subsystem::Console));
+        assert_eq!(OutputType::ConsoleApp.subsystem(Some(Subsystem::Windows)), Some(Subsystem::Windows));
+        // e.g., library members of a workspace built with `--subsystem`
+        assert_eq!(OutputType::DynamicLibrary.subsystem(Some(Subsystem::Console)), None);
+        assert_eq!(OutputType::StaticLibrary.subsystem(Some(Subsystem::Windows)), None);
+    }
+
+    #[test]
+    fn hardening_link_flags_only_apply_64_bit_features_to_64_bit_targets() {
+        let hardening = HardeningOptions { control_flow_guard: true, cet_compat: true, high_entropy_va: true };
+        assert_eq!(hardening.link_flags(Arch::X64), ["/GUARD:CF", "/CETCOMPAT", "/DYNAMICBASE", "/HIGHENTROPYVA"]);
+        assert_eq!(hardening.link_flags(Arch::X86), ["/GUARD:CF"]);
+        assert!(HardeningOptions::default().link_flags(Arch::X64).is_empty());
+    }
+
+    #[test]
+    fn hardening_field_names_are_complete() {
+        let hardening = HardeningOptions { control_flow_guard: true, cet_compat: true, high_entropy_va: true };
+        let value = serde_json::to_value(hardening).unwrap();
+        let mut names: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        let mut field_names = HardeningOptions::FIELD_NAMES.to_vec();
+        names.sort_unstable();
+        field_names.sort_unstable();
+        assert_eq!(names, field_names);
+        assert!(!hardening.is_empty());
+        assert!(HardeningOptions::default().is_empty());
+    }
+
+    #[test]
+    fn mode_standards_override_the_standard() {
+        let cxx_options: CxxOptions = serde_json::from_value(serde_json::json!({
+            "rtti": false,
+            "async_await": false,
+            "standard": "c++17",
+            "mode_standards": { "debug": "c++20", "minsizerel": "c++14" },
+        })).unwrap();
+        assert_eq!(cxx_options.standard_for(CompileMode::Debug), CxxStandard::Cxx20);
+        assert_eq!(cxx_options.standard_for(CompileMode::Release), CxxStandard::Cxx17);
+        assert_eq!(cxx_options.standard_for(CompileMode::RelWithDebInfo), CxxStandard::Cxx17);
+        assert_eq!(cxx_options.standard_for(CompileMode::MinSizeRel), CxxStandard::Cxx14);
+        // Only the overrides that were set are written back out
+        assert_eq!(serde_json::to_value(cxx_options.mode_standards).unwrap(), serde_json::json!({ "debug": "c++20", "minsizerel": "c++14" }));
+        assert!(CxxOptions::default().mode_standards.is_empty());
+    }
+
+    #[test]
+    fn dependencies_may_use_older_standards_but_must_match_other_options() {
+        let root = CxxOptions { standard: CxxStandard::Cxx20, ..CxxOptions::default() };
+        let dependency = |options: CxxOptions| options.check_compatible_with(&root, CompileMode::Debug);
+        assert_eq!(dependency(root), Ok(()));
+        assert_eq!(dependency(CxxOptions { standard: CxxStandard::Cxx17, ..root }), Ok(()));
+        assert_eq!(
+            dependency(CxxOptions { standard: CxxStandard::Latest, ..root }),
+            Err(CxxIncompatibility::Standard { dependency: CxxStandard::Latest, root: CxxStandard::Cxx20 }),
+        );
+        assert_eq!(
+            dependency(CxxOptions { rtti: !root.rtti, ..root }),
+            Err(CxxIncompatibility::Rtti { dependency: !root.rtti, root: root.rtti }),
+        );
+        assert_eq!(
+            dependency(CxxOptions { async_await: !root.async_await, ..root }),
+            Err(CxxIncompatibility::AsyncAwait { dependency: !root.async_await, root: root.async_await }),
+        );
+        // Options that only affect the dependency's own objects don't need to match
+        assert_eq!(dependency(CxxOptions { big_obj: true, debug_info_format: DebugInfoFormat::Embedded, ..root }), Ok(()));
+    }
+
+    #[test]
+    fn standards_are_compared_in_the_mode_being_built() {
+        let root = CxxOptions { standard: CxxStandard::Cxx17, ..CxxOptions::default() };
+        let dependency = CxxOptions {
+            mode_standards: ModeStandards { debug: Some(CxxStandard::Cxx20), ..ModeStandards::default() },
+            ..root
+        };
+        assert_eq!(dependency.check_compatible_with(&root, CompileMode::Release), Ok(()));
+        assert_eq!(
+            dependency.check_compatible_with(&root, CompileMode::Debug),
+            Err(CxxIncompatibility::Standard { dependency: CxxStandard::Cxx20, root: CxxStandard::Cxx17 }),
+        );
+        assert_eq!(
+            CxxIncompatibility::Standard { dependency: CxxStandard::Cxx20, root: CxxStandard::Cxx17 }.to_string(),
+            "standard: dependency=c++20, root=c++17",
+        );
+    }
+
+    #[test]
+    fn pinned_dependencies_keep_their_own_cxx_options() {
+        let mut root = config("app");
+        root.cxx_options.standard = CxxStandard::Cxx20;
+        root.hardening.control_flow_guard = true;
+
+        let mut dependency = config("dependency");
+        dependency.cxx_options.standard = CxxStandard::Cxx17;
+        dependency.adapt_to_workspace(&root);
+        assert_eq!(dependency.cxx_options.standard, CxxStandard::Cxx20);
+
+        let mut pinned = config("pinned");
+        pinned.cxx_options.standard = CxxStandard::Cxx17;
+        pinned.pin_cxx_options = true;
+        pinned.adapt_to_workspace(&root);
+        assert_eq!(pinned.cxx_options.standard, CxxStandard::Cxx17);
+        // Pinning only covers the C++ options
+        assert!(pinned.hardening.control_flow_guard);
+        assert_eq!(pinned.cxx_options.check_compatible_with(&root.cxx_options, CompileMode::Debug), Ok(()));
+    }
+
+    #[test]
+    fn cxx23_is_named_and_ordered_between_cxx20_and_latest() {
+        assert_eq!(serde_json::to_value(CxxStandard::Cxx23).unwrap(), "c++23");
+        assert_eq!(serde_json::from_value::<CxxStandard>(serde_json::json!("c++23")).unwrap(), CxxStandard::Cxx23);
+        assert_eq!(CxxStandard::Cxx23.name(), "c++23");
+        assert!(CxxStandard::Cxx20 < CxxStandard::Cxx23);
+        assert!(CxxStandard::Cxx23 < CxxStandard::Latest);
+        // So a C++20 project can't depend on a C++23 one
+        let mut dependency = config("dependency").cxx_options;
+        dependency.standard = CxxStandard::Cxx23;
+        let root = config("app").cxx_options;
+        assert_eq!(
+            dependency.check_compatible_with(&root, CompileMode::Debug),
+            Err(CxxIncompatibility::Standard { dependency: CxxStandard::Cxx23, root: CxxStandard::Cxx20 }),
+        );
+    }
+}
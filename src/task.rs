@@ -1,6 +1,8 @@
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::sync::atomic::Ordering;
 
 use async_trait::async_trait;
 use tokio::sync::mpsc;
@@ -8,10 +10,10 @@ use tokio::task;
 use indicatif::ProgressBar;
 
 // TODO: should not depend on BuildEnvironment
-use crate::build::{WarningCache, BuildEnvironment, BuildError, PchOption, DependencyBuilder};
+use crate::build::{exit_code_suffix, WarningCache, BuildEnvironment, BuildError, PchOption, DependencyBuilder, MissingDependency, create_output_dir, retry_output_io_async, args_hash};
 use crate::cmd_options::CompileMode;
-use crate::proj_config::{Platform, Os};
-use crate::build_manager::{compile_cxx, CompileFlags, CompilerOutput};
+use crate::proj_config::{Platform, Os, Arch, DebugInfoFormat};
+use crate::build_manager::{compile_cxx, run_cmd, CmdResult, CompileFlags, CompilerOutput, OutputLine};
 use crate::println_above_progress_bar_if_visible;
 
 #[async_trait]
@@ -48,150 +50,403 @@ impl Task for IdentityTask {
     }
 }
 
+/// Whether a compile failed because the object file would have had more sections than it can hold without /bigobj
+fn exceeds_section_limit(diagnostics: &[String]) -> bool {
+    diagnostics.iter().any(|diagnostic| diagnostic.contains("error C1128"))
+}
+
 pub struct CxxTask { src: Box<dyn TaskExt + Sync + Send>, pch: PchOption }
 
 impl CxxTask {
     pub fn compile(src: impl Into<PathBuf>, pch: PchOption) -> Self {
         Self { src: Box::new(IdentityTask(src.into())), pch }
     }
-}
-
-#[async_trait]
-impl Task for CxxTask {
-    fn previous_valid_run(&self, env: &BuildEnvironment) -> Result<Option<PathBuf>, BuildError> {
-        let path = self.src.previous_valid_run(env)?;
-        let path = if let Some(path) = path {
-            path
-        } else {
-            return Ok(None)
-        };
-        let generating_pch = matches!(self.pch, PchOption::GeneratePch);
-        let extension = if generating_pch {
-            "pch"
-        } else {
-            "obj"
-        };
-        let artifact_path = env.get_artifact_path(&path, &env.objs_path, extension);
-        let is_pch = path.file_name() == Some(OsStr::new("pch.cpp")) && path.parent() == Some(&env.src_dir_path);
-        let dependencies = env.discover_src_deps(&path)?.map(|dependencies| {
-            DependencyBuilder::default()
-                .file(path)
-                .files(dependencies)
-                .build()
-        });
-
-        let should_rebuild = (generating_pch || !is_pch) && if let Some(dependencies) = &dependencies {
-            env.should_build_artifact(dependencies, &artifact_path)?
-        } else {
-            true
-        };
 
-        if should_rebuild {
-            Ok(None)
-        } else {
-            Ok(Some(artifact_path))
-        }
+    /// The flags with which to compile the source at `path`, and the path of the resulting object file
+    pub fn compile_flags(env: &BuildEnvironment, path: &Path, pch: PchOption) -> Result<(CompileFlags, PathBuf), BuildError> {
+        Self::compile_flags_on(Platform::host().os(), env, path, pch)
     }
 
-    async fn run_guaranteed(&self, env: &BuildEnvironment) -> Result<PathBuf, BuildError> {
-        let path = self.src.run(env).await?;
-        let host = Platform::host();
+    /// Like `compile_flags`, but for the toolchain of `host` rather than that of the actual host
+    pub fn compile_flags_on(host: Os, env: &BuildEnvironment, path: &Path, pch: PchOption) -> Result<(CompileFlags, PathBuf), BuildError> {
         let obj_path = env.objs_path.clone();
         // TODO: instead of matching over the host OS here, perhaps it would be better to match over the compiler vendor
-        let (flags, obj_path) = match host.os() {
+        let (flags, obj_path) = match host {
             Os::Windows => {
                 let mut flags = CompileFlags::empty()
                     .singles([
+                        "/nologo",
                         "/W3",
-                        "/Zi",
                         "/EHsc",
                         "/c",
-                        "/FS",
                     ])
+                    .debug_info_format(env.config.cxx_options.debug_info_format_for(env.build_options.compile_mode))
                     .rtti(env.config.cxx_options.rtti)
                     .async_await(env.config.cxx_options.async_await)
-                    .cxx_standard(env.config.cxx_options.standard);
+                    .cxx_standard(env.config.cxx_options.standard_for(env.build_options.compile_mode));
 
+                if env.config.hardening.control_flow_guard {
+                    flags = flags.single("/guard:cf");
+                }
+                if env.config.cxx_options.big_obj {
+                    flags = flags.single("/bigobj");
+                }
+                if env.config.reproducible {
+                    // Omit timestamps, and refer to sources relative to the project directory rather than by
+                    // absolute path
+                    let mut path_mapping = env.project_path.clone().into_os_string();
+                    path_mapping.push("=.");
+                    flags = flags
+                        .single("/Brepro")
+                        .double("/pathmap:", path_mapping);
+                }
                 match env.build_options.compile_mode {
                     CompileMode::Debug => flags = flags.singles(["/MDd", "/RTC1"]),
                     CompileMode::Release => flags = flags.single("/O2"),
+                    // Improves the debug info emitted for optimized code (e.g., for inlined functions and locals)
+                    CompileMode::RelWithDebInfo => flags = flags.singles(["/O2", "/Zo"]),
+                    CompileMode::MinSizeRel => flags = flags.single("/O1"),
                 }
                 flags = flags
                     .defines(env.definitions.iter().cloned())
                     .include_paths(&env.toolchain_paths.include_paths)
-                    .include_paths([
-                        &env.dependency_headers_path,
-                        &env.src_dir_path,
-                    ]);
-                match self.pch {
+                    .include_paths(env.include_paths());
+                match pch {
                     PchOption::GeneratePch | PchOption::UsePch => {
                         let path = env.get_artifact_path(env.src_dir_path.join("pch.h"), &obj_path, "pch");
-                        flags = flags.pch_path(path, matches!(self.pch, PchOption::GeneratePch));
+                        flags = flags.pch_path(path, matches!(pch, PchOption::GeneratePch));
                     },
                     _ => {}
                 }
-                let src_deps_json_path = env.get_artifact_path(&path, &env.src_deps_path, "json");
+                let src_deps_json_path = env.get_artifact_path(path, &env.src_deps_path, "json");
                 let src_deps_parent = src_deps_json_path.parent().unwrap();
-                fs::create_dir_all(src_deps_parent)?;
-                let obj_path = env.get_artifact_path(&path, &obj_path, "obj");
+                create_output_dir(src_deps_parent)?;
+                let obj_path = env.get_artifact_path(path, &obj_path, "obj");
+                flags = flags.obj_path(&obj_path);
+                if matches!(env.config.cxx_options.debug_info_format_for(env.build_options.compile_mode), DebugInfoFormat::Pdb) {
+                    flags = flags.double("/Fd", env.objs_path.join(format!("{}.pdb", &env.config.name)));
+                }
+                // Last, so that they can override any of the above
+                flags = flags.singles(&env.extra_compile_flags);
                 flags = flags
-                    .obj_path(&obj_path)
-                    .double("/Fd", env.objs_path.join(&format!("{}.pdb", &env.config.name)))
                     .double("/sourceDependencies", src_deps_json_path)
-                    .src_path(&path);
+                    .src_path(path);
+                if let Some(conflict) = flags.conflict() {
+                    return Err(BuildError::ConflictingCompileFlags(conflict));
+                }
                 (flags, obj_path)
             },
             Os::Linux => {
                 // TODO: implement
-                (CompileFlags::empty(), env.get_artifact_path(&path, &obj_path, "o"))
+                let mut flags = CompileFlags::empty();
+                if env.build_options.time_trace {
+                    // Writes a trace with the same name as the object file, but a .json extension
+                    flags = flags.single("-ftime-trace");
+                }
+                flags = flags.singles(&env.extra_compile_flags);
+                (flags, env.get_artifact_path(path, &obj_path, "o"))
             }
         };
+        Ok((flags, obj_path))
+    }
+
+    /// Where the hash of the flags that the precompiled header generated from `path` was last generated with is
+    /// recorded. The precompiled header encodes those flags (e.g., whether RTTI is enabled), so it must be regenerated
+    /// whenever they change, even if none of its sources have.
+    fn pch_flags_path(env: &BuildEnvironment, path: &Path) -> PathBuf {
+        env.get_artifact_path(path, &env.objs_path, "flags")
+    }
+
+    fn pch_flags_hash(env: &BuildEnvironment, path: &Path) -> Result<String, BuildError> {
+        let (flags, _) = Self::compile_flags(env, path, PchOption::GeneratePch)?;
+        Ok(format!("{:016x}", args_hash(&flags.build())))
+    }
+
+    /// Runs just the compiler's front end on the source at `path`, which records its header dependencies in src_deps
+    /// without generating an object
+    pub async fn scan_dependencies(env: &BuildEnvironment<'_>, path: &Path, pch: PchOption) -> Result<(), BuildError> {
+        let (flags, _) = Self::compile_flags(env, path, pch)?;
+        // Only checks syntax, which still processes every include
+        let (result, _, diagnostics) = Self::compile_collecting_output(env, flags.single("/Zs")).await;
+        match result {
+            CmdResult::Succeeded => Ok(()),
+            CmdResult::Failed(code) => {
+                env.print_diagnostics(path, &diagnostics);
+                Err(BuildError::CompilerError(code))
+            },
+            CmdResult::TimedOut => Err(BuildError::CompileTimedOut(path.to_owned(), env.build_options.compile_timeout.unwrap())),
+        }
+    }
 
+    /// Compiles with `flags`, and returns how compilation ended, along with the warnings and diagnostics it produced
+    async fn compile_collecting_output(env: &BuildEnvironment<'_>, flags: CompileFlags) -> (CmdResult, WarningCache, Vec<String>) {
         let (tx, mut rx) = mpsc::unbounded_channel::<CompilerOutput>();
-        let unique_output = env.unique_compiler_output.clone();
-        let progress_bar = env.progress_bar.lock().unwrap().clone();
         let handle = task::spawn(async move {
-            // let unique_output = ;
             let mut warning_cache = WarningCache::default();
+            // Diagnostics are buffered so that they can be printed together once the file is done
+            // compiling, rather than interleaved with those of other files.
+            let mut diagnostics = Vec::new();
             while let Some(output) = rx.recv().await {
-                match &output {
+                match output {
                     CompilerOutput::Begun { .. } => {},
-                    CompilerOutput::Error(s) | CompilerOutput::Warning(s) => {
-                        if unique_output.lock().unwrap().insert(s.lines().next().unwrap().to_string()) {
-                            println_above_progress_bar_if_visible!(progress_bar, "{}", s);
-                        }
-                        if matches!(output, CompilerOutput::Warning(_)) {
-                            warning_cache.warnings.push(s.clone());
-                        }
-                    }
+                    CompilerOutput::Error(s) => diagnostics.push(s),
+                    CompilerOutput::Warning(s) => {
+                        warning_cache.warnings.push(s.clone());
+                        diagnostics.push(s);
+                    },
                 }
             }
-            warning_cache
+            (warning_cache, diagnostics)
+        });
+        let timeout = env.build_options.compile_timeout.map(Duration::from_secs);
+        let result = compile_cxx(env.toolchain_paths, flags, timeout, tx).await;
+        let (warning_cache, diagnostics) = handle.await.unwrap();
+        (result, warning_cache, diagnostics)
+    }
+}
+
+#[async_trait]
+impl Task for CxxTask {
+    fn previous_valid_run(&self, env: &BuildEnvironment) -> Result<Option<PathBuf>, BuildError> {
+        let path = self.src.previous_valid_run(env)?;
+        let path = if let Some(path) = path {
+            path
+        } else {
+            return Ok(None)
+        };
+        let generating_pch = matches!(self.pch, PchOption::GeneratePch);
+        let extension = if generating_pch {
+            "pch"
+        } else {
+            "obj"
+        };
+        let artifact_path = env.get_artifact_path(&path, &env.objs_path, extension);
+        let is_pch = path.file_name() == Some(OsStr::new("pch.cpp")) && path.parent() == Some(&env.src_dir_path);
+        let flags_changed = generating_pch &&
+            fs::read_to_string(Self::pch_flags_path(env, &path)).ok() != Some(Self::pch_flags_hash(env, &path)?);
+        let dependencies = env.discover_src_deps(&path)?.map(|dependencies| {
+            let mut builder = DependencyBuilder::default()
+                .file(&path)
+                .files(dependencies);
+            // Objects compiled against an outdated precompiled header must be rebuilt once it's regenerated
+            if matches!(self.pch, PchOption::UsePch) {
+                builder = builder.file(env.get_artifact_path(env.src_dir_path.join("pch.cpp"), &env.objs_path, "pch"));
+            }
+            builder.build()
         });
 
-        let val = if compile_cxx(&env.toolchain_paths, flags, tx).await {
-            Ok(obj_path)
+        let should_rebuild = (generating_pch || !is_pch) && (flags_changed || if let Some(dependencies) = &dependencies {
+            env.should_build_artifact(dependencies, MissingDependency::Rebuild, &artifact_path)?
+        } else {
+            true
+        });
+
+        if should_rebuild {
+            Ok(None)
         } else {
-            Err(BuildError::CompilerError)
+            Ok(Some(artifact_path))
+        }
+    }
+
+    async fn run_guaranteed(&self, env: &BuildEnvironment) -> Result<PathBuf, BuildError> {
+        let path = self.src.run(env).await?;
+        let (flags, obj_path) = Self::compile_flags(env, &path, self.pch)?;
+
+        let (mut result, mut warning_cache, mut diagnostics) = Self::compile_collecting_output(env, flags).await;
+        // Users rarely know that /bigobj exists until they need it, so rather than fail, retry with it
+        if matches!(result, CmdResult::Failed(_)) && !env.config.cxx_options.big_obj && exceeds_section_limit(&diagnostics) {
+            println_above_progress_bar_if_visible!(env.progress_bar.lock().unwrap(), "Note: \"{}\" exceeds the object file section limit, so it will be recompiled with /bigobj. To skip the failed first attempt, add `\"big_obj\": true` to cxx_options in abs.json.", path.as_os_str().to_string_lossy());
+            let (flags, _) = Self::compile_flags(env, &path, self.pch)?;
+            (result, warning_cache, diagnostics) = Self::compile_collecting_output(env, flags.single("/bigobj")).await;
+        }
+        // Every diagnostic that isn't a warning is an error
+        let reported_error = diagnostics.len() > warning_cache.warnings.len();
+        // Diagnostics in shared headers are only printed for the first file that produces them
+        let diagnostics: Vec<String> = diagnostics.into_iter()
+            .filter(|diagnostic| env.unique_compiler_output.lock().unwrap().insert(diagnostic.lines().next().unwrap().to_string()))
+            .collect();
+        let num_new_errors = diagnostics.iter()
+            .filter(|diagnostic| !warning_cache.warnings.contains(diagnostic))
+            .count();
+        env.num_errors.fetch_add(num_new_errors, Ordering::Relaxed);
+
+        let val = match result {
+            CmdResult::Succeeded => {
+                if env.build_options.dep_files {
+                    let (dep_file_path, contents) = env.dep_file(&path)?;
+                    retry_output_io_async(&dep_file_path, || fs::write(&dep_file_path, &contents)).await?;
+                }
+                if matches!(self.pch, PchOption::GeneratePch) {
+                    let flags_path = Self::pch_flags_path(env, &path);
+                    let flags_hash = Self::pch_flags_hash(env, &path)?;
+                    retry_output_io_async(&flags_path, || fs::write(&flags_path, &flags_hash)).await?;
+                }
+                Ok(obj_path)
+            },
+            CmdResult::Failed(code) => {
+                // e.g., the compiler crashed, so the code is the only clue
+                if !reported_error {
+                    println_above_progress_bar_if_visible!(env.progress_bar.lock().unwrap(), "Failed to compile \"{}\" without an error being reported{}.", path.as_os_str().to_string_lossy(), exit_code_suffix("compiler", code));
+                }
+                Err(BuildError::CompilerError(code))
+            },
+            CmdResult::TimedOut => Err(BuildError::CompileTimedOut(path.clone(), env.build_options.compile_timeout.unwrap())),
         };
         if let Some(progress_bar) = env.progress_bar.lock().unwrap().upgrade() {
             progress_bar.inc(1);
         }
-        let warning_cache = handle.await.unwrap();
+        env.print_diagnostics(&path, &diagnostics);
+        env.record_warnings(&path, &warning_cache.warnings);
         let warning_cache_path = env.get_artifact_path(path, &env.warning_cache_path, "warnings");
         if let Some(parent) = warning_cache_path.parent() {
-            fs::create_dir_all(parent)?;
+            create_output_dir(parent)?;
         }
         let warning_cache = serde_json::to_string(&warning_cache).unwrap();
-        fs::write(warning_cache_path, warning_cache)?;
+        retry_output_io_async(&warning_cache_path, || fs::write(&warning_cache_path, &warning_cache)).await?;
         val
     }
 }
 
+pub struct AsmTask { src: PathBuf }
+
+impl AsmTask {
+    pub fn assemble(src: impl Into<PathBuf>) -> Self {
+        Self { src: src.into() }
+    }
+
+    /// The object file produced for an assembly source. Uses a distinct extension so that e.g.
+    /// foo.asm and foo.cpp in the same directory don't collide.
+    pub fn obj_path(env: &BuildEnvironment, src: impl AsRef<Path>) -> PathBuf {
+        env.get_artifact_path(src, &env.objs_path, "asm.obj")
+    }
+
+    /// The assembler to run for an assembly source, and the arguments to run it with
+    pub fn assemble_command(env: &BuildEnvironment, src: &Path) -> Result<(&'static str, Vec<OsString>), BuildError> {
+        let obj_path = Self::obj_path(env, src);
+        let host = Platform::host();
+        let command = match host.os() {
+            Os::Windows => {
+                let mut args: Vec<OsString> = vec!["/nologo".into(), "/c".into()];
+                let assembler = match env.target.architecture() {
+                    Arch::X64 => "ml64.exe",
+                    Arch::X86 => {
+                        args.push("/safeseh".into());
+                        "ml.exe"
+                    },
+                };
+                let debug_info_format = env.config.cxx_options.debug_info_format_for(env.build_options.compile_mode);
+                if matches!(env.build_options.compile_mode, CompileMode::Debug | CompileMode::RelWithDebInfo) && debug_info_format != DebugInfoFormat::None {
+                    args.push("/Zi".into());
+                }
+                let mut obj_flag = OsString::from("/Fo");
+                obj_flag.push(&obj_path);
+                args.push(obj_flag);
+                args.push(src.into());
+                (assembler, args)
+            },
+            Os::Linux => {
+                // TODO: implement
+                return Err(BuildError::AssemblerError(None));
+            }
+        };
+        Ok(command)
+    }
+}
+
+#[async_trait]
+impl Task for AsmTask {
+    fn previous_valid_run(&self, env: &BuildEnvironment) -> Result<Option<PathBuf>, BuildError> {
+        let obj_path = Self::obj_path(env, &self.src);
+        if env.should_build_artifact([&self.src], MissingDependency::Fail, &obj_path)? {
+            Ok(None)
+        } else {
+            Ok(Some(obj_path))
+        }
+    }
+
+    async fn run_guaranteed(&self, env: &BuildEnvironment) -> Result<PathBuf, BuildError> {
+        let obj_path = Self::obj_path(env, &self.src);
+        if let Some(parent) = obj_path.parent() {
+            create_output_dir(parent)?;
+        }
+        let (assembler, args) = Self::assemble_command(env, &self.src)?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<OutputLine>();
+        let progress_bar = env.progress_bar.lock().unwrap().clone();
+        let handle = task::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                let line = match line {
+                    OutputLine::Stdout(line) | OutputLine::Stderr(line) => line,
+                };
+                // Skip blank lines and the progress message printed for each file
+                if !line.trim().is_empty() && !line.trim_start().starts_with("Assembling:") {
+                    println_above_progress_bar_if_visible!(progress_bar, "{}", line);
+                }
+            }
+        });
+        let result = run_cmd(assembler, &args, &env.toolchain_paths.bin_paths, None, tx).await;
+        handle.await.unwrap();
+        if let Some(progress_bar) = env.progress_bar.lock().unwrap().upgrade() {
+            progress_bar.inc(1);
+        }
+        match result {
+            CmdResult::Succeeded => Ok(obj_path),
+            CmdResult::Failed(code) => Err(BuildError::AssemblerError(code)),
+            CmdResult::TimedOut => Err(BuildError::AssemblerError(None)),
+        }
+    }
+}
+
 /*
 fn build() {
     let task = CxxTask::compile("hello.cpp");
     task.run();
 }
 
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{set_age, TestProject};
+
+    #[test]
+    fn section_limit_errors_are_recognized() {
+        let diagnostics = |diagnostics: &[&str]| diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<_>>();
+        assert!(exceeds_section_limit(&diagnostics(&[
+            r"C:\project\src\main.cpp(10): warning C4244: 'argument': conversion from 'double' to 'int'",
+            r"C:\project\src\main.cpp : fatal error C1128: number of sections exceeded object file format limit: compile with /bigobj",
+        ])));
+        assert!(!exceeds_section_limit(&diagnostics(&[r"C:\project\src\C1128.cpp(1): error C2065: 'x': undeclared identifier"])));
+        assert!(!exceeds_section_limit(&[]));
+    }
+
+    #[test]
+    fn precompiled_headers_are_regenerated_when_their_flags_change() {
+        let mut project = TestProject::new("app");
+        let pch_src_path = project.dir.write("src/pch.cpp", "#include \"pch.h\"");
+        let task = CxxTask::compile(&pch_src_path, PchOption::GeneratePch);
+
+        // As if the precompiled header was just generated
+        let generated = project.env();
+        let src_deps_path = generated.get_artifact_path(&pch_src_path, &generated.src_deps_path, "json");
+        let pch_path = generated.get_artifact_path(&pch_src_path, &generated.objs_path, "pch");
+        let flags_path = CxxTask::pch_flags_path(&generated, &pch_src_path);
+        fs::write(&src_deps_path, r#"{ "Version": "1.1", "Data": { "Includes": [] } }"#).unwrap();
+        fs::write(&pch_path, "").unwrap();
+        fs::write(&flags_path, CxxTask::pch_flags_hash(&generated, &pch_src_path).unwrap()).unwrap();
+        drop(generated);
+        project.make_fresh(&[&pch_src_path]);
+        set_age(&[&src_deps_path, &pch_path], 50);
+        assert_eq!(task.previous_valid_run(&project.env()).unwrap(), Some(pch_path));
+
+        let rtti = project.config.cxx_options.rtti;
+        project.config.cxx_options.rtti = !rtti;
+        // RTTI is only passed to the compiler on Windows so far
+        if matches!(Platform::host().os(), Os::Windows) {
+            assert_eq!(task.previous_valid_run(&project.env()).unwrap(), None);
+        }
+        project.config.cxx_options.rtti = rtti;
+        fs::write(&flags_path, "0000000000000000").unwrap();
+        assert_eq!(task.previous_valid_run(&project.env()).unwrap(), None);
+    }
+}
@@ -0,0 +1,123 @@
+// Helpers shared by the unit tests of the other modules
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::Parser;
+use filetime::FileTime;
+
+use crate::build::BuildEnvironment;
+use crate::cmd_options::BuildOptions;
+use crate::proj_config::{Platform, ProjectConfig};
+use crate::toolchain_paths::{ToolchainPaths, Vendor};
+
+/// A uniquely-named directory under the system temp directory, which is removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("abs-test-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path.canonicalize().unwrap())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `contents` to `relative_path`, creating its parent directories as needed
+    pub fn write(&self, relative_path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(relative_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A minimal console app config named `name` that supports win64, with every optional field left at its default
+pub fn config(name: &str) -> ProjectConfig {
+    serde_json::from_value(serde_json::json!({
+        "name": name,
+        "cxx_options": { "rtti": false, "async_await": true, "standard": "c++20" },
+        "output_type": "console_app",
+        "link_libraries": [],
+        "supported_targets": ["win64"],
+        "dependencies": [],
+    })).unwrap()
+}
+
+/// Build options parsed from `args`, as if they were passed to `abs build`
+pub fn build_options(args: &[&str]) -> BuildOptions {
+    BuildOptions::parse_from(std::iter::once("build").chain(args.iter().copied()))
+}
+
+/// A toolchain with no tools or directories, which is enough to set up a build without running anything
+pub fn toolchain() -> ToolchainPaths {
+    ToolchainPaths {
+        debugger_path: PathBuf::new(),
+        clang_format_path: None,
+        signtool_path: None,
+        include_paths: Vec::new(),
+        lib_paths: Vec::new(),
+        bin_paths: Vec::new(),
+        vendor: Vendor::Clang,
+        msvc_version: None,
+        details: Vec::new(),
+    }
+}
+
+/// Makes the files in `paths` appear to have been last modified `seconds_ago`
+pub fn set_age(paths: &[&Path], seconds_ago: i64) {
+    let now = FileTime::now();
+    let time = FileTime::from_unix_time(now.unix_seconds() - seconds_ago, 0);
+    for path in paths {
+        filetime::set_file_mtime(path, time).unwrap();
+    }
+}
+
+/// A project in a temporary directory, with everything needed to set up a debug build of it for win64. The fields may
+/// be changed before calling `env()`.
+pub struct TestProject {
+    pub dir: TempDir,
+    pub config: ProjectConfig,
+    pub config_path: PathBuf,
+    pub build_options: BuildOptions,
+    pub toolchain_paths: ToolchainPaths,
+    pub artifact_path: PathBuf,
+}
+
+impl TestProject {
+    /// A project named `name`, whose directory is the temporary directory itself
+    pub fn new(name: &str) -> Self {
+        let dir = TempDir::new();
+        let config_path = dir.write("abs.json", "{}");
+        let artifact_path = dir.path().join("abs/debug").join(name).join("Win64");
+        TestProject { config: config(name), config_path, build_options: build_options(&[]), toolchain_paths: toolchain(), artifact_path, dir }
+    }
+
+    pub fn env(&self) -> BuildEnvironment<'_> {
+        self.env_with_definitions(&[])
+    }
+
+    pub fn env_with_definitions<'a>(&'a self, definitions: &'a [(&'a str, &'a str)]) -> BuildEnvironment<'a> {
+        BuildEnvironment::new(&self.config, &self.config_path, &self.build_options, Platform::Win64, &self.toolchain_paths, definitions, &self.artifact_path).unwrap()
+    }
+
+    /// Makes `paths`, and the files that everything built for the project depends on, look older than what's built
+    /// afterwards (i.e., anything made to look younger than 100 seconds). The environment must have been set up once
+    /// already, since that's what writes those files.
+    pub fn make_fresh(&self, paths: &[&Path]) {
+        set_age(paths, 100);
+        set_age(&[&self.config_path, &self.artifact_path.join("env_flags"), &self.artifact_path.join("definitions")], 100);
+    }
+}
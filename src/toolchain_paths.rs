@@ -1,19 +1,28 @@
 use std::path::{PathBuf, Path};
-use std::io::Error as IoError;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::time::SystemTime;
 use std::ffi::OsString;
 use std::cmp::Ordering;
 use std::fs;
 
 use crate::Platform;
-use crate::proj_config::{Arch, Os};
+use crate::proj_config::{Arch, Os, CxxStandard};
 
 pub struct ToolchainPaths {
     pub debugger_path: PathBuf,
+    /// The clang-format executable that ships with the toolchain, if any
+    pub clang_format_path: Option<PathBuf>,
+    /// The signtool executable that ships with the Windows SDK, if any
+    pub signtool_path: Option<PathBuf>,
     pub include_paths: Vec<PathBuf>,
     pub lib_paths: Vec<PathBuf>,
     pub bin_paths: Vec<PathBuf>,
     pub vendor: Vendor,
+    /// The version of the MSVC toolset (e.g., [14, 29, 30133]), if the toolchain is MSVC
+    pub msvc_version: Option<[u64; 3]>,
+    /// The versions and other properties of the installation that were selected, as (description, value) pairs, for
+    /// troubleshooting
+    pub details: Vec<(&'static str, String)>,
 }
 
 #[derive(Copy, Clone)]
@@ -40,6 +49,34 @@ fn parse_version<const N: usize>(version: &str) -> Option<[u64; N]> {
 
 
 impl ToolchainPaths {
+    /// The executables that must be present in `bin_paths` in order to build anything
+    pub fn required_tools(&self) -> &'static [&'static str] {
+        match self.vendor {
+            Vendor::Msvc => &["cl.exe", "link.exe", "lib.exe"],
+            // TODO: Clang support is incomplete, so there is nothing to check yet
+            Vendor::Clang => &[],
+        }
+    }
+
+    /// Returns the first required tool that can't be found in `bin_paths`, if any
+    pub fn missing_tool(&self) -> Option<&'static str> {
+        self.required_tools().iter()
+            .copied()
+            .find(|tool| !self.bin_paths.iter().any(|bin_path| bin_path.join(tool).is_file()))
+    }
+
+    /// If the toolchain is too old to understand the `/std` flag for `standard`, returns the oldest version that does
+    pub fn unsupported_standard(&self, standard: CxxStandard) -> Option<&'static str> {
+        let version = self.msvc_version?;
+        let (minimum, description) = match standard {
+            CxxStandard::Cxx20 => ([14, 29], "MSVC 14.29 (Visual Studio 2019 version 16.11)"),
+            CxxStandard::Cxx23 => ([14, 43], "MSVC 14.43 (Visual Studio 2022 version 17.13)"),
+            // Older standards have been supported for as long as `/std` has, and `/std:c++latest` always works
+            CxxStandard::Cxx11 | CxxStandard::Cxx14 | CxxStandard::Cxx17 | CxxStandard::Latest => return None,
+        };
+        (version[..2] < minimum[..]).then_some(description)
+    }
+
     pub fn find(target: Platform) -> Result<ToolchainPaths, IoError> {
         // TODO: Detect toolchain in a much more robust way
         let vendor = match target.os() {
@@ -51,7 +88,11 @@ impl ToolchainPaths {
                 let mut path = PathBuf::from(r"C:\Program Files (x86)");
                 let program_files = path.clone();
                 path.push("Microsoft Visual Studio");
-                let year = fs::read_dir(&path)?.filter_map(|entry| {
+                let not_found = |what: &str, path: &Path| IoError::new(
+                    IoErrorKind::NotFound,
+                    format!("no {} found in \"{}\"", what, path.as_os_str().to_string_lossy()),
+                );
+                let year = fs::read_dir(&path).map_err(|_| not_found("Visual Studio installation", &path))?.filter_map(|entry| {
                     entry.ok()
                         .filter(|entry| 
                             entry.file_type().ok()
@@ -64,7 +105,7 @@ impl ToolchainPaths {
                         )
                 })
                     .max()
-                    .unwrap();
+                    .ok_or_else(|| not_found("Visual Studio installation", &path))?;
                 path.push(year.to_string());
                 // Pick the name of the newest folder ("Community", "Preview", etc.).
                 // TODO: more principled way of choosing edition.
@@ -81,6 +122,10 @@ impl ToolchainPaths {
                         }
                     }
                 }
+                let mut details = vec![
+                    ("Visual Studio year", year.to_string()),
+                    ("Visual Studio edition", edition.to_string_lossy().into_owned()),
+                ];
                 path.push(edition);
                 let edition = path.clone();
 
@@ -88,9 +133,9 @@ impl ToolchainPaths {
 
 
                 fn newest_version<P: AsRef<Path>, const N: usize>(parent: P) -> Option<PathBuf> {
-                    fs::read_dir(parent.as_ref()).unwrap()
+                    fs::read_dir(parent.as_ref()).ok()?
                         .filter_map(|entry| {
-                            entry.unwrap().file_name().to_str()
+                            entry.ok()?.file_name().to_str()
                                 .and_then(parse_version)
                         }).max_by(|a: &[u64; N], b: &[u64; N]| {
                         for (a, b) in a.iter().zip(b.iter()) {
@@ -107,14 +152,17 @@ impl ToolchainPaths {
                             if i > 0 {
                                 name.push('.');
                             }
-                            name.extend(num.to_string().chars());
+                            name.push_str(&num.to_string());
                         }
                         PathBuf::from(name)
                     })
                 }
 
-                // TODO: error handling
-                path.push(newest_version::<_, 3>(&path).unwrap());
+                let msvc_version = newest_version::<_, 3>(&path)
+                    .ok_or_else(|| not_found("MSVC version", &path))?;
+                details.push(("MSVC version", msvc_version.to_string_lossy().into_owned()));
+                let parsed_msvc_version = parse_version(&msvc_version.to_string_lossy());
+                path.push(msvc_version);
                 let version = path.clone();
 
                 let target = match target.architecture() {
@@ -158,6 +206,18 @@ impl ToolchainPaths {
                 path.push(target);
                 lib_paths.push(path);
 
+                // Installed by Visual Studio's optional "C++ Clang tools for Windows" component
+                let mut path = edition.clone();
+                path.push("VC");
+                path.push("Tools");
+                path.push("Llvm");
+                if host == "x64" {
+                    path.push("x64");
+                }
+                path.push("bin");
+                path.push("clang-format.exe");
+                let clang_format_path = Some(path).filter(|path| path.is_file());
+
                 let mut path = edition;
                 path.push("Common7");
                 path.push("IDE");
@@ -170,8 +230,10 @@ impl ToolchainPaths {
                 let win10 = path.clone();
 
                 path.push("Include");
-                // TODO: error handling
-                path.push(newest_version::<_, 4>(&path).unwrap());
+                let sdk_version = newest_version::<_, 4>(&path)
+                    .ok_or_else(|| not_found("Windows SDK version", &path))?;
+                details.push(("Windows SDK version", sdk_version.to_string_lossy().into_owned()));
+                path.push(sdk_version);
                 // include_paths.push(path.clone());
                 for &name in &["ucrt", "shared", "um", "winrt"] {
                     path.push(name);
@@ -181,8 +243,7 @@ impl ToolchainPaths {
 
                 let mut path = win10.clone();
                 path.push("Lib");
-                // TODO: error handling
-                path.push(newest_version::<_, 4>(&path).unwrap());
+                path.push(newest_version::<_, 4>(&path).ok_or_else(|| not_found("Windows SDK version", &path))?);
                 for &name in &["ucrt", "um"] {
                     path.push(name);
                     path.push(target);
@@ -193,38 +254,99 @@ impl ToolchainPaths {
 
                 let mut path = win10.clone();
                 path.push("bin");
-                // TODO: error handling
-                path.push(newest_version::<_, 4>(&path).unwrap());
+                path.push(newest_version::<_, 4>(&path).ok_or_else(|| not_found("Windows SDK version", &path))?);
                 path.push(host);
+                let signtool_path = Some(path.join("signtool.exe")).filter(|path| path.is_file());
                 bin_paths.push(path);
 
                 Ok(
                     ToolchainPaths {
                         debugger_path,
+                        clang_format_path,
+                        signtool_path,
                         include_paths,
                         lib_paths,
                         bin_paths,
                         vendor,
+                        msvc_version: parsed_msvc_version,
+                        details,
                     }
                 )
             },
             Vendor::Clang => {
                 // TODO: Clang on Windows; Clang on Linux installed somewhere other than /usr/include
                 let include = PathBuf::from("/usr/include");
-                let mut include_paths = Vec::new();
-                include_paths.push(include.clone());
+                let include_paths = vec![include];
 
                 Ok(
                     ToolchainPaths {
                         // TODO: only Windows actually needs this path; remove this field
                         debugger_path: PathBuf::new(),
+                        clang_format_path: None,
+                        signtool_path: None,
                         include_paths,
                         lib_paths: vec![],
                         bin_paths: vec![],
                         vendor,
+                        msvc_version: None,
+                        details: vec![],
                     }
                 )
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{toolchain, TempDir};
+
+    #[test]
+    fn missing_tools_are_found_in_order() {
+        let dir = TempDir::new();
+        dir.write("host/cl.exe", "");
+        dir.write("tools/link.exe", "");
+        let mut toolchain_paths = toolchain();
+        // Nothing is required of a clang-based toolchain yet
+        assert_eq!(toolchain_paths.missing_tool(), None);
+
+        toolchain_paths.vendor = Vendor::Msvc;
+        toolchain_paths.bin_paths = vec![dir.path().join("host"), dir.path().join("tools")];
+        assert_eq!(toolchain_paths.missing_tool(), Some("lib.exe"));
+        dir.write("tools/lib.exe", "");
+        assert_eq!(toolchain_paths.missing_tool(), None);
+
+        toolchain_paths.bin_paths = vec![dir.path().join("missing")];
+        assert_eq!(toolchain_paths.missing_tool(), Some("cl.exe"));
+    }
+
+    #[test]
+    fn versions_have_exactly_the_expected_components() {
+        assert_eq!(parse_version::<3>("14.29.30133"), Some([14, 29, 30133]));
+        assert_eq!(parse_version::<3>("14.29"), None);
+        assert_eq!(parse_version::<2>("14.29.30133"), None);
+        assert_eq!(parse_version::<3>("14.29.x"), None);
+        assert_eq!(parse_version::<3>(""), None);
+    }
+
+    #[test]
+    fn toolsets_too_old_for_the_standard_are_reported() {
+        let mut toolchain_paths = toolchain();
+        // Without a known MSVC version (e.g., with clang), any standard goes
+        assert_eq!(toolchain_paths.unsupported_standard(CxxStandard::Cxx23), None);
+        toolchain_paths.vendor = Vendor::Msvc;
+        let mut too_old = |version: &str, standard| {
+            toolchain_paths.msvc_version = parse_version(version);
+            toolchain_paths.unsupported_standard(standard)
+        };
+        assert_eq!(too_old("14.28.29910", CxxStandard::Cxx20), Some("MSVC 14.29 (Visual Studio 2019 version 16.11)"));
+        assert_eq!(too_old("14.29.30133", CxxStandard::Cxx20), None);
+        assert_eq!(too_old("14.42.34433", CxxStandard::Cxx23), Some("MSVC 14.43 (Visual Studio 2022 version 17.13)"));
+        assert_eq!(too_old("14.43.34808", CxxStandard::Cxx23), None);
+        // Versions are compared numerically, not as strings
+        assert_eq!(too_old("14.100.0", CxxStandard::Cxx23), None);
+        assert_eq!(too_old("14.0.0", CxxStandard::Cxx17), None);
+        assert_eq!(too_old("14.0.0", CxxStandard::Latest), None);
+    }
+}